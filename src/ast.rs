@@ -7,11 +7,13 @@ pub enum FStrPart {
 
 #[derive(Debug, Clone)]
 pub enum Expr {
+    Int(i64),
     Number(f64),
     Str(String),
     #[allow(dead_code)]
     FStr(Vec<FStrPart>),
     Bool(bool),
+    None,
     Variable(String),
     Array(Vec<Expr>),
     Dict(Vec<(Expr, Expr)>),
@@ -19,14 +21,55 @@ pub enum Expr {
     Binary   { left: Box<Expr>, op: BinaryOp,  right: Box<Expr> },
     Logical  { left: Box<Expr>, op: LogicalOp, right: Box<Expr> },
     Unary    { op: UnaryOp, operand: Box<Expr> },
-    Call     { name: String, arguments: Vec<Expr>, line: usize },
-    Lambda   { params: Vec<String>, body: Vec<Stmt>, line: usize },
-    CallExpr { callee: Box<Expr>, arguments: Vec<Expr>, line: usize },
+    // `named` holds the `name: value` arguments, which follow the positional ones.
+    Call     { name: String, arguments: Vec<Expr>, named: Vec<(String, Expr)>, line: usize },
+    Lambda   { params: Vec<Param>, body: Vec<Stmt>, line: usize },
+    CallExpr { callee: Box<Expr>, arguments: Vec<Expr>, named: Vec<(String, Expr)>, line: usize },
+    Member   { object: Box<Expr>, name: String, line: usize },
+    StructLit { name: String, fields: Vec<(String, Expr)>, line: usize },
+    Match    { subject: Box<Expr>, arms: Vec<MatchArm>, line: usize },
+    // `if` as an expression: each branch's value is its last statement's.
+    If       { condition: Box<Expr>, then_branch: Vec<Stmt>, else_branch: Option<Vec<Stmt>>, line: usize },
+    // `condition ? then_value : else_value`
+    Conditional { condition: Box<Expr>, then_value: Box<Expr>, else_value: Box<Expr> },
+}
+
+/// `name`, `name = default`, or the trailing `..name` (`rest`).
+#[derive(Debug, Clone)]
+pub struct Param {
+    pub name:    String,
+    pub default: Option<Expr>,
+    pub rest:    bool,
+}
+
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    Wildcard,
+    Literal(Expr),
+    Bind(String),
+    /// `name: type` — `type_of` equals `type`; `_: type` binds nothing.
+    Typed { name: String, type_name: String },
+    /// `[a, b, ..rest]` — `rest` is `None` when the length must match exactly.
+    Array { elements: Vec<Pattern>, rest: Option<String> },
+    Dict(Vec<(String, Pattern)>),
+}
+
+#[derive(Debug, Clone)]
+pub enum ArmBody {
+    Expr(Expr),
+    Block(Vec<Stmt>),
+}
+
+#[derive(Debug, Clone)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub guard:   Option<Expr>,
+    pub body:    ArmBody,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum BinaryOp {
-    Add, Sub, Mul, Div, Mod,
+    Add, Sub, Mul, Div, IntDiv, Mod,
     Less, LessEqual, Greater, GreaterEqual,
     EqualEqual, BangEqual,
 }
@@ -40,14 +83,25 @@ pub enum UnaryOp { Not, Neg }
 #[derive(Debug, Clone)]
 pub enum Stmt {
     Let         { name: String, value: Expr, line: usize },
+    // `let [a, ..rest] = value` / `let {name} = value`.
+    Destructure { pattern: Pattern, value: Expr, line: usize },
     Print       { value: Expr,               line: usize },
     If          { condition: Expr, then_branch: Vec<Stmt>, else_branch: Option<Vec<Stmt>>, line: usize },
     While       { condition: Expr, body: Vec<Stmt>,        line: usize },
-    For         { variable: String, iterable: Expr, body: Vec<Stmt>, line: usize },
-    Function    { name: String, params: Vec<String>, body: Vec<Stmt>, line: usize },
+    For         { variable: Pattern, iterable: Expr, body: Vec<Stmt>, line: usize },
+    Function    { name: String, params: Vec<Param>, body: Vec<Stmt>, line: usize },
     Return      { value: Option<Expr>, line: usize },
+    // Makes the enclosing function a generator.
+    Yield       { value: Option<Expr>, line: usize },
     Break       { line: usize },
     Continue    { line: usize },
-    IndexAssign { object: String, index: Expr, value: Expr, line: usize },
+    Try         { body: Vec<Stmt>, error_name: String, handler: Vec<Stmt>, line: usize },
+    Throw       { value: Expr, line: usize },
+    Import      { path: String, alias: Option<String>, line: usize },
+    Global      { names: Vec<String>, line: usize },
+    Struct      { name: String, fields: Vec<String>, line: usize },
+    // `target = value`, or `target op= value` when `op` is set.  The
+    // target is a variable followed by any chain of `[index]` and `.field`.
+    Assign      { target: Expr, op: Option<BinaryOp>, value: Expr, line: usize },
     Expression  { expr: Expr, line: usize },
 }
\ No newline at end of file
@@ -1,6 +1,6 @@
 use crate::error::{ErrorKind, WhispemError, WhispemResult};
 use crate::opcode::OpCode;
-use crate::value::Value;
+use crate::value::{StructDef, Value};
 
 #[derive(Debug, Clone)]
 pub struct Chunk {
@@ -8,12 +8,18 @@ pub struct Chunk {
     pub constants:     Vec<Value>,
     pub lines:         Vec<usize>,
     pub name:          String,
+    // Positional parameters; the rest parameter is not counted.
     pub param_count:   usize,
+    // Leading parameters without a default.
+    pub required:      usize,
+    pub has_rest:      bool,
+    // Every parameter name in order, the rest parameter last.
+    pub param_names:   Vec<String>,
     pub upvalue_count: usize,
 }
 
 pub const MAGIC:          &[u8; 4] = b"WHBC";
-pub const FORMAT_VERSION: u8       = 4;
+pub const FORMAT_VERSION: u8       = 5;
 
 impl Chunk {
     pub fn new(name: impl Into<String>) -> Self {
@@ -23,6 +29,9 @@ impl Chunk {
             lines:         Vec::new(),
             name:          name.into(),
             param_count:   0,
+            required:      0,
+            has_rest:      false,
+            param_names:   Vec::new(),
             upvalue_count: 0,
         }
     }
@@ -65,6 +74,9 @@ impl Chunk {
             Value::Str(s) => self.constants.iter().position(|c| {
                 matches!(c, Value::Str(e) if e == s)
             }),
+            Value::Int(i) => self.constants.iter().position(|c| {
+                matches!(c, Value::Int(e) if e == i)
+            }),
             Value::Number(n) => self.constants.iter().position(|c| {
                 matches!(c, Value::Number(e) if e.to_bits() == n.to_bits())
             }),
@@ -115,6 +127,9 @@ fn write_chunk(chunk: &Chunk, out: &mut Vec<u8>) -> WhispemResult<()> {
     out.extend_from_slice(name_bytes);
     out.push(chunk.param_count   as u8);
     out.push(chunk.upvalue_count as u8);
+    out.push(chunk.required      as u8);
+    out.push(chunk.has_rest      as u8);
+    for param in &chunk.param_names { write_str(param, out)?; }
 
     if chunk.constants.len() > 256 {
         return Err(WhispemError::runtime(ErrorKind::SerializationError(
@@ -141,27 +156,34 @@ fn write_const(v: &Value, out: &mut Vec<u8>) -> WhispemResult<()> {
             out.push(0);
             out.extend_from_slice(&n.to_bits().to_be_bytes());
         }
+        Value::Int(i) => {
+            out.push(5);
+            out.extend_from_slice(&i.to_be_bytes());
+        }
         Value::Bool(b) => {
             out.push(1);
             out.push(if *b { 1 } else { 0 });
         }
         Value::Str(s) => {
             out.push(2);
-            let bytes = s.as_bytes();
-            if bytes.len() > 0xFFFF {
+            write_str(s, out)?;
+        }
+        Value::None => { out.push(3); }
+        // A struct declaration, as the prototype `MAKE_STRUCT` copies.
+        Value::Struct(st) => {
+            out.push(4);
+            write_str(&st.def.name, out)?;
+            if st.def.fields.len() > 0xFF {
                 return Err(WhispemError::runtime(ErrorKind::SerializationError(
-                    "string constant too long".to_string(),
+                    format!("struct '{}' has more than 255 fields", st.def.name),
                 )));
             }
-            let slen = bytes.len() as u16;
-            out.push((slen >> 8) as u8);
-            out.push((slen & 0xFF) as u8);
-            out.extend_from_slice(bytes);
+            out.push(st.def.fields.len() as u8);
+            for f in &st.def.fields { write_str(f, out)?; }
         }
-        Value::None => { out.push(3); }
-        Value::Array(_) | Value::Dict(_) | Value::Closure { .. } => {
+        Value::Array(_) | Value::Range(_) | Value::Dict(_) | Value::Closure { .. } | Value::Native(_) | Value::Generator(_) => {
             return Err(WhispemError::runtime(ErrorKind::SerializationError(
-                "arrays, dicts, and closures cannot appear in the constants pool".to_string(),
+                "arrays, dicts, and functions cannot appear in the constants pool".to_string(),
             )));
         }
     }
@@ -214,6 +236,17 @@ fn read_chunk(data: &[u8], mut cursor: usize) -> WhispemResult<(Chunk, usize)> {
     let param_count   = data[cursor] as usize; cursor += 1;
     let upvalue_count = data[cursor] as usize; cursor += 1;
 
+    need(data, cursor, 2)?;
+    let required = data[cursor] as usize; cursor += 1;
+    let has_rest = data[cursor] != 0;     cursor += 1;
+    if required > param_count { return Err(bad_bc("more required parameters than parameters")); }
+    let mut param_names = Vec::with_capacity(param_count + has_rest as usize);
+    for _ in 0..param_count + has_rest as usize {
+        let (param, next) = read_str(data, cursor)?;
+        param_names.push(param);
+        cursor = next;
+    }
+
     need(data, cursor, 1)?;
     let const_count = data[cursor] as usize;
     cursor += 1;
@@ -240,7 +273,31 @@ fn read_chunk(data: &[u8], mut cursor: usize) -> WhispemResult<(Chunk, usize)> {
     }
     cursor += lines_len * 4;
 
-    Ok((Chunk { code, constants, lines, name, param_count, upvalue_count }, cursor))
+    Ok((Chunk { code, constants, lines, name, param_count, required, has_rest, param_names, upvalue_count }, cursor))
+}
+
+fn write_str(s: &str, out: &mut Vec<u8>) -> WhispemResult<()> {
+    let bytes = s.as_bytes();
+    if bytes.len() > 0xFFFF {
+        return Err(WhispemError::runtime(ErrorKind::SerializationError(
+            "string constant too long".to_string(),
+        )));
+    }
+    let slen = bytes.len() as u16;
+    out.push((slen >> 8) as u8);
+    out.push((slen & 0xFF) as u8);
+    out.extend_from_slice(bytes);
+    Ok(())
+}
+
+fn read_str(data: &[u8], mut cursor: usize) -> WhispemResult<(String, usize)> {
+    let slen = read_u16(data, cursor)? as usize;
+    cursor += 2;
+    need(data, cursor, slen)?;
+    let s = std::str::from_utf8(&data[cursor..cursor + slen])
+        .map_err(|_| bad_bc("string constant is not valid UTF-8"))?
+        .to_string();
+    Ok((s, cursor + slen))
 }
 
 fn read_const(data: &[u8], mut cursor: usize) -> WhispemResult<(Value, usize)> {
@@ -261,16 +318,29 @@ fn read_const(data: &[u8], mut cursor: usize) -> WhispemResult<(Value, usize)> {
             Ok((Value::Bool(b), cursor))
         }
         2 => {
-            let slen = read_u16(data, cursor)? as usize;
-            cursor += 2;
-            need(data, cursor, slen)?;
-            let s = std::str::from_utf8(&data[cursor..cursor + slen])
-                .map_err(|_| bad_bc("string constant is not valid UTF-8"))?
-                .to_string();
-            cursor += slen;
+            let (s, cursor) = read_str(data, cursor)?;
             Ok((Value::Str(s), cursor))
         }
         3 => Ok((Value::None, cursor)),
+        4 => {
+            let (name, mut cursor) = read_str(data, cursor)?;
+            need(data, cursor, 1)?;
+            let count = data[cursor] as usize;
+            cursor += 1;
+            let mut fields = Vec::with_capacity(count);
+            for _ in 0..count {
+                let (f, next) = read_str(data, cursor)?;
+                fields.push(f);
+                cursor = next;
+            }
+            Ok((Value::struct_prototype(StructDef { name, fields }), cursor))
+        }
+        5 => {
+            need(data, cursor, 8)?;
+            let i = i64::from_be_bytes(data[cursor..cursor + 8].try_into().unwrap());
+            cursor += 8;
+            Ok((Value::Int(i), cursor))
+        }
         _ => Err(bad_bc(format!("unknown constant tag {}", tag))),
     }
 }
@@ -315,8 +385,21 @@ impl Chunk {
                 }
                 pos
             }
+            OpCode::CallNamed => {
+                let name_idx = self.code[offset + 1] as usize;
+                println!(
+                    "{:<20} {:3}    {} ({} args, {} named)",
+                    op.name(), name_idx, self.constant_annotation(name_idx),
+                    self.code[offset + 2], self.code[offset + 3]
+                );
+                offset + 4
+            }
             _ => match op.operand_size() {
                 0 => { println!("{}", op.name()); offset + 1 }
+                1 if matches!(op, OpCode::UnpackDict | OpCode::HasArg) => {
+                    println!("{:<20} {:3}", op.name(), self.code[offset + 1]);
+                    offset + 2
+                }
                 1 => {
                     let idx = self.code[offset + 1] as usize;
                     println!("{:<20} {:3}    {}", op.name(), idx, self.constant_annotation(idx));
@@ -328,11 +411,16 @@ impl Chunk {
                         | OpCode::JumpIfFalse
                         | OpCode::JumpIfTrue
                         | OpCode::PeekJumpIfFalse
-                        | OpCode::PeekJumpIfTrue => {
+                        | OpCode::PeekJumpIfTrue
+                        | OpCode::TryBegin => {
                             let hi = self.code[offset + 1] as u16;
                             let lo = self.code[offset + 2] as u16;
                             println!("{:<20}        -> {:04}", op.name(), (hi << 8) | lo);
                         }
+                        OpCode::UnpackArray => {
+                            let rest = if self.code[offset + 2] != 0 { " + rest" } else { "" };
+                            println!("{:<20} {:3}{}", op.name(), self.code[offset + 1], rest);
+                        }
                         OpCode::Call => {
                             let name_idx = self.code[offset + 1] as usize;
                             let argc     = self.code[offset + 2];
@@ -357,15 +445,19 @@ impl Chunk {
         if idx >= self.constants.len() { return "(out of range)".to_string(); }
         match &self.constants[idx] {
             Value::Str(s)    => format!("'{}'", s),
+            Value::Int(i)    => format!("'{}'", i),
             Value::Number(n) => {
                 if n.fract() == 0.0 { format!("'{}'", *n as i64) }
                 else { format!("'{}'", n) }
             }
             Value::Bool(b)      => format!("'{}'", b),
             Value::None         => "'none'".to_string(),
-            Value::Array(_)     => "[array]".to_string(),
+            Value::Array(_) | Value::Range(_) => "[array]".to_string(),
             Value::Dict(_)      => "{dict}".to_string(),
+            Value::Struct(st)   => format!("<struct {}>", st.def.name),
             Value::Closure {..} => "<closure>".to_string(),
+            Value::Native(_)    => "<native>".to_string(),
+            Value::Generator(_) => "<generator>".to_string(),
         }
     }
 }
@@ -1,9 +1,13 @@
-use crate::ast::{BinaryOp, Expr, LogicalOp, Stmt, UnaryOp};
+use crate::ast::{ArmBody, BinaryOp, Expr, FStrPart, LogicalOp, Param, Pattern, Stmt, UnaryOp};
 use crate::chunk::Chunk;
-use crate::error::{ErrorKind, Span, WhispemError, WhispemResult};
+use crate::error::{ErrorKind, Span, Warning, WhispemError, WhispemResult};
 use crate::opcode::OpCode;
-use crate::value::Value;
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use crate::permissions::Permissions;
+use crate::value::{StructDef, Value};
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug, Clone)]
 pub struct UpvalueDesc {
@@ -14,13 +18,22 @@ pub struct UpvalueDesc {
 struct FnScope {
     locals:   Vec<String>,
     upvalues: Vec<(String, UpvalueDesc)>,
+    blocks:   Blocks,
+    // Names declared with `global`.
+    globals:  Vec<String>,
+    // Globals this function has read so far, for the shadowing warning.
+    read_globals: Vec<String>,
 }
 
 impl FnScope {
     fn new(params: &[String]) -> Self {
-        Self { locals: params.to_vec(), upvalues: Vec::new() }
+        Self {
+            locals: params.to_vec(), upvalues: Vec::new(), blocks: Blocks::default(),
+            globals: Vec::new(), read_globals: Vec::new(),
+        }
     }
     fn has_local(&self, name: &str)   -> bool          { self.locals.iter().any(|n| n == name) }
+    fn has_global(&self, name: &str)  -> bool          { self.globals.iter().any(|n| n == name) }
     fn add_local(&mut self, name: &str) {
         if !self.has_local(name) { self.locals.push(name.to_string()); }
     }
@@ -38,18 +51,90 @@ impl FnScope {
     }
 }
 
+// The `{}` blocks open in one function body, or at the top level.  A name
+// declared in a block is stored under a slot of its own, `name#N`, so it
+// shadows outer bindings and cannot be reached after the block.
+#[derive(Default)]
+struct Blocks {
+    // Innermost last: source name → slot.
+    open:     Vec<Vec<(String, String)>>,
+    // Every slot declared so far, for a `catch` to drop.
+    declared: Vec<String>,
+}
+
+impl Blocks {
+    fn slot(&self, name: &str) -> Option<String> {
+        self.open.iter().rev()
+            .find_map(|block| block.iter().find(|(n, _)| n == name))
+            .map(|(_, slot)| slot.clone())
+    }
+}
+
+/// Import and struct state that outlives a single compilation, so a REPL
+/// session keeps what its earlier inputs imported and declared.
+#[derive(Debug, Clone, Default)]
+pub struct Modules {
+    // Source name → global name, for names visible unqualified here.
+    renames: HashMap<String, String>,
+    // `import ... as alias` → that module's exports.
+    aliases: HashMap<String, HashMap<String, String>>,
+    // Canonical path → exports, for every module already compiled.
+    loaded:  HashMap<PathBuf, HashMap<String, String>>,
+    // Global name → struct declaration, as a `MAKE_STRUCT` prototype.
+    structs: HashMap<String, Value>,
+}
+
+/// The `<main>` chunk, the function chunks, the import state and the
+/// warnings, as returned by `compile_with_modules`.
+pub type Compiled = (Chunk, HashMap<String, Chunk>, Modules, Vec<Warning>);
+
 pub struct Compiler {
     current:      Chunk,
     functions:    HashMap<String, Chunk>,
     loop_stack:   Vec<LoopContext>,
     global_names: Vec<String>,
     scope_stack:  Vec<FnScope>,
+    top_blocks:   Blocks,
+    block_count:  usize,
     lambda_count: usize,
+    match_count:  usize,
+    key_count:    usize,
+    // `try` blocks open around the statement being compiled, in this function.
+    try_depth:    usize,
+    modules:      Modules,
+    base_dir:     PathBuf,
+    // Which module files `import` may read.
+    permissions:  Permissions,
+    // Modules being compiled, outermost first, for cycle detection.
+    loading:      Vec<PathBuf>,
+    warnings:     Vec<Warning>,
+}
+
+impl Default for Compiler {
+    fn default() -> Self { Self::new() }
 }
 
 struct LoopContext {
     break_jumps:    Vec<usize>,
     continue_jumps: Vec<usize>,
+    try_depth:      usize,
+    // Blocks open outside the loop body, which `break` / `continue` keep.
+    block_depth:    usize,
+}
+
+// One step of an assignment target after its root variable.
+enum Place {
+    Index(Expr),
+    Field(String),
+}
+
+// How an assignment step's key is emitted: a literal or single-use index
+// expression inline, any other index from its hidden variable, a field by
+// its name constant.
+enum Key {
+    Inline(Expr),
+    Hidden(u8),
+    Field(u8),
 }
 
 impl Compiler {
@@ -60,19 +145,107 @@ impl Compiler {
             loop_stack:   Vec::new(),
             global_names: Vec::new(),
             scope_stack:  Vec::new(),
+            top_blocks:   Blocks::default(),
+            block_count:  0,
             lambda_count: 0,
+            match_count:  0,
+            key_count:    0,
+            try_depth:    0,
+            modules:      Modules::default(),
+            base_dir:     PathBuf::from("."),
+            permissions:  Permissions::all(),
+            loading:      Vec::new(),
+            warnings:     Vec::new(),
         }
     }
 
+    /// Resolve `import` paths relative to `dir` (default: the current directory).
+    pub fn with_base_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.base_dir = dir.into();
+        self
+    }
+
+    /// Only import modules that `permissions.fs_read` allows (default:
+    /// any file).
+    pub fn with_permissions(mut self, permissions: Permissions) -> Self {
+        self.permissions = permissions;
+        self
+    }
+
+    /// Continue from the import state of an earlier compilation.
+    pub fn with_modules(mut self, modules: Modules) -> Self {
+        self.modules = modules;
+        self
+    }
+
     pub fn compile(
-        mut self,
+        self,
         program: Vec<Stmt>,
     ) -> WhispemResult<(Chunk, HashMap<String, Chunk>)> {
+        let (main, functions, ..) = self.compile_with_modules(program)?;
+        Ok((main, functions))
+    }
+
+    /// Like `compile`, also handing back the import state for `with_modules`
+    /// and the warnings raised while compiling.
+    pub fn compile_with_modules(
+        mut self,
+        program: Vec<Stmt>,
+    ) -> WhispemResult<Compiled> {
+        self.compile_module(program, None)?;
+        self.current.emit_op(OpCode::Halt, 0);
+        Ok((self.current, self.functions, self.modules, self.warnings))
+    }
+
+    // Compile one module's top level into the current chunk and return its
+    // exports.  The main program (`prefix` None) keeps its names as they
+    // are; an imported module's become `prefix::name` so that every module
+    // has its own globals.  The parser keeps imports ahead of everything
+    // else, so compiling them first preserves the source order.
+    fn compile_module(
+        &mut self, program: Vec<Stmt>, prefix: Option<&str>,
+    ) -> WhispemResult<HashMap<String, String>> {
+        let (imports, program): (Vec<Stmt>, Vec<Stmt>) =
+            program.into_iter().partition(|s| matches!(s, Stmt::Import { .. }));
+        for stmt in imports {
+            if let Stmt::Import { path, alias, line } = stmt {
+                let exports = self.import(&path, line)?;
+                match alias {
+                    Some(alias) => { self.modules.aliases.insert(alias, exports); }
+                    None => for (name, global) in exports {
+                        self.modules.renames.entry(name).or_insert(global);
+                    },
+                }
+            }
+        }
+
+        let mut own = Vec::new();
+        collect_top_level_names(&program, &mut own);
+        let mut exports = HashMap::new();
+        for name in own {
+            let global = match prefix {
+                Some(p) => format!("{}::{}", p, name),
+                None    => name.clone(),
+            };
+            if prefix.is_some() { self.modules.renames.insert(name.clone(), global.clone()); }
+            else                { self.modules.renames.remove(&name); }
+            exports.insert(name, global);
+        }
+
         for stmt in &program {
-            if let Stmt::Let { name, .. } = stmt {
-                if !self.global_names.contains(name) {
+            match stmt {
+                Stmt::Let { name, .. } if !self.global_names.contains(name) => {
                     self.global_names.push(name.clone());
                 }
+                Stmt::Destructure { pattern, .. } => {
+                    let mut names = Vec::new();
+                    pattern_names(pattern, &mut names);
+                    for name in names {
+                        if !self.global_names.contains(&name) { self.global_names.push(name); }
+                    }
+                }
+                Stmt::Struct { name, fields, .. } => self.declare_struct(name, fields),
+                _ => {}
             }
         }
         for stmt in &program {
@@ -85,13 +258,131 @@ impl Compiler {
                 self.compile_stmt(stmt)?;
             }
         }
-        self.current.emit_op(OpCode::Halt, 0);
-        Ok((self.current, self.functions))
+        Ok(exports)
+    }
+
+    // Compile the module at `path` (once) and return its exports.  Its
+    // top-level code is inlined where it is first imported.
+    fn import(&mut self, path: &str, line: usize) -> WhispemResult<HashMap<String, String>> {
+        let span = Span::new(line, 0);
+        let full = self.base_dir.join(path);
+        // Checked before touching the file, so a denied import reveals
+        // nothing about it, not even whether it exists.
+        if !self.permissions.can_read(&full) {
+            return Err(WhispemError::new(
+                ErrorKind::PermissionDenied { builtin: "import".to_string(), resource: full.display().to_string() },
+                span,
+            ));
+        }
+        let canon = full.canonicalize().map_err(|e| WhispemError::new(
+            ErrorKind::FileRead { path: full.display().to_string(), reason: e.to_string() }, span,
+        ))?;
+        if let Some(start) = self.loading.iter().position(|p| *p == canon) {
+            let chain: Vec<String> = self.loading[start..].iter().chain(std::iter::once(&canon))
+                .map(|p| module_label(p))
+                .collect();
+            return Err(WhispemError::new(ErrorKind::ImportCycle(chain.join(" -> ")), span));
+        }
+        if let Some(exports) = self.modules.loaded.get(&canon) {
+            return Ok(exports.clone());
+        }
+
+        let source = std::fs::read_to_string(&canon).map_err(|e| WhispemError::new(
+            ErrorKind::FileRead { path: full.display().to_string(), reason: e.to_string() }, span,
+        ))?;
+        let prefix = self.module_prefix(&canon);
+
+        let saved_renames = std::mem::take(&mut self.modules.renames);
+        let saved_aliases = std::mem::take(&mut self.modules.aliases);
+        let saved_globals = std::mem::take(&mut self.global_names);
+        let dir           = canon.parent().map(Path::to_path_buf).unwrap_or_default();
+        let saved_dir     = std::mem::replace(&mut self.base_dir, dir);
+        self.loading.push(canon.clone());
+
+        let result = Lexer::new(&source).tokenize()
+            .and_then(|tokens| Parser::new(tokens).parse_program())
+            .and_then(|program| self.compile_module(program, Some(&prefix)));
+
+        self.loading.pop();
+        self.base_dir        = saved_dir;
+        self.global_names    = saved_globals;
+        self.modules.aliases = saved_aliases;
+        self.modules.renames = saved_renames;
+
+        let exports = result.map_err(|e| match e.kind {
+            ErrorKind::ImportCycle(_) | ErrorKind::ImportError(_) | ErrorKind::PermissionDenied { .. } => e,
+            _ => WhispemError::new(ErrorKind::ImportError(format!("in '{}' at line {}: {}", path, e.span.line, e.kind.message())), span),
+        })?;
+        self.modules.loaded.insert(canon, exports.clone());
+        Ok(exports)
+    }
+
+    // The file stem, made unique among the modules loaded so far.
+    fn module_prefix(&self, path: &Path) -> String {
+        let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "module".to_string());
+        let taken = |p: &str| self.modules.loaded.values()
+            .any(|ex| ex.values().any(|g| g.split("::").next() == Some(p)));
+        let mut prefix = stem.clone();
+        let mut n = 2;
+        while taken(&prefix) {
+            prefix = format!("{}{}", stem, n);
+            n += 1;
+        }
+        prefix
+    }
+
+    fn declare_struct(&mut self, name: &str, fields: &[String]) {
+        let def = StructDef { name: name.to_string(), fields: fields.to_vec() };
+        self.modules.structs.insert(self.global_name(name), Value::struct_prototype(def));
+    }
+
+    // The struct declaration `name` refers to here, unless a variable hides it.
+    fn struct_named(&self, name: &str) -> Option<Value> {
+        if self.scope_stack.last().is_some_and(|s| s.has_local(name)) { return None; }
+        self.modules.structs.get(&self.global_name(name)).cloned()
+    }
+
+    fn struct_fields(proto: &Value) -> &[String] {
+        match proto {
+            Value::Struct(st) => &st.def.fields,
+            _                 => &[],
+        }
+    }
+
+    // The global a top-level name refers to in the module being compiled.
+    fn global_name(&self, name: &str) -> String {
+        self.modules.renames.get(name).cloned().unwrap_or_else(|| name.to_string())
+    }
+
+    // `name` as a block variable or variable of the current function, or
+    // else as a global.
+    fn var_name(&self, name: &str) -> String {
+        if let Some(slot) = self.blocks().slot(name) { return slot; }
+        match self.scope_stack.last() {
+            Some(scope) if scope.has_global(name) => self.global_name(name),
+            Some(scope) if scope.has_local(name) || scope.upvalue_slot(name).is_some() => name.to_string(),
+            _ => self.global_name(name),
+        }
+    }
+
+    fn is_module_alias(&self, object: &Expr) -> bool {
+        matches!(object, Expr::Variable(a) if self.modules.aliases.contains_key(a))
+    }
+
+    // The global bound to `alias.member`, for an `import ... as alias`.
+    fn member_global(&self, object: &Expr, member: &str, line: usize) -> WhispemResult<String> {
+        let Expr::Variable(alias) = object else { unreachable!("checked by is_module_alias") };
+        self.modules.aliases[alias].get(member).cloned().ok_or_else(|| WhispemError::new(
+            ErrorKind::ImportError(format!("module '{}' has no member '{}'", alias, member)),
+            Span::new(line, 0),
+        ))
     }
 
     fn compile_named_fn(
-        &mut self, name: &str, params: &[String], body: &[Stmt], line: usize,
+        &mut self, name: &str, params: &[Param], body: &[Stmt], line: usize,
     ) -> WhispemResult<()> {
+        let name = &self.global_name(name);
         let (chunk, uv_descs) = self.compile_fn_body(name, params, body, line)?;
         if !uv_descs.is_empty() {
             self.emit_make_closure(&chunk.name, &uv_descs, line)?;
@@ -102,19 +393,43 @@ impl Compiler {
     }
 
     fn compile_fn_body(
-        &mut self, name: &str, params: &[String], body: &[Stmt], line: usize,
+        &mut self, name: &str, params: &[Param], body: &[Stmt], line: usize,
     ) -> WhispemResult<(Chunk, Vec<UpvalueDesc>)> {
-        let parent = std::mem::replace(&mut self.current, Chunk::new(name));
-        self.scope_stack.push(FnScope::new(params));
-        self.current.param_count = params.len();
+        let parent       = std::mem::replace(&mut self.current, Chunk::new(name));
+        let parent_loops = std::mem::take(&mut self.loop_stack);
+        let parent_trys  = std::mem::replace(&mut self.try_depth, 0);
+        let names: Vec<String> = params.iter().map(|p| p.name.clone()).collect();
+        self.scope_stack.push(FnScope::new(&names));
+        self.current.param_count = params.iter().filter(|p| !p.rest).count();
+        self.current.required    = params.iter().filter(|p| !p.rest && p.default.is_none()).count();
+        self.current.has_rest    = params.iter().any(|p| p.rest);
+        self.current.param_names = names;
 
+        // The caller binds every parameter, pushing `none` for a missing
+        // optional one; its default then runs here, after the parameters
+        // before it are stored.
         for param in params.iter().rev() {
-            let idx = self.name_const(param, line)?;
+            let idx = self.name_const(&param.name, line)?;
             self.current.emit_op_u8(OpCode::Store, idx, line);
         }
+        for (i, param) in params.iter().enumerate() {
+            let Some(default) = &param.default else { continue };
+            self.current.emit_op_u8(OpCode::HasArg, i as u8, line);
+            let skip = self.current.emit_jump(OpCode::JumpIfTrue, line);
+            self.compile_expr(default.clone(), line)?;
+            let idx = self.name_const(&param.name, line)?;
+            self.current.emit_op_u8(OpCode::Store, idx, line);
+            let here = self.current.code.len();
+            self.current.patch_jump(skip, here);
+        }
+        // A generator's call ends here, handing back the suspended frame;
+        // `next` and `for` run the rest.
+        if block_yields(body) { self.current.emit_op(OpCode::MakeGenerator, line); }
         for stmt in body { self.compile_stmt(stmt.clone())?; }
         self.current.emit_op(OpCode::ReturnNone, line);
 
+        self.loop_stack = parent_loops;
+        self.try_depth  = parent_trys;
         let fn_chunk = std::mem::replace(&mut self.current, parent);
         let scope    = self.scope_stack.pop().unwrap();
         let uv_descs = scope.upvalue_descs();
@@ -122,6 +437,30 @@ impl Compiler {
         Ok((fn_chunk, uv_descs))
     }
 
+    // `CALL name argc`, or with named arguments each pushed as a name and
+    // value after the positional ones, `CALL_NAMED name argc named`.
+    fn emit_call(
+        &mut self, name_c: u8, argc: u8, named: Vec<(String, Expr)>, line: usize,
+    ) -> WhispemResult<()> {
+        if named.is_empty() {
+            self.current.emit_byte(OpCode::Call as u8, line);
+            self.current.emit_byte(name_c, line);
+            self.current.emit_byte(argc,   line);
+            return Ok(());
+        }
+        let count = named.len() as u8;
+        for (param, value) in named {
+            let idx = self.name_const(&param, line)?;
+            self.current.emit_op_u8(OpCode::PushConst, idx, line);
+            self.compile_expr(value, line)?;
+        }
+        self.current.emit_byte(OpCode::CallNamed as u8, line);
+        self.current.emit_byte(name_c, line);
+        self.current.emit_byte(argc,   line);
+        self.current.emit_byte(count,  line);
+        Ok(())
+    }
+
     fn emit_make_closure(
         &mut self, chunk_name: &str, uv_descs: &[UpvalueDesc], line: usize,
     ) -> WhispemResult<()> {
@@ -143,24 +482,26 @@ impl Compiler {
     fn compile_stmt(&mut self, stmt: Stmt) -> WhispemResult<()> {
         match stmt {
             Stmt::Let { name, value, line } => {
+                // `let i = i + 1` in a loop body used to update the outer `i`;
+                // now it declares a new one and the loop may never end.
+                let rebinds = self.let_shadows_outer(&name) && expr_reads(&value, &name);
+                let warned  = self.warnings.len();
                 self.compile_expr(value, line)?;
-                let depth = self.scope_stack.len();
-                if depth > 0 {
-                    if let Some(slot) = self.scope_stack[depth - 1].upvalue_slot(&name) {
-                        self.current.emit_op_u8(OpCode::StoreUpvalue, slot, line);
-                        return Ok(());
-                    }
-                }
-                let idx = self.name_const(&name, line)?;
-                self.current.emit_op_u8(OpCode::Store, idx, line);
-                if self.scope_stack.is_empty() {
-                    if !self.global_names.contains(&name) {
-                        self.global_names.push(name.clone());
-                    }
-                } else {
-                    self.scope_stack.last_mut().unwrap().add_local(&name);
+                self.emit_let_store(&name, line)?;
+                // The global-shadowing warning, if raised, already covers it.
+                if rebinds && self.warnings.len() == warned {
+                    self.warnings.push(Warning {
+                        message: format!(
+                            "'let {0}' in a block declares a new '{0}' that shadows the outer '{0}' it reads; \
+                             use '{0} = ...' to update the outer variable", name),
+                        span: Span::new(line, 0),
+                    });
                 }
             }
+            Stmt::Destructure { pattern, value, line } => {
+                self.compile_expr(value, line)?;
+                self.compile_destructure(&pattern, line)?;
+            }
             Stmt::Print { value, line } => {
                 self.compile_expr(value, line)?;
                 self.current.emit_op(OpCode::Print, line);
@@ -168,12 +509,12 @@ impl Compiler {
             Stmt::If { condition, then_branch, else_branch, line } => {
                 self.compile_expr(condition, line)?;
                 let jelse = self.current.emit_jump(OpCode::JumpIfFalse, line);
-                for s in then_branch { self.compile_stmt(s)?; }
+                self.compile_block(then_branch, line)?;
                 if let Some(else_stmts) = else_branch {
                     let jend       = self.current.emit_jump(OpCode::Jump, line);
                     let else_start = self.current.current_offset();
                     self.current.patch_jump(jelse, else_start);
-                    for s in else_stmts { self.compile_stmt(s)?; }
+                    self.compile_block(else_stmts, line)?;
                     let end = self.current.current_offset();
                     self.current.patch_jump(jend, end);
                 } else {
@@ -183,10 +524,10 @@ impl Compiler {
             }
             Stmt::While { condition, body, line } => {
                 let loop_start = self.current.current_offset();
-                self.loop_stack.push(LoopContext { break_jumps: vec![], continue_jumps: vec![] });
+                self.push_loop();
                 self.compile_expr(condition, line)?;
                 let exit = self.current.emit_jump(OpCode::JumpIfFalse, line);
-                for s in body { self.compile_stmt(s)?; }
+                self.compile_block(body, line)?;
                 self.current.emit_op_u16(OpCode::Jump, loop_start as u16, line);
                 let after = self.current.current_offset();
                 self.current.patch_jump(exit, after);
@@ -195,50 +536,34 @@ impl Compiler {
                 for p in ctx.continue_jumps { self.current.patch_jump(p, loop_start); }
             }
             Stmt::For { variable, iterable, body, line } => {
-                let depth     = self.loop_stack.len();
-                let iter_name = format!("__iter_{}", depth);
-                let idx_name  = format!("__idx_{}", depth);
-
+                // `ITER` turns the array, string, dict or generator into a
+                // generator; `ITER_NEXT` pushes its next item and `true`, or
+                // just `false` once it is exhausted.
+                let iter_name = format!("__iter_{}", self.loop_stack.len());
                 self.compile_expr(iterable, line)?;
+                self.current.emit_op(OpCode::Iter, line);
                 let iter_c = self.name_const(&iter_name, line)?;
                 self.current.emit_op_u8(OpCode::Store, iter_c, line);
-                let zero   = self.current.add_constant(Value::Number(0.0));
-                self.current.emit_op_u8(OpCode::PushConst, zero, line);
-                let idx_c  = self.name_const(&idx_name, line)?;
-                self.current.emit_op_u8(OpCode::Store, idx_c, line);
 
                 let loop_start = self.current.current_offset();
-                self.loop_stack.push(LoopContext { break_jumps: vec![], continue_jumps: vec![] });
-
-                self.current.emit_op_u8(OpCode::Load, idx_c, line);
+                self.push_loop();
                 self.current.emit_op_u8(OpCode::Load, iter_c, line);
-                let len_c = self.name_const("length", line)?;
-                self.current.emit_byte(OpCode::Call as u8, line);
-                self.current.emit_byte(len_c, line);
-                self.current.emit_byte(1, line);
-                self.current.emit_op(OpCode::Lt, line);
+                self.current.emit_op(OpCode::IterNext, line);
                 let exit = self.current.emit_jump(OpCode::JumpIfFalse, line);
 
-                self.current.emit_op_u8(OpCode::Load, iter_c, line);
-                self.current.emit_op_u8(OpCode::Load, idx_c, line);
-                self.current.emit_op(OpCode::GetIndex, line);
-                let var_c = self.name_const(&variable, line)?;
-                self.current.emit_op_u8(OpCode::Store, var_c, line);
-
+                // The loop variable belongs to the body's block, so each
+                // iteration binds it afresh.
+                self.begin_block();
+                self.compile_destructure(&variable, line)?;
                 for s in body { self.compile_stmt(s)?; }
+                self.end_block(line)?;
 
-                let continue_target = self.current.current_offset();
-                self.current.emit_op_u8(OpCode::Load, idx_c, line);
-                let one = self.current.add_constant(Value::Number(1.0));
-                self.current.emit_op_u8(OpCode::PushConst, one, line);
-                self.current.emit_op(OpCode::Add, line);
-                self.current.emit_op_u8(OpCode::Store, idx_c, line);
                 self.current.emit_op_u16(OpCode::Jump, loop_start as u16, line);
                 let after = self.current.current_offset();
                 self.current.patch_jump(exit, after);
                 let ctx = self.loop_stack.pop().unwrap();
                 for p in ctx.break_jumps    { self.current.patch_jump(p, after); }
-                for p in ctx.continue_jumps { self.current.patch_jump(p, continue_target); }
+                for p in ctx.continue_jumps { self.current.patch_jump(p, loop_start); }
             }
             Stmt::Function { .. } => {}
             Stmt::Return { value, line } => {
@@ -253,6 +578,8 @@ impl Compiler {
                 if self.loop_stack.is_empty() {
                     return Err(WhispemError::new(ErrorKind::BreakOutsideLoop, Span::new(line, 0)));
                 }
+                self.emit_try_exits(line);
+                self.emit_block_exits(line)?;
                 let p = self.current.emit_jump(OpCode::Jump, line);
                 self.loop_stack.last_mut().unwrap().break_jumps.push(p);
             }
@@ -260,17 +587,55 @@ impl Compiler {
                 if self.loop_stack.is_empty() {
                     return Err(WhispemError::new(ErrorKind::ContinueOutsideLoop, Span::new(line, 0)));
                 }
+                self.emit_try_exits(line);
+                self.emit_block_exits(line)?;
                 let p = self.current.emit_jump(OpCode::Jump, line);
                 self.loop_stack.last_mut().unwrap().continue_jumps.push(p);
             }
-            Stmt::IndexAssign { object, index, value, line } => {
-                let obj_c = self.name_const(&object, line)?;
-                self.current.emit_op_u8(OpCode::Load, obj_c, line);
-                self.compile_expr(index, line)?;
+            Stmt::Try { body, error_name, handler, line } => {
+                let begin    = self.current.emit_jump(OpCode::TryBegin, line);
+                let declared = self.blocks().declared.len();
+                self.try_depth += 1;
+                self.compile_block(body, line)?;
+                self.try_depth -= 1;
+                self.current.emit_op(OpCode::TryEnd, line);
+                let jend = self.current.emit_jump(OpCode::Jump, line);
+                let catch_start = self.current.current_offset();
+                self.current.patch_jump(begin, catch_start);
+                // An error skips the ends of the blocks it left.
+                let left: Vec<String> = self.blocks().declared[declared..].to_vec();
+                for slot in left { self.emit_close(&slot, line)?; }
+                // The VM enters the handler with the error dict on the stack.
+                self.begin_block();
+                self.emit_let_store(&error_name, line)?;
+                for s in handler { self.compile_stmt(s)?; }
+                self.end_block(line)?;
+                let end = self.current.current_offset();
+                self.current.patch_jump(jend, end);
+            }
+            Stmt::Yield { value, line } => {
+                if self.scope_stack.is_empty() {
+                    return Err(WhispemError::new(ErrorKind::YieldOutsideFunction, Span::new(line, 0)));
+                }
+                match value {
+                    Some(expr) => self.compile_expr(expr, line)?,
+                    None       => self.current.emit_op(OpCode::PushNone, line),
+                }
+                self.current.emit_op(OpCode::Yield, line);
+            }
+            Stmt::Throw { value, line } => {
                 self.compile_expr(value, line)?;
-                self.current.emit_op(OpCode::SetIndex, line);
-                self.current.emit_op_u8(OpCode::Store, obj_c, line);
+                self.current.emit_op(OpCode::Throw, line);
             }
+            Stmt::Import { line, .. } => {
+                return Err(WhispemError::new(
+                    ErrorKind::ImportError("'import' is only allowed at the top level".to_string()),
+                    Span::new(line, 0),
+                ));
+            }
+            Stmt::Global { names, line } => self.declare_globals(names, line)?,
+            Stmt::Struct { name, fields, .. } => self.declare_struct(&name, &fields),
+            Stmt::Assign { target, op, value, line } => self.compile_assign(target, op, value, line)?,
             Stmt::Expression { expr, line } => {
                 self.compile_expr(expr, line)?;
                 self.current.emit_op(OpCode::Pop, line);
@@ -279,8 +644,297 @@ impl Compiler {
         Ok(())
     }
 
+    // `root[k].f[j] op= value`: every container on the path is loaded,
+    // updated from the inside out with SET_INDEX / SET_FIELD, and the
+    // root is stored back into the binding it names.  A key needed more than once is
+    // evaluated once, into a hidden variable.
+    fn compile_assign(
+        &mut self, target: Expr, op: Option<BinaryOp>, value: Expr, line: usize,
+    ) -> WhispemResult<()> {
+        let mut steps = Vec::new();
+        let mut node  = target;
+        let root = loop {
+            match node {
+                Expr::Variable(name)              => break name,
+                Expr::Index { object, index }     => { steps.push(Place::Index(*index)); node = *object; }
+                Expr::Member { object, name, .. } => { steps.push(Place::Field(name));   node = *object; }
+                _ => unreachable!("the parser only builds assignable targets"),
+            }
+        };
+        steps.reverse();
+
+        let n = steps.len();
+        let mut keys = Vec::with_capacity(n);
+        for (j, step) in steps.into_iter().enumerate() {
+            keys.push(match step {
+                Place::Field(name) => Key::Field(self.name_const(&name, line)?),
+                Place::Index(index @ (Expr::Int(_) | Expr::Number(_) | Expr::Str(_) | Expr::Bool(_))) => Key::Inline(index),
+                Place::Index(index) if j + 1 == n && op.is_none() => Key::Inline(index),
+                Place::Index(index) => {
+                    let hidden = format!("__key_{}", self.key_count);
+                    self.key_count += 1;
+                    self.compile_expr(index, line)?;
+                    let c = self.name_const(&hidden, line)?;
+                    self.current.emit_op_u8(OpCode::Store, c, line);
+                    Key::Hidden(c)
+                }
+            });
+        }
+
+        // Each container with the key that addresses its child.
+        for j in 0..n {
+            self.emit_place(&root, &keys[..j], line)?;
+            self.emit_key(&keys[j], line)?;
+        }
+        if let Some(op) = op {
+            self.emit_place(&root, &keys, line)?;
+            self.compile_expr(value, line)?;
+            self.current.emit_op(binary_opcode(&op), line);
+        } else {
+            self.compile_expr(value, line)?;
+        }
+        for key in keys.iter().rev() {
+            match key {
+                Key::Field(c) => self.current.emit_op_u8(OpCode::SetField, *c, line),
+                _             => self.current.emit_op(OpCode::SetIndex, line),
+            }
+        }
+        self.emit_assign_store(&root, line)
+    }
+
+    // Push the value at `root` followed by `keys`.
+    fn emit_place(&mut self, root: &str, keys: &[Key], line: usize) -> WhispemResult<()> {
+        self.emit_load(root, line)?;
+        for key in keys {
+            match key {
+                Key::Field(c) => self.current.emit_op_u8(OpCode::GetField, *c, line),
+                _ => {
+                    self.emit_key(key, line)?;
+                    self.current.emit_op(OpCode::GetIndex, line);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn emit_key(&mut self, key: &Key, line: usize) -> WhispemResult<()> {
+        match key {
+            Key::Inline(index) => self.compile_expr(index.clone(), line),
+            Key::Hidden(c)     => { self.current.emit_op_u8(OpCode::Load, *c, line); Ok(()) }
+            Key::Field(_)      => Ok(()),
+        }
+    }
+
+    // Bind the value on top of the stack to `name`, as `let` does.  Inside
+    // a block this declares the name there, unless the block already has it.
+    fn emit_let_store(&mut self, name: &str, line: usize) -> WhispemResult<()> {
+        if !self.blocks().open.is_empty() {
+            let existing = self.blocks().open.last().unwrap().iter()
+                .find(|(n, _)| n == name).map(|(_, slot)| slot.clone());
+            let slot = match existing {
+                Some(slot) => slot,
+                None => {
+                    self.warn_if_shadowing(name, line);
+                    let slot = format!("{}#{}", name, self.block_count);
+                    self.block_count += 1;
+                    let blocks = self.blocks_mut();
+                    blocks.open.last_mut().unwrap().push((name.to_string(), slot.clone()));
+                    blocks.declared.push(slot.clone());
+                    slot
+                }
+            };
+            let idx = self.name_const(&slot, line)?;
+            self.current.emit_op_u8(OpCode::Store, idx, line);
+            return Ok(());
+        }
+        let depth = self.scope_stack.len();
+        if depth > 0 {
+            let scope = &self.scope_stack[depth - 1];
+            if scope.has_global(name) { return self.emit_store_global(name, line); }
+            if let Some(slot) = scope.upvalue_slot(name) {
+                self.current.emit_op_u8(OpCode::StoreUpvalue, slot, line);
+                return Ok(());
+            }
+            if !scope.has_local(name) { self.warn_if_shadowing(name, line); }
+        }
+        let target = if depth == 0 { self.global_name(name) } else { name.to_string() };
+        let idx = self.name_const(&target, line)?;
+        self.current.emit_op_u8(OpCode::Store, idx, line);
+        if self.scope_stack.is_empty() {
+            if !self.global_names.iter().any(|n| n == name) {
+                self.global_names.push(name.to_string());
+            }
+        } else {
+            self.scope_stack.last_mut().unwrap().add_local(name);
+        }
+        Ok(())
+    }
+
+    // Store the value on top of the stack into the binding `name` already
+    // refers to: a block variable, a local, a captured variable or a global.
+    // A name bound nowhere is declared as `let` would.
+    fn emit_assign_store(&mut self, name: &str, line: usize) -> WhispemResult<()> {
+        if let Some(slot) = self.blocks().slot(name) {
+            let idx = self.name_const(&slot, line)?;
+            self.current.emit_op_u8(OpCode::Store, idx, line);
+            return Ok(());
+        }
+        let depth = self.scope_stack.len();
+        if depth > 0 && self.scope_stack[depth - 1].has_global(name) {
+            return self.emit_store_global(name, line);
+        }
+        if depth > 0 && !self.scope_stack[depth - 1].has_local(name) {
+            let slot = match self.scope_stack[depth - 1].upvalue_slot(name) {
+                Some(slot) => Some(slot),
+                None       => self.resolve_upvalue(depth - 1, name)?,
+            };
+            if let Some(slot) = slot {
+                self.current.emit_op_u8(OpCode::StoreUpvalue, slot, line);
+                return Ok(());
+            }
+        }
+        if depth == 0 || self.scope_stack[depth - 1].has_local(name) {
+            let target = if depth == 0 { self.global_name(name) } else { name.to_string() };
+            let idx = self.name_const(&target, line)?;
+            self.current.emit_op_u8(OpCode::Store, idx, line);
+            if depth == 0 && !self.global_names.iter().any(|n| n == name) {
+                self.global_names.push(name.to_string());
+            }
+            return Ok(());
+        }
+        if self.global_names.iter().any(|n| n == name) {
+            return self.emit_store_global(name, line);
+        }
+        self.emit_let_store(name, line)
+    }
+
+    fn emit_store_global(&mut self, name: &str, line: usize) -> WhispemResult<()> {
+        let idx = self.name_const(&self.global_name(name), line)?;
+        self.current.emit_op_u8(OpCode::StoreGlobal, idx, line);
+        Ok(())
+    }
+
+    // `global a, b`: in the rest of this function, these names are the
+    // globals, for reading and for `let` / assignment.
+    fn declare_globals(&mut self, names: Vec<String>, line: usize) -> WhispemResult<()> {
+        if self.scope_stack.is_empty() {
+            return Err(WhispemError::new(ErrorKind::GlobalOutsideFunction, Span::new(line, 0)));
+        }
+        for name in names {
+            let local = self.blocks().slot(&name).is_some()
+                || self.scope_stack.last().is_some_and(|s| s.has_local(&name) || s.upvalue_slot(&name).is_some());
+            if local {
+                return Err(WhispemError::new(ErrorKind::GlobalAfterLocal(name), Span::new(line, 0)));
+            }
+            let scope = self.scope_stack.last_mut().unwrap();
+            if !scope.has_global(&name) { scope.globals.push(name); }
+        }
+        Ok(())
+    }
+
+    // A function that reads a global and then declares a variable of the
+    // same name most likely meant to update the global.
+    fn warn_if_shadowing(&mut self, name: &str, line: usize) {
+        let Some(scope) = self.scope_stack.last() else { return };
+        if !scope.read_globals.iter().any(|n| n == name) { return; }
+        self.warnings.push(Warning {
+            message: format!(
+                "'let {0}' declares a local that shadows the global '{0}' this function reads; \
+                 use '{0} = ...' or 'global {0}' to update the global", name),
+            span: Span::new(line, 0),
+        });
+    }
+
+    // Whether `let name` here would declare a block variable hiding a
+    // binding from outside the innermost block.
+    fn let_shadows_outer(&self, name: &str) -> bool {
+        let blocks = self.blocks();
+        let Some(innermost) = blocks.open.last() else { return false };
+        if innermost.iter().any(|(n, _)| n == name) { return false; }
+        blocks.slot(name).is_some()
+            || self.scope_stack.iter().any(|s| s.has_local(name) || s.has_global(name) || s.blocks.slot(name).is_some())
+            || self.scope_stack.last().is_some_and(|s| s.upvalue_slot(name).is_some())
+            || self.global_names.iter().any(|n| n == name)
+    }
+
+    fn blocks(&self) -> &Blocks {
+        self.scope_stack.last().map_or(&self.top_blocks, |scope| &scope.blocks)
+    }
+
+    fn blocks_mut(&mut self) -> &mut Blocks {
+        match self.scope_stack.last_mut() {
+            Some(scope) => &mut scope.blocks,
+            None        => &mut self.top_blocks,
+        }
+    }
+
+    fn begin_block(&mut self) { self.blocks_mut().open.push(Vec::new()); }
+
+    // Close the innermost block, dropping the names it declared.
+    fn end_block(&mut self, line: usize) -> WhispemResult<()> {
+        let block = self.blocks_mut().open.pop().unwrap_or_default();
+        for (_, slot) in block { self.emit_close(&slot, line)?; }
+        Ok(())
+    }
+
+    fn compile_block(&mut self, stmts: Vec<Stmt>, line: usize) -> WhispemResult<()> {
+        self.begin_block();
+        for s in stmts { self.compile_stmt(s)?; }
+        self.end_block(line)
+    }
+
+    // A block whose value is left on the stack: that of a final expression
+    // statement, or else `none`.
+    fn compile_value_block(&mut self, mut stmts: Vec<Stmt>, line: usize) -> WhispemResult<()> {
+        self.begin_block();
+        let last = stmts.pop();
+        for s in stmts { self.compile_stmt(s)?; }
+        match last {
+            Some(Stmt::Expression { expr, line }) => self.compile_expr(expr, line)?,
+            Some(s) => {
+                self.compile_stmt(s)?;
+                self.current.emit_op(OpCode::PushNone, line);
+            }
+            None => self.current.emit_op(OpCode::PushNone, line),
+        }
+        self.end_block(line)
+    }
+
+    fn emit_close(&mut self, slot: &str, line: usize) -> WhispemResult<()> {
+        let idx = self.name_const(slot, line)?;
+        self.current.emit_op_u8(OpCode::CloseUpvalue, idx, line);
+        Ok(())
+    }
+
+    // `break` / `continue` leave every block opened inside the loop.
+    fn emit_block_exits(&mut self, line: usize) -> WhispemResult<()> {
+        let from  = self.loop_stack.last().map_or(0, |l| l.block_depth);
+        let slots: Vec<String> = self.blocks().open[from..].iter()
+            .flat_map(|block| block.iter().map(|(_, slot)| slot.clone()))
+            .collect();
+        for slot in slots { self.emit_close(&slot, line)?; }
+        Ok(())
+    }
+
+    fn push_loop(&mut self) {
+        let block_depth = self.blocks().open.len();
+        self.loop_stack.push(LoopContext {
+            break_jumps: vec![], continue_jumps: vec![], try_depth: self.try_depth, block_depth,
+        });
+    }
+
+    // `break` / `continue` leave every `try` opened inside the loop.
+    fn emit_try_exits(&mut self, line: usize) {
+        let loop_trys = self.loop_stack.last().map_or(0, |l| l.try_depth);
+        for _ in loop_trys..self.try_depth { self.current.emit_op(OpCode::TryEnd, line); }
+    }
+
     fn compile_expr(&mut self, expr: Expr, line: usize) -> WhispemResult<()> {
         match expr {
+            Expr::Int(n) => {
+                let idx = self.current.add_constant(Value::Int(n));
+                self.current.emit_op_u8(OpCode::PushConst, idx, line);
+            }
             Expr::Number(n) => {
                 let idx = self.current.add_constant(Value::Number(n));
                 self.current.emit_op_u8(OpCode::PushConst, idx, line);
@@ -291,6 +945,7 @@ impl Compiler {
             }
             Expr::Bool(true)  => self.current.emit_op(OpCode::PushTrue,  line),
             Expr::Bool(false) => self.current.emit_op(OpCode::PushFalse, line),
+            Expr::None        => self.current.emit_op(OpCode::PushNone,  line),
             Expr::FStr(_)     => unreachable!("FStr must be desugared by the parser"),
             Expr::Variable(name) => { self.emit_load(&name, line)?; }
             Expr::Array(elems) => {
@@ -314,20 +969,7 @@ impl Compiler {
             Expr::Binary { left, op, right } => {
                 self.compile_expr(*left,  line)?;
                 self.compile_expr(*right, line)?;
-                let opcode = match op {
-                    BinaryOp::Add          => OpCode::Add,
-                    BinaryOp::Sub          => OpCode::Sub,
-                    BinaryOp::Mul          => OpCode::Mul,
-                    BinaryOp::Div          => OpCode::Div,
-                    BinaryOp::Mod          => OpCode::Mod,
-                    BinaryOp::Less         => OpCode::Lt,
-                    BinaryOp::LessEqual    => OpCode::Lte,
-                    BinaryOp::Greater      => OpCode::Gt,
-                    BinaryOp::GreaterEqual => OpCode::Gte,
-                    BinaryOp::EqualEqual   => OpCode::Eq,
-                    BinaryOp::BangEqual    => OpCode::Neq,
-                };
-                self.current.emit_op(opcode, line);
+                self.current.emit_op(binary_opcode(&op), line);
             }
             Expr::Logical { left, op, right } => {
                 self.compile_expr(*left, line)?;
@@ -348,6 +990,28 @@ impl Compiler {
                     }
                 }
             }
+            Expr::If { condition, then_branch, else_branch, line: iline } => {
+                self.compile_expr(*condition, iline)?;
+                let jelse = self.current.emit_jump(OpCode::JumpIfFalse, iline);
+                self.compile_value_block(then_branch, iline)?;
+                let jend = self.current.emit_jump(OpCode::Jump, iline);
+                let else_start = self.current.current_offset();
+                self.current.patch_jump(jelse, else_start);
+                self.compile_value_block(else_branch.unwrap_or_default(), iline)?;
+                let end = self.current.current_offset();
+                self.current.patch_jump(jend, end);
+            }
+            Expr::Conditional { condition, then_value, else_value } => {
+                self.compile_expr(*condition, line)?;
+                let jelse = self.current.emit_jump(OpCode::JumpIfFalse, line);
+                self.compile_expr(*then_value, line)?;
+                let jend = self.current.emit_jump(OpCode::Jump, line);
+                let else_start = self.current.current_offset();
+                self.current.patch_jump(jelse, else_start);
+                self.compile_expr(*else_value, line)?;
+                let end = self.current.current_offset();
+                self.current.patch_jump(jend, end);
+            }
             Expr::Unary { op, operand } => {
                 self.compile_expr(*operand, line)?;
                 match op {
@@ -355,22 +1019,72 @@ impl Compiler {
                     UnaryOp::Neg => self.current.emit_op(OpCode::Neg, line),
                 }
             }
-            Expr::Call { name, arguments, line: call_line } => {
+            Expr::Call { name, arguments, named, line: call_line } if self.struct_named(&name).is_some() => {
+                let proto  = self.struct_named(&name).unwrap();
+                let fields = Self::struct_fields(&proto);
+                if !named.is_empty() && arguments.len() <= fields.len() {
+                    // `Point(1, y: 2)` is the literal `Point { x: 1, y: 2 }`.
+                    let mut given: Vec<(String, Expr)> = fields.iter().cloned().zip(arguments).collect();
+                    given.extend(named);
+                    return self.compile_expr(Expr::StructLit { name, fields: given, line: call_line }, line);
+                }
+                if arguments.len() != fields.len() {
+                    return Err(WhispemError::new(
+                        ErrorKind::ArgumentCount {
+                            name, min: fields.len(), max: Some(fields.len()), got: arguments.len() + named.len(),
+                        },
+                        Span::new(call_line, 0),
+                    ));
+                }
+                for arg in arguments { self.compile_expr(arg, call_line)?; }
+                let idx = self.struct_const(proto, call_line)?;
+                self.current.emit_op_u8(OpCode::MakeStruct, idx, call_line);
+            }
+            Expr::StructLit { name, fields: mut given, line: lit_line } => {
+                let span  = Span::new(lit_line, 0);
+                let proto = self.struct_named(&name)
+                    .ok_or_else(|| WhispemError::new(ErrorKind::UnknownStruct(name.clone()), span))?;
+                let decl  = Self::struct_fields(&proto).to_vec();
+                if let Some((field, _)) = given.iter().find(|(f, _)| !decl.contains(f)) {
+                    return Err(WhispemError::new(
+                        ErrorKind::UnknownField { struct_name: name, field: field.clone() }, span,
+                    ));
+                }
+                for field in &decl {
+                    let pos = given.iter().position(|(f, _)| f == field).ok_or_else(|| WhispemError::new(
+                        ErrorKind::MissingField { struct_name: name.clone(), field: field.clone() }, span,
+                    ))?;
+                    let (_, value) = given.remove(pos);
+                    self.compile_expr(value, lit_line)?;
+                }
+                if let Some((field, _)) = given.first() {
+                    return Err(WhispemError::new(
+                        ErrorKind::UnexpectedToken { expected: "each field once".to_string(), found: format!("'{}' again", field) },
+                        span,
+                    ));
+                }
+                let idx = self.struct_const(proto, lit_line)?;
+                self.current.emit_op_u8(OpCode::MakeStruct, idx, lit_line);
+            }
+            Expr::Call { name, arguments, named, line: call_line } => {
                 let argc   = arguments.len() as u8;
                 for arg in arguments { self.compile_expr(arg, call_line)?; }
-                let name_c = self.name_const(&name, call_line)?;
-                self.current.emit_byte(OpCode::Call as u8, call_line);
-                self.current.emit_byte(name_c, call_line);
-                self.current.emit_byte(argc,   call_line);
+                let name_c = self.name_const(&self.var_name(&name), call_line)?;
+                self.emit_call(name_c, argc, named, call_line)?;
             }
-            Expr::CallExpr { callee, arguments, line: call_line } => {
+            Expr::CallExpr { callee, arguments, named, line: call_line }
+                if matches!(&*callee, Expr::Member { object, .. } if self.is_module_alias(object)) =>
+            {
+                let Expr::Member { object, name, line: mline } = *callee else { unreachable!() };
+                let global = self.member_global(&object, &name, mline)?;
+                self.compile_expr(Expr::Call { name: global, arguments, named, line: call_line }, line)?;
+            }
+            Expr::CallExpr { callee, arguments, named, line: call_line } => {
                 let argc = arguments.len() as u8;
                 self.compile_expr(*callee, call_line)?;
                 for arg in arguments { self.compile_expr(arg, call_line)?; }
                 let sentinel = self.name_const("__callee__", call_line)?;
-                self.current.emit_byte(OpCode::Call as u8, call_line);
-                self.current.emit_byte(sentinel, call_line);
-                self.current.emit_byte(argc, call_line);
+                self.emit_call(sentinel, argc, named, call_line)?;
             }
             Expr::Lambda { params, body, line: lline } => {
                 let lambda_name = format!("__lambda_{}_{}", lline, self.lambda_count);
@@ -379,19 +1093,222 @@ impl Compiler {
                     self.compile_fn_body(&lambda_name, &params, &body, lline)?;
                 self.emit_make_closure(&chunk.name, &uv_descs, lline)?;
             }
+            Expr::Match { subject, arms, line: mline } => {
+                // The subject is evaluated once into a hidden variable that
+                // every pattern test reloads.
+                let hidden = format!("__match_{}", self.match_count);
+                self.match_count += 1;
+                self.compile_expr(*subject, mline)?;
+                let subject_c = self.name_const(&hidden, mline)?;
+                self.current.emit_op_u8(OpCode::Store, subject_c, mline);
+
+                let mut end_jumps  = Vec::new();
+                let mut exhaustive = false;
+                for arm in arms {
+                    // Each arm's bindings are scoped to the arm.
+                    self.begin_block();
+                    let mut fails = Vec::new();
+                    self.compile_pattern(&arm.pattern, subject_c, &mut Vec::new(), &mut fails, mline)?;
+                    if let Some(guard) = arm.guard {
+                        self.compile_expr(guard, mline)?;
+                        fails.push(self.current.emit_jump(OpCode::JumpIfFalse, mline));
+                    } else if fails.is_empty() {
+                        exhaustive = true;
+                    }
+                    match arm.body {
+                        ArmBody::Expr(e) => self.compile_expr(e, mline)?,
+                        ArmBody::Block(stmts) => {
+                            for s in stmts { self.compile_stmt(s)?; }
+                            self.current.emit_op(OpCode::PushNone, mline);
+                        }
+                    }
+                    let bound = self.blocks().open.last().cloned().unwrap_or_default();
+                    self.end_block(mline)?;
+                    end_jumps.push(self.current.emit_jump(OpCode::Jump, mline));
+                    let next = self.current.current_offset();
+                    // A failed test may come after some of the bindings.
+                    if !fails.is_empty() {
+                        for (_, slot) in bound { self.emit_close(&slot, mline)?; }
+                    }
+                    for f in fails { self.current.patch_jump(f, next); }
+                    // Arms after one that always matches can never run.
+                    if exhaustive { break; }
+                }
+                if !exhaustive {
+                    self.current.emit_op_u8(OpCode::Load, subject_c, mline);
+                    self.current.emit_op(OpCode::MatchFail, mline);
+                }
+                let end = self.current.current_offset();
+                for j in end_jumps { self.current.patch_jump(j, end); }
+            }
+            Expr::Member { object, name, line: mline } if self.is_module_alias(&object) => {
+                let global = self.member_global(&object, &name, mline)?;
+                let idx    = self.name_const(&global, mline)?;
+                self.current.emit_op_u8(OpCode::Load, idx, mline);
+            }
+            Expr::Member { object, name, line: mline } => {
+                self.compile_expr(*object, mline)?;
+                let idx = self.name_const(&name, mline)?;
+                self.current.emit_op_u8(OpCode::GetField, idx, mline);
+            }
+        }
+        Ok(())
+    }
+
+    // Emit the tests for `pattern` against the subject stored in `subject_c`,
+    // reached through the index keys in `path`.  Each failing test jumps to
+    // a location pushed onto `fails`; bindings are stored as `let` does.
+    fn compile_pattern(
+        &mut self, pattern: &Pattern, subject_c: u8, path: &mut Vec<Value>,
+        fails: &mut Vec<usize>, line: usize,
+    ) -> WhispemResult<()> {
+        match pattern {
+            Pattern::Wildcard => {}
+            Pattern::Literal(e) => {
+                self.emit_subject(subject_c, path, line)?;
+                self.compile_expr(e.clone(), line)?;
+                self.current.emit_op(OpCode::Eq, line);
+                fails.push(self.current.emit_jump(OpCode::JumpIfFalse, line));
+            }
+            Pattern::Bind(name) => {
+                self.emit_subject(subject_c, path, line)?;
+                self.emit_let_store(name, line)?;
+            }
+            Pattern::Typed { name, type_name } => {
+                self.emit_type_test(type_name, subject_c, path, fails, line)?;
+                if name != "_" {
+                    self.emit_subject(subject_c, path, line)?;
+                    self.emit_let_store(name, line)?;
+                }
+            }
+            Pattern::Array { elements, rest } => {
+                self.emit_type_test("array", subject_c, path, fails, line)?;
+                self.emit_subject(subject_c, path, line)?;
+                self.emit_builtin_call("length", 1, line)?;
+                let n = self.constant(Value::Int(elements.len() as i64), line)?;
+                self.current.emit_op_u8(OpCode::PushConst, n, line);
+                self.current.emit_op(if rest.is_some() { OpCode::Gte } else { OpCode::Eq }, line);
+                fails.push(self.current.emit_jump(OpCode::JumpIfFalse, line));
+                for (i, element) in elements.iter().enumerate() {
+                    path.push(Value::Int(i as i64));
+                    self.compile_pattern(element, subject_c, path, fails, line)?;
+                    path.pop();
+                }
+                if let Some(rest) = rest.as_ref().filter(|r| *r != "_") {
+                    self.emit_subject(subject_c, path, line)?;
+                    self.current.emit_op_u8(OpCode::PushConst, n, line);
+                    self.emit_subject(subject_c, path, line)?;
+                    self.emit_builtin_call("length", 1, line)?;
+                    self.emit_builtin_call("slice", 3, line)?;
+                    self.emit_let_store(rest, line)?;
+                }
+            }
+            Pattern::Dict(entries) => {
+                self.emit_type_test("dict", subject_c, path, fails, line)?;
+                for (key, sub) in entries {
+                    self.emit_subject(subject_c, path, line)?;
+                    let k = self.constant(Value::Str(key.clone()), line)?;
+                    self.current.emit_op_u8(OpCode::PushConst, k, line);
+                    self.emit_builtin_call("has_key", 2, line)?;
+                    fails.push(self.current.emit_jump(OpCode::JumpIfFalse, line));
+                    path.push(Value::Str(key.clone()));
+                    self.compile_pattern(sub, subject_c, path, fails, line)?;
+                    path.pop();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Bind the value on top of the stack to the names in a `let` / `for`
+    // pattern.  UNPACK_ARRAY and UNPACK_DICT check the shape and leave the
+    // parts on the stack, first part on top, for the sub-patterns to take.
+    fn compile_destructure(&mut self, pattern: &Pattern, line: usize) -> WhispemResult<()> {
+        match pattern {
+            Pattern::Bind(name) => self.emit_let_store(name, line)?,
+            Pattern::Wildcard   => self.current.emit_op(OpCode::Pop, line),
+            Pattern::Array { elements, rest } => {
+                let count = self.pattern_width(elements.len(), line)?;
+                self.current.emit_op_u8(OpCode::UnpackArray, count, line);
+                self.current.emit_byte(rest.is_some() as u8, line);
+                for element in elements { self.compile_destructure(element, line)?; }
+                match rest.as_deref() {
+                    Some("_")  => self.current.emit_op(OpCode::Pop, line),
+                    Some(rest) => self.emit_let_store(rest, line)?,
+                    None       => {}
+                }
+            }
+            Pattern::Dict(entries) => {
+                let count = self.pattern_width(entries.len(), line)?;
+                for (key, _) in entries {
+                    let k = self.constant(Value::Str(key.clone()), line)?;
+                    self.current.emit_op_u8(OpCode::PushConst, k, line);
+                }
+                self.current.emit_op_u8(OpCode::UnpackDict, count, line);
+                for (_, sub) in entries { self.compile_destructure(sub, line)?; }
+            }
+            Pattern::Literal(_) | Pattern::Typed { .. } => unreachable!("the parser builds bindings without tests"),
         }
         Ok(())
     }
 
+    fn pattern_width(&self, n: usize, line: usize) -> WhispemResult<u8> {
+        u8::try_from(n).map_err(|_| WhispemError::new(
+            ErrorKind::UnexpectedToken { expected: "at most 255 names in one pattern".to_string(), found: n.to_string() },
+            Span::new(line, 0),
+        ))
+    }
+
+    fn emit_subject(&mut self, subject_c: u8, path: &[Value], line: usize) -> WhispemResult<()> {
+        self.current.emit_op_u8(OpCode::Load, subject_c, line);
+        for key in path {
+            let k = self.constant(key.clone(), line)?;
+            self.current.emit_op_u8(OpCode::PushConst, k, line);
+            self.current.emit_op(OpCode::GetIndex, line);
+        }
+        Ok(())
+    }
+
+    fn emit_type_test(
+        &mut self, type_name: &str, subject_c: u8, path: &[Value], fails: &mut Vec<usize>, line: usize,
+    ) -> WhispemResult<()> {
+        self.emit_subject(subject_c, path, line)?;
+        self.emit_builtin_call("type_of", 1, line)?;
+        let t = self.constant(Value::Str(type_name.to_string()), line)?;
+        self.current.emit_op_u8(OpCode::PushConst, t, line);
+        self.current.emit_op(OpCode::Eq, line);
+        fails.push(self.current.emit_jump(OpCode::JumpIfFalse, line));
+        Ok(())
+    }
+
+    fn emit_builtin_call(&mut self, name: &str, argc: u8, line: usize) -> WhispemResult<()> {
+        let name_c = self.name_const(name, line)?;
+        self.current.emit_byte(OpCode::Call as u8, line);
+        self.current.emit_byte(name_c, line);
+        self.current.emit_byte(argc, line);
+        Ok(())
+    }
+
     fn emit_load(&mut self, name: &str, line: usize) -> WhispemResult<()> {
+        if let Some(slot) = self.blocks().slot(name) {
+            let idx = self.name_const(&slot, line)?;
+            self.current.emit_op_u8(OpCode::Load, idx, line);
+            return Ok(());
+        }
         let depth = self.scope_stack.len();
 
         if depth == 0 {
-            let idx = self.name_const(name, line)?;
+            let idx = self.name_const(&self.global_name(name), line)?;
             self.current.emit_op_u8(OpCode::Load, idx, line);
             return Ok(());
         }
 
+        if self.scope_stack[depth - 1].has_global(name) {
+            let idx = self.name_const(&self.global_name(name), line)?;
+            self.current.emit_op_u8(OpCode::LoadGlobal, idx, line);
+            return Ok(());
+        }
+
         if self.scope_stack[depth - 1].has_local(name) {
             let idx = self.name_const(name, line)?;
             self.current.emit_op_u8(OpCode::Load, idx, line);
@@ -403,37 +1320,47 @@ impl Compiler {
             return Ok(());
         }
 
-        if let Some(uv_slot) = self.resolve_upvalue(depth - 1, name, line)? {
+        if let Some(uv_slot) = self.resolve_upvalue(depth - 1, name)? {
             self.current.emit_op_u8(OpCode::LoadUpvalue, uv_slot, line);
             return Ok(());
         }
 
         if self.global_names.contains(&name.to_string()) {
-            let idx = self.name_const(name, line)?;
+            let scope = &mut self.scope_stack[depth - 1];
+            if !scope.read_globals.iter().any(|n| n == name) { scope.read_globals.push(name.to_string()); }
+            let idx = self.name_const(&self.global_name(name), line)?;
             self.current.emit_op_u8(OpCode::LoadGlobal, idx, line);
             return Ok(());
         }
 
-        let idx = self.name_const(name, line)?;
+        let idx = self.name_const(&self.global_name(name), line)?;
         self.current.emit_op_u8(OpCode::Load, idx, line);
         Ok(())
     }
 
     fn resolve_upvalue(
-        &mut self, scope_idx: usize, name: &str, line: usize,
+        &mut self, scope_idx: usize, name: &str,
     ) -> WhispemResult<Option<u8>> {
-        if scope_idx == 0 { return Ok(None); }
-        let parent = scope_idx - 1;
-
-        if self.scope_stack[parent].has_local(name) {
+        // The enclosing function's binding, or for a function directly
+        // under the top level, a variable of a top-level block.
+        let enclosing = match scope_idx.checked_sub(1) {
+            Some(parent) => {
+                let scope = &self.scope_stack[parent];
+                scope.blocks.slot(name).or_else(|| scope.has_local(name).then(|| name.to_string()))
+            }
+            None => self.top_blocks.slot(name),
+        };
+        if let Some(slot) = enclosing {
             let slot = self.scope_stack[scope_idx].add_upvalue(
                 name,
-                UpvalueDesc { is_local: true, name: name.to_string() },
+                UpvalueDesc { is_local: true, name: slot },
             );
             return Ok(Some(slot));
         }
+        if scope_idx == 0 { return Ok(None); }
+        let parent = scope_idx - 1;
 
-        if let Some(parent_slot) = self.resolve_upvalue(parent, name, line)? {
+        if let Some(parent_slot) = self.resolve_upvalue(parent, name)? {
             let slot = self.scope_stack[scope_idx].add_upvalue(
                 name,
                 UpvalueDesc { is_local: false, name: parent_slot.to_string() },
@@ -444,10 +1371,170 @@ impl Compiler {
         Ok(None)
     }
 
+    fn struct_const(&mut self, proto: Value, line: usize) -> WhispemResult<u8> {
+        if let Some(i) = self.current.constants.iter().position(|c| match (c, &proto) {
+            (Value::Struct(a), Value::Struct(b)) => std::rc::Rc::ptr_eq(&a.def, &b.def),
+            _ => false,
+        }) {
+            return Ok(i as u8);
+        }
+        if self.current.constants.len() >= 256 {
+            return Err(WhispemError::new(ErrorKind::TooManyConstants, Span::new(line, 0)));
+        }
+        Ok(self.current.add_constant(proto))
+    }
+
     fn name_const(&mut self, name: &str, line: usize) -> WhispemResult<u8> {
+        self.constant(Value::Str(name.to_string()), line)
+    }
+
+    fn constant(&mut self, value: Value, line: usize) -> WhispemResult<u8> {
         if self.current.constants.len() >= 256 {
             return Err(WhispemError::new(ErrorKind::TooManyConstants, Span::new(line, 0)));
         }
-        Ok(self.current.add_constant(Value::Str(name.to_string())))
+        Ok(self.current.add_constant(value))
+    }
+}
+fn binary_opcode(op: &BinaryOp) -> OpCode {
+    match op {
+        BinaryOp::Add          => OpCode::Add,
+        BinaryOp::Sub          => OpCode::Sub,
+        BinaryOp::Mul          => OpCode::Mul,
+        BinaryOp::Div          => OpCode::Div,
+        BinaryOp::IntDiv       => OpCode::IntDiv,
+        BinaryOp::Mod          => OpCode::Mod,
+        BinaryOp::Less         => OpCode::Lt,
+        BinaryOp::LessEqual    => OpCode::Lte,
+        BinaryOp::Greater      => OpCode::Gt,
+        BinaryOp::GreaterEqual => OpCode::Gte,
+        BinaryOp::EqualEqual   => OpCode::Eq,
+        BinaryOp::BangEqual    => OpCode::Neq,
+    }
+}
+
+// Names a module defines at its top level: functions, structs, and `let`s
+// outside any block.
+fn collect_top_level_names(stmts: &[Stmt], out: &mut Vec<String>) {
+    let add = |out: &mut Vec<String>, name: &String| {
+        if !out.contains(name) { out.push(name.clone()); }
+    };
+    for stmt in stmts {
+        match stmt {
+            Stmt::Let { name, .. } | Stmt::Function { name, .. } | Stmt::Struct { name, .. } => add(out, name),
+            Stmt::Destructure { pattern, .. } => pattern_names(pattern, out),
+            _ => {}
+        }
+    }
+}
+
+// The names a destructuring pattern binds, in order.
+fn pattern_names(pattern: &Pattern, out: &mut Vec<String>) {
+    let mut add = |name: &String| if !out.contains(name) { out.push(name.clone()); };
+    match pattern {
+        Pattern::Bind(name) => add(name),
+        Pattern::Array { elements, rest } => {
+            if let Some(rest) = rest.as_ref().filter(|r| *r != "_") { add(rest); }
+            for e in elements { pattern_names(e, out); }
+        }
+        Pattern::Dict(entries) => for (_, sub) in entries { pattern_names(sub, out); },
+        _ => {}
+    }
+}
+
+// Whether a function body contains a `yield` of its own, outside any
+// nested function or lambda.
+fn block_yields(stmts: &[Stmt]) -> bool {
+    stmts.iter().any(|stmt| match stmt {
+        Stmt::Yield { .. } => true,
+        Stmt::If { condition, then_branch, else_branch, .. } =>
+            expr_yields(condition) || block_yields(then_branch) || else_branch.as_deref().is_some_and(block_yields),
+        Stmt::While { condition, body, .. } => expr_yields(condition) || block_yields(body),
+        Stmt::For { iterable, body, .. }    => expr_yields(iterable) || block_yields(body),
+        Stmt::Try { body, handler, .. }     => block_yields(body) || block_yields(handler),
+        Stmt::Let { value, .. } | Stmt::Destructure { value, .. } | Stmt::Print { value, .. }
+        | Stmt::Throw { value, .. } | Stmt::Assign { value, .. } => expr_yields(value),
+        Stmt::Return { value: Some(value), .. } | Stmt::Expression { expr: value, .. } => expr_yields(value),
+        _ => false,
+    })
+}
+
+// `if` and `match` expressions hold blocks, which may `yield`.
+fn expr_yields(expr: &Expr) -> bool {
+    let any = |exprs: &[Expr]| exprs.iter().any(expr_yields);
+    match expr {
+        Expr::If { condition, then_branch, else_branch, .. } =>
+            expr_yields(condition) || block_yields(then_branch) || else_branch.as_deref().is_some_and(block_yields),
+        Expr::Match { subject, arms, .. } => expr_yields(subject) || arms.iter().any(|arm| {
+            arm.guard.as_ref().is_some_and(expr_yields) || match &arm.body {
+                ArmBody::Expr(e)  => expr_yields(e),
+                ArmBody::Block(b) => block_yields(b),
+            }
+        }),
+        Expr::FStr(parts) => parts.iter().any(|p| matches!(p, FStrPart::Expr(e) if expr_yields(e))),
+        Expr::Array(items) => any(items),
+        Expr::Dict(pairs)  => pairs.iter().any(|(k, v)| expr_yields(k) || expr_yields(v)),
+        Expr::Index { object: a, index: b } | Expr::Binary { left: a, right: b, .. }
+        | Expr::Logical { left: a, right: b, .. } => expr_yields(a) || expr_yields(b),
+        Expr::Unary { operand, .. } | Expr::Member { object: operand, .. } => expr_yields(operand),
+        Expr::Call { arguments, named, .. } => any(arguments) || named.iter().any(|(_, e)| expr_yields(e)),
+        Expr::CallExpr { callee, arguments, named, .. } =>
+            expr_yields(callee) || any(arguments) || named.iter().any(|(_, e)| expr_yields(e)),
+        Expr::StructLit { fields, .. } => fields.iter().any(|(_, e)| expr_yields(e)),
+        Expr::Conditional { condition, then_value, else_value } =>
+            expr_yields(condition) || expr_yields(then_value) || expr_yields(else_value),
+        // A lambda is a function of its own.
+        Expr::Lambda { .. } | Expr::Int(_) | Expr::Number(_) | Expr::Str(_) | Expr::Bool(_)
+        | Expr::None | Expr::Variable(_) => false,
     }
-}
\ No newline at end of file
+}
+
+// Whether `expr` reads the variable `name`, outside any nested lambda.
+fn expr_reads(expr: &Expr, name: &str) -> bool {
+    let any = |exprs: &[Expr]| exprs.iter().any(|e| expr_reads(e, name));
+    match expr {
+        Expr::Variable(v) => v == name,
+        Expr::If { condition, then_branch, else_branch, .. } =>
+            expr_reads(condition, name) || block_reads(then_branch, name)
+                || else_branch.as_deref().is_some_and(|b| block_reads(b, name)),
+        Expr::Match { subject, arms, .. } => expr_reads(subject, name) || arms.iter().any(|arm| {
+            arm.guard.as_ref().is_some_and(|g| expr_reads(g, name)) || match &arm.body {
+                ArmBody::Expr(e)  => expr_reads(e, name),
+                ArmBody::Block(b) => block_reads(b, name),
+            }
+        }),
+        Expr::FStr(parts) => parts.iter().any(|p| matches!(p, FStrPart::Expr(e) if expr_reads(e, name))),
+        Expr::Array(items) => any(items),
+        Expr::Dict(pairs)  => pairs.iter().any(|(k, v)| expr_reads(k, name) || expr_reads(v, name)),
+        Expr::Index { object: a, index: b } | Expr::Binary { left: a, right: b, .. }
+        | Expr::Logical { left: a, right: b, .. } => expr_reads(a, name) || expr_reads(b, name),
+        Expr::Unary { operand, .. } | Expr::Member { object: operand, .. } => expr_reads(operand, name),
+        Expr::Call { arguments, named, .. } => any(arguments) || named.iter().any(|(_, e)| expr_reads(e, name)),
+        Expr::CallExpr { callee, arguments, named, .. } =>
+            expr_reads(callee, name) || any(arguments) || named.iter().any(|(_, e)| expr_reads(e, name)),
+        Expr::StructLit { fields, .. } => fields.iter().any(|(_, e)| expr_reads(e, name)),
+        Expr::Conditional { condition, then_value, else_value } =>
+            expr_reads(condition, name) || expr_reads(then_value, name) || expr_reads(else_value, name),
+        Expr::Lambda { .. } | Expr::Int(_) | Expr::Number(_) | Expr::Str(_) | Expr::Bool(_) | Expr::None => false,
+    }
+}
+
+// The statements of an `if` / `match` expression block, for `expr_reads`.
+fn block_reads(stmts: &[Stmt], name: &str) -> bool {
+    stmts.iter().any(|stmt| match stmt {
+        Stmt::Let { value, .. } | Stmt::Destructure { value, .. } | Stmt::Print { value, .. }
+        | Stmt::Throw { value, .. } | Stmt::Assign { value, .. } => expr_reads(value, name),
+        Stmt::Expression { expr, .. } | Stmt::Return { value: Some(expr), .. }
+        | Stmt::Yield { value: Some(expr), .. } => expr_reads(expr, name),
+        Stmt::If { condition, then_branch, else_branch, .. } =>
+            expr_reads(condition, name) || block_reads(then_branch, name)
+                || else_branch.as_deref().is_some_and(|b| block_reads(b, name)),
+        Stmt::While { condition, body, .. } => expr_reads(condition, name) || block_reads(body, name),
+        Stmt::For { iterable, body, .. }    => expr_reads(iterable, name) || block_reads(body, name),
+        Stmt::Try { body, handler, .. }     => block_reads(body, name) || block_reads(handler, name),
+        _ => false,
+    })
+}
+
+fn module_label(path: &Path) -> String {
+    path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default()
+}
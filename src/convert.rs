@@ -0,0 +1,176 @@
+use crate::error::{ErrorKind, WhispemError, WhispemResult};
+use crate::value::Value;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// Conversion from a Rust value into a Whispem `Value`.
+pub trait IntoValue {
+    fn into_value(self) -> Value;
+}
+
+/// Conversion from a Whispem `Value` into a Rust value.  Fails with a
+/// `TypeError` naming the script-side type that was found.
+pub trait FromValue: Sized {
+    fn from_value(value: Value) -> WhispemResult<Self>;
+}
+
+fn mismatch(expected: &str, found: &str) -> WhispemError {
+    WhispemError::runtime(ErrorKind::TypeError { expected: expected.to_string(), found: found.to_string() })
+}
+
+impl IntoValue for Value { fn into_value(self) -> Value { self } }
+impl FromValue for Value { fn from_value(value: Value) -> WhispemResult<Self> { Ok(value) } }
+
+impl IntoValue for () { fn into_value(self) -> Value { Value::None } }
+impl FromValue for () {
+    fn from_value(value: Value) -> WhispemResult<Self> {
+        match value {
+            Value::None => Ok(()),
+            other       => Err(mismatch("none", other.type_name())),
+        }
+    }
+}
+
+impl IntoValue for f64 { fn into_value(self) -> Value { Value::Number(self) } }
+impl FromValue for f64 {
+    fn from_value(value: Value) -> WhispemResult<Self> {
+        match value {
+            Value::Number(n) => Ok(n),
+            Value::Int(i)    => Ok(i as f64),
+            other            => Err(mismatch("number", other.type_name())),
+        }
+    }
+}
+
+impl IntoValue for f32 { fn into_value(self) -> Value { Value::Number(self as f64) } }
+impl FromValue for f32 {
+    fn from_value(value: Value) -> WhispemResult<Self> { f64::from_value(value).map(|n| n as f32) }
+}
+
+// Integers travel as ints; converting back accepts an int, or a float
+// with no fraction, that fits the target type.
+macro_rules! int_conversions {
+    ($($t:ty),*) => {$(
+        impl IntoValue for $t {
+            fn into_value(self) -> Value {
+                match i64::try_from(self) {
+                    Ok(i)  => Value::Int(i),
+                    Err(_) => Value::Number(self as f64),
+                }
+            }
+        }
+        impl FromValue for $t {
+            fn from_value(value: Value) -> WhispemResult<Self> {
+                if value.as_f64().is_none() { return Err(mismatch("number", value.type_name())); }
+                value.as_int()
+                    .and_then(|i| <$t>::try_from(i).ok())
+                    .ok_or_else(|| mismatch(stringify!($t), &format!("number {}", value)))
+            }
+        }
+    )*};
+}
+int_conversions!(i8, i16, i32, i64, isize, u8, u16, u32, u64, usize);
+
+impl IntoValue for bool { fn into_value(self) -> Value { Value::Bool(self) } }
+impl FromValue for bool {
+    fn from_value(value: Value) -> WhispemResult<Self> {
+        match value {
+            Value::Bool(b) => Ok(b),
+            other          => Err(mismatch("bool", other.type_name())),
+        }
+    }
+}
+
+impl IntoValue for String { fn into_value(self) -> Value { Value::Str(self) } }
+impl IntoValue for &str   { fn into_value(self) -> Value { Value::Str(self.to_string()) } }
+impl FromValue for String {
+    fn from_value(value: Value) -> WhispemResult<Self> {
+        match value {
+            Value::Str(s) => Ok(s),
+            other         => Err(mismatch("string", other.type_name())),
+        }
+    }
+}
+
+impl<T: IntoValue> IntoValue for Vec<T> {
+    fn into_value(self) -> Value {
+        Value::Array(Rc::new(self.into_iter().map(IntoValue::into_value).collect()))
+    }
+}
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: Value) -> WhispemResult<Self> {
+        match value {
+            Value::Array(a) => {
+                let items = Rc::try_unwrap(a).unwrap_or_else(|rc| (*rc).clone());
+                items.into_iter().map(T::from_value).collect()
+            }
+            Value::Range(r) => r.iter().map(T::from_value).collect(),
+            other => Err(mismatch("array", other.type_name())),
+        }
+    }
+}
+
+impl<T: IntoValue> IntoValue for HashMap<String, T> {
+    fn into_value(self) -> Value {
+        Value::Dict(Rc::new(self.into_iter().map(|(k, v)| (k, v.into_value())).collect()))
+    }
+}
+impl<T: FromValue> FromValue for HashMap<String, T> {
+    fn from_value(value: Value) -> WhispemResult<Self> {
+        match value {
+            Value::Dict(d) => {
+                let map = Rc::try_unwrap(d).unwrap_or_else(|rc| (*rc).clone());
+                map.into_iter().map(|(k, v)| Ok((k, T::from_value(v)?))).collect()
+            }
+            other => Err(mismatch("dict", other.type_name())),
+        }
+    }
+}
+
+// `none` maps to `None`; anything else must convert to `T`.
+impl<T: IntoValue> IntoValue for Option<T> {
+    fn into_value(self) -> Value {
+        match self {
+            Some(v) => v.into_value(),
+            None    => Value::None,
+        }
+    }
+}
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: Value) -> WhispemResult<Self> {
+        match value {
+            Value::None => Ok(None),
+            other       => T::from_value(other).map(Some),
+        }
+    }
+}
+
+// Tuples are arrays of exactly their arity.
+macro_rules! tuple_conversions {
+    ($len:expr; $($name:ident),+) => {
+        impl<$($name: IntoValue),+> IntoValue for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn into_value(self) -> Value {
+                let ($($name,)+) = self;
+                Value::Array(Rc::new(vec![$($name.into_value()),+]))
+            }
+        }
+        impl<$($name: FromValue),+> FromValue for ($($name,)+) {
+            fn from_value(value: Value) -> WhispemResult<Self> {
+                let items = Vec::<Value>::from_value(value)?;
+                if items.len() != $len {
+                    return Err(mismatch(
+                        &format!("array of length {}", $len),
+                        &format!("array of length {}", items.len()),
+                    ));
+                }
+                let mut it = items.into_iter();
+                Ok(($($name::from_value(it.next().unwrap())?,)+))
+            }
+        }
+    };
+}
+tuple_conversions!(1; A);
+tuple_conversions!(2; A, B);
+tuple_conversions!(3; A, B, C);
+tuple_conversions!(4; A, B, C, D);
@@ -1,3 +1,4 @@
+use crate::value::Value;
 use std::fmt;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -35,9 +36,16 @@ pub enum ErrorKind {
     UndefinedFunction(String),
     TypeError { expected: String, found: String },
     IndexOutOfBounds { index: usize, length: usize },
+    KeyNotFound(String),
     InvalidIndex,
     DivisionByZero,
-    ArgumentCount { name: String, expected: usize, got: usize },
+    IntegerOverflow(String),
+    // `max` is `None` when a rest parameter takes any number of extras.
+    ArgumentCount { name: String, min: usize, max: Option<usize>, got: usize },
+    UnknownArgument   { function: String, param: String },
+    DuplicateArgument { function: String, param: String },
+    MissingArgument   { function: String, param: String },
+    InvalidArgument   { function: String, reason: String },
     EmptyArray,
     SliceOutOfBounds { end: usize, length: usize },
     InvalidSlice { start: usize, end: usize },
@@ -45,6 +53,9 @@ pub enum ErrorKind {
     FileWrite { path: String, reason: String },
     BreakOutsideLoop,
     ContinueOutsideLoop,
+    GlobalOutsideFunction,
+    YieldOutsideFunction,
+    GlobalAfterLocal(String),
     TooManyConstants,
     StackUnderflow,
     InvalidOpcode(u8),
@@ -53,11 +64,89 @@ pub enum ErrorKind {
     AssertionFailed(String),
     Exit(i64),
     UpvalueError(String),
+    ImportError(String),
+    UnknownStruct(String),
+    UnknownField { struct_name: String, field: String },
+    MissingField { struct_name: String, field: String },
+    NonExhaustiveMatch(String),
+    GeneratorRunning(String),
+    UnpackLength { expected: usize, at_least: bool, got: usize },
+    ImportCycle(String),
+    PermissionDenied { builtin: String, resource: String },
+    StepLimitExceeded(u64),
+    StackOverflow { what: String, limit: usize },
+    MemoryLimitExceeded(usize),
+    Interrupted,
+    Thrown(Value),
 }
 
-impl fmt::Display for WhispemError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let msg = match &self.kind {
+impl ErrorKind {
+    /// The variant name, as seen by scripts in a caught error's `kind`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ErrorKind::UnexpectedCharacter(_)     => "UnexpectedCharacter",
+            ErrorKind::UnterminatedString         => "UnterminatedString",
+            ErrorKind::UnexpectedToken { .. }     => "UnexpectedToken",
+            ErrorKind::UnexpectedEof              => "UnexpectedEof",
+            ErrorKind::UndefinedVariable(_)       => "UndefinedVariable",
+            ErrorKind::UndefinedFunction(_)       => "UndefinedFunction",
+            ErrorKind::TypeError { .. }           => "TypeError",
+            ErrorKind::IndexOutOfBounds { .. }    => "IndexOutOfBounds",
+            ErrorKind::KeyNotFound(_)             => "KeyNotFound",
+            ErrorKind::InvalidIndex               => "InvalidIndex",
+            ErrorKind::DivisionByZero             => "DivisionByZero",
+            ErrorKind::IntegerOverflow(_)         => "IntegerOverflow",
+            ErrorKind::ArgumentCount { .. }       => "ArgumentCount",
+            ErrorKind::UnknownArgument { .. }     => "UnknownArgument",
+            ErrorKind::DuplicateArgument { .. }   => "DuplicateArgument",
+            ErrorKind::MissingArgument { .. }     => "MissingArgument",
+            ErrorKind::InvalidArgument { .. }     => "InvalidArgument",
+            ErrorKind::EmptyArray                 => "EmptyArray",
+            ErrorKind::SliceOutOfBounds { .. }    => "SliceOutOfBounds",
+            ErrorKind::InvalidSlice { .. }        => "InvalidSlice",
+            ErrorKind::FileRead { .. }            => "FileRead",
+            ErrorKind::FileWrite { .. }           => "FileWrite",
+            ErrorKind::BreakOutsideLoop           => "BreakOutsideLoop",
+            ErrorKind::ContinueOutsideLoop        => "ContinueOutsideLoop",
+            ErrorKind::GlobalOutsideFunction      => "GlobalOutsideFunction",
+            ErrorKind::YieldOutsideFunction       => "YieldOutsideFunction",
+            ErrorKind::GlobalAfterLocal(_)        => "GlobalAfterLocal",
+            ErrorKind::TooManyConstants           => "TooManyConstants",
+            ErrorKind::StackUnderflow             => "StackUnderflow",
+            ErrorKind::InvalidOpcode(_)           => "InvalidOpcode",
+            ErrorKind::InvalidBytecode(_)         => "InvalidBytecode",
+            ErrorKind::SerializationError(_)      => "SerializationError",
+            ErrorKind::AssertionFailed(_)         => "AssertionFailed",
+            ErrorKind::Exit(_)                    => "Exit",
+            ErrorKind::UpvalueError(_)            => "UpvalueError",
+            ErrorKind::PermissionDenied { .. }    => "PermissionDenied",
+            ErrorKind::StepLimitExceeded(_)       => "StepLimitExceeded",
+            ErrorKind::StackOverflow { .. }       => "StackOverflow",
+            ErrorKind::MemoryLimitExceeded(_)     => "MemoryLimitExceeded",
+            ErrorKind::ImportError(_)             => "ImportError",
+            ErrorKind::UnknownStruct(_)           => "UnknownStruct",
+            ErrorKind::UnknownField { .. }        => "UnknownField",
+            ErrorKind::MissingField { .. }        => "MissingField",
+            ErrorKind::NonExhaustiveMatch(_)      => "NonExhaustiveMatch",
+            ErrorKind::GeneratorRunning(_)        => "GeneratorRunning",
+            ErrorKind::UnpackLength { .. }        => "UnpackLength",
+            ErrorKind::ImportCycle(_)             => "ImportCycle",
+            ErrorKind::Interrupted                => "Interrupted",
+            ErrorKind::Thrown(_)                  => "Thrown",
+        }
+    }
+
+    /// Whether `try` / `catch` may intercept this error.  `exit()`, host
+    /// interrupts and the step / memory budgets always end the script.
+    pub fn is_catchable(&self) -> bool {
+        !matches!(self,
+            ErrorKind::Exit(_) | ErrorKind::Interrupted
+            | ErrorKind::StepLimitExceeded(_) | ErrorKind::MemoryLimitExceeded(_))
+    }
+
+    /// The error text without its location.
+    pub fn message(&self) -> String {
+        match self {
             ErrorKind::UnexpectedCharacter(c)         => format!("Unexpected character: '{}'", c),
             ErrorKind::UnterminatedString             => "Unterminated string literal".to_string(),
             ErrorKind::UnexpectedToken { expected, found } =>
@@ -69,11 +158,27 @@ impl fmt::Display for WhispemError {
                 format!("Type error: expected {}, found {}", expected, found),
             ErrorKind::IndexOutOfBounds { index, length } =>
                 format!("Array index {} out of bounds (length: {})", index, length),
-            ErrorKind::InvalidIndex                   => "Array index must be a number".to_string(),
+            ErrorKind::KeyNotFound(key)               => format!("Key \"{}\" not found in dict", key),
+            ErrorKind::InvalidIndex                   => "Array index must be a non-negative integer".to_string(),
             ErrorKind::DivisionByZero                 => "Division by zero".to_string(),
-            ErrorKind::ArgumentCount { name, expected, got } =>
+            ErrorKind::IntegerOverflow(expr)          => format!("Integer overflow: {}", expr),
+            ErrorKind::ArgumentCount { name, min, max, got } => {
+                let (range, last) = match max {
+                    Some(max) if max == min => (min.to_string(), *max),
+                    Some(max)               => (format!("{} to {}", min, max), *max),
+                    None                    => (format!("at least {}", min), *min),
+                };
                 format!("Function '{}' expected {} argument{}, got {}",
-                    name, expected, if *expected == 1 { "" } else { "s" }, got),
+                    name, range, if last == 1 { "" } else { "s" }, got)
+            }
+            ErrorKind::UnknownArgument { function, param } =>
+                format!("Function '{}' has no parameter '{}'", function, param),
+            ErrorKind::DuplicateArgument { function, param } =>
+                format!("Function '{}' got more than one value for '{}'", function, param),
+            ErrorKind::MissingArgument { function, param } =>
+                format!("Function '{}' is missing an argument for '{}'", function, param),
+            ErrorKind::InvalidArgument { function, reason } =>
+                format!("Function '{}' got an invalid argument: {}", function, reason),
             ErrorKind::EmptyArray                     => "Cannot pop from an empty array".to_string(),
             ErrorKind::SliceOutOfBounds { end, length } =>
                 format!("slice() end index {} out of bounds (length: {})", end, length),
@@ -83,6 +188,10 @@ impl fmt::Display for WhispemError {
             ErrorKind::FileWrite { path, reason }     => format!("Failed to write '{}': {}", path, reason),
             ErrorKind::BreakOutsideLoop               => "'break' used outside of a loop".to_string(),
             ErrorKind::ContinueOutsideLoop            => "'continue' used outside of a loop".to_string(),
+            ErrorKind::GlobalOutsideFunction          => "'global' used outside of a function".to_string(),
+            ErrorKind::YieldOutsideFunction           => "'yield' used outside of a function".to_string(),
+            ErrorKind::GlobalAfterLocal(n)            =>
+                format!("'{}' is already a local here and cannot be declared global", n),
             ErrorKind::TooManyConstants               => "Too many constants in one function (max 256).".to_string(),
             ErrorKind::StackUnderflow                 => "Internal error: stack underflow".to_string(),
             ErrorKind::InvalidOpcode(b)               => format!("Internal error: unknown opcode {:#04x}", b),
@@ -92,7 +201,45 @@ impl fmt::Display for WhispemError {
             ErrorKind::Exit(code)                     => format!("exit({})", code),
             ErrorKind::UpvalueError(msg)              =>
                 format!("Internal error: upvalue in invalid state: {}", msg),
-        };
+            ErrorKind::ImportError(msg)               => format!("Import error: {}", msg),
+            ErrorKind::UnknownStruct(name)            => format!("Unknown struct '{}'", name),
+            ErrorKind::UnknownField { struct_name, field } =>
+                format!("Struct '{}' has no field '{}'", struct_name, field),
+            ErrorKind::MissingField { struct_name, field } =>
+                format!("Struct '{}' is missing field '{}'", struct_name, field),
+            ErrorKind::NonExhaustiveMatch(value)      => format!("No match arm matches {}", value),
+            ErrorKind::GeneratorRunning(name)         => format!("Generator '{}' resumed while it is running", name),
+            ErrorKind::UnpackLength { expected, at_least, got } =>
+                format!("Cannot unpack {} value{} into {}{} name{}",
+                    got, if *got == 1 { "" } else { "s" },
+                    if *at_least { "at least " } else { "" }, expected, if *expected == 1 { "" } else { "s" }),
+            ErrorKind::ImportCycle(chain)             => format!("Import cycle: {}", chain),
+            ErrorKind::PermissionDenied { builtin, resource } =>
+                format!("Permission denied: '{}' may not access '{}'", builtin, resource),
+            ErrorKind::StepLimitExceeded(max)         => format!("Step limit exceeded: more than {} instructions", max),
+            ErrorKind::StackOverflow { what, limit }  => format!("Stack overflow: {} exceeded {}", what, limit),
+            ErrorKind::MemoryLimitExceeded(max)       => format!("Memory limit exceeded: more than {} bytes in use", max),
+            ErrorKind::Interrupted                    => "Interrupted".to_string(),
+            ErrorKind::Thrown(v)                      => format!("Uncaught exception: {}", thrown_message(v)),
+        }
+    }
+}
+
+/// The message of a thrown value: a thrown error dict's own `message`,
+/// or the value itself as `print` would show it.
+pub fn thrown_message(v: &Value) -> String {
+    match v {
+        Value::Dict(d) => match d.get("message") {
+            Some(Value::Str(m)) => m.clone(),
+            _                   => v.format(),
+        },
+        other => other.format(),
+    }
+}
+
+impl fmt::Display for WhispemError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = self.kind.message();
         if self.span.is_known() { write!(f, "[{}] Error: {}", self.span, msg) }
         else                    { write!(f, "Error: {}", msg) }
     }
@@ -103,4 +250,20 @@ impl WhispemError {
     pub fn runtime(kind: ErrorKind)         -> Self { Self { kind, span: Span::unknown() } }
 }
 
-pub type WhispemResult<T> = Result<T, WhispemError>;
\ No newline at end of file
+impl std::error::Error for WhispemError {}
+
+pub type WhispemResult<T> = Result<T, WhispemError>;
+
+/// A compile-time diagnostic that does not stop compilation.
+#[derive(Debug, Clone)]
+pub struct Warning {
+    pub message: String,
+    pub span:    Span,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.span.is_known() { write!(f, "[{}] Warning: {}", self.span, self.message) }
+        else                    { write!(f, "Warning: {}", self.message) }
+    }
+}
\ No newline at end of file
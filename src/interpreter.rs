@@ -0,0 +1,107 @@
+use crate::chunk::{deserialise, serialise, Chunk};
+use crate::compiler::{Compiler, Modules};
+use crate::error::{Warning, WhispemResult};
+use crate::lexer::Lexer;
+use crate::parser::Parser;
+use crate::vm::Vm;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// A compiled Whispem program: the `<main>` chunk plus every named
+/// function and lambda it references.  This is exactly what a `.whbc`
+/// file contains.
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub main:      Chunk,
+    pub functions: HashMap<String, Chunk>,
+    /// Compile-time warnings.  Not part of the `.whbc` file, so a program
+    /// read back with `from_bytes` has none.
+    pub warnings:  Vec<Warning>,
+}
+
+impl Program {
+    /// Decode a program from `.whbc` bytes.
+    pub fn from_bytes(data: &[u8]) -> WhispemResult<Self> {
+        let (main, functions) = deserialise(data)?;
+        Ok(Self { main, functions, warnings: Vec::new() })
+    }
+
+    /// Encode the program in the `.whbc` format.
+    pub fn to_bytes(&self) -> WhispemResult<Vec<u8>> {
+        serialise(&self.main, &self.functions)
+    }
+
+    /// Print a disassembly of every chunk to stdout (`--dump`).
+    pub fn disassemble(&self) {
+        self.main.disassemble();
+        let mut names: Vec<&String> = self.functions.keys().collect();
+        names.sort();
+        for n in names { println!(); self.functions[n].disassemble(); }
+    }
+}
+
+/// The embedding entry point: source → tokens → AST → bytecode → `Vm`.
+///
+/// The wrapped `Vm` persists between calls, so globals and functions
+/// defined by one `eval` are visible to the next (this is how the REPL
+/// works).
+pub struct Interpreter {
+    vm:      Vm,
+    modules: RefCell<Modules>,
+}
+
+impl Interpreter {
+    pub fn new() -> Self { Self::with_vm(Vm::new()) }
+
+    /// Wrap an already configured `Vm`.
+    pub fn with_vm(vm: Vm) -> Self { Self { vm, modules: RefCell::default() } }
+
+    pub fn vm(&self)         -> &Vm     { &self.vm }
+    pub fn vm_mut(&mut self) -> &mut Vm { &mut self.vm }
+
+    /// Lex, parse and compile `source` without running it.  `import`
+    /// paths are resolved against the current directory.
+    pub fn compile(&self, source: &str) -> WhispemResult<Program> {
+        self.compile_in(source, Path::new("."))
+    }
+
+    /// Like `compile`, resolving `import` paths against `base_dir`
+    /// (normally the directory of the file `source` was read from).
+    /// Imported modules are compiled into the returned program, and are
+    /// remembered: importing one again in a later `compile` does not
+    /// re-run its top-level code.  Imports obey the `Vm`'s
+    /// `permissions.fs_read`, like `read_file`.
+    pub fn compile_in(&self, source: &str, base_dir: &Path) -> WhispemResult<Program> {
+        let mut lexer  = Lexer::new(source);
+        let tokens     = lexer.tokenize()?;
+        let mut parser = Parser::new(tokens);
+        let program    = parser.parse_program()?;
+        let compiler   = Compiler::new()
+            .with_base_dir(base_dir)
+            .with_permissions(self.vm.permissions.clone())
+            .with_modules(self.modules.borrow().clone());
+        let (main, functions, modules, warnings) = compiler.compile_with_modules(program)?;
+        *self.modules.borrow_mut() = modules;
+        Ok(Program { main, functions, warnings })
+    }
+
+    /// Run a compiled program on the wrapped `Vm`.  Its functions are
+    /// added to those already loaded.
+    pub fn run(&mut self, program: &Program) -> WhispemResult<()> {
+        for (name, chunk) in &program.functions {
+            self.vm.functions.insert(name.clone(), chunk.clone());
+        }
+        self.vm.run(program.main.clone())
+    }
+
+    /// Compile and run `source` in one step.
+    pub fn eval(&mut self, source: &str) -> WhispemResult<()> {
+        let program = self.compile(source)?;
+        self.run(&program)
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self { Self::new() }
+}
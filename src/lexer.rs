@@ -54,14 +54,21 @@ impl Lexer {
             Some(']') => { self.advance(); Token::RightBracket }
             Some(',') => { self.advance(); Token::Comma }
             Some(':') => { self.advance(); Token::Colon }
-            Some('+') => { self.advance(); Token::Plus }
-            Some('*') => { self.advance(); Token::Star }
-            Some('/') => { self.advance(); Token::Slash }
-            Some('%') => { self.advance(); Token::Percent }
-            Some('-') => { self.advance(); Token::Minus }
+            Some('?') => { self.advance(); Token::Question }
+            Some('.') => { self.advance(); Token::Dot }
+            Some('+') => { self.advance(); self.or_equals(Token::Plus, Token::PlusEqual) }
+            Some('*') => { self.advance(); self.or_equals(Token::Star, Token::StarEqual) }
+            Some('/') => {
+                self.advance();
+                if self.cur() == Some('/') { self.advance(); Token::SlashSlash }
+                else { self.or_equals(Token::Slash, Token::SlashEqual) }
+            }
+            Some('%') => { self.advance(); self.or_equals(Token::Percent, Token::PercentEqual) }
+            Some('-') => { self.advance(); self.or_equals(Token::Minus, Token::MinusEqual) }
             Some('=') => {
                 self.advance();
-                if self.cur() == Some('=') { self.advance(); Token::EqualEqual }
+                if self.cur() == Some('=')      { self.advance(); Token::EqualEqual }
+                else if self.cur() == Some('>') { self.advance(); Token::FatArrow }
                 else { Token::Equals }
             }
             Some('!') => {
@@ -96,16 +103,26 @@ impl Lexer {
         Ok(Spanned { token, line, column: col })
     }
 
+    // `op` or, when an `=` follows, its compound assignment `op=`.
+    fn or_equals(&mut self, plain: Token, compound: Token) -> Token {
+        if self.cur() == Some('=') { self.advance(); compound } else { plain }
+    }
+
     fn read_number(&mut self) -> Token {
         let mut s   = String::new();
         let mut dot = false;
         while let Some(c) = self.cur() {
             if c.is_ascii_digit() {
                 s.push(c); self.advance();
-            } else if c == '.' && !dot && self.peek().map_or(false, |x| x.is_ascii_digit()) {
+            } else if c == '.' && !dot && self.peek().is_some_and(|x| x.is_ascii_digit()) {
                 dot = true; s.push(c); self.advance();
             } else { break; }
         }
+        // Literals without a fraction are exact integers; one too large for
+        // an i64 falls back to the nearest float.
+        if !dot {
+            if let Ok(i) = s.parse::<i64>() { return Token::Int(i); }
+        }
         Token::Number(s.parse().unwrap_or(0.0))
     }
 
@@ -130,8 +147,18 @@ impl Lexer {
             "return"     => Token::Return,
             "break"      => Token::Break,
             "continue"   => Token::Continue,
+            "try"        => Token::Try,
+            "catch"      => Token::Catch,
+            "throw"      => Token::Throw,
+            "import"     => Token::Import,
+            "as"         => Token::As,
+            "struct"     => Token::Struct,
+            "match"      => Token::Match,
+            "global"     => Token::Global,
+            "yield"      => Token::Yield,
             "true"       => Token::True,
             "false"      => Token::False,
+            "none"       => Token::None,
             "assert"     => Token::Assert,
             "type_of"    => Token::TypeOf,
             "exit"       => Token::Exit,
@@ -0,0 +1,1832 @@
+//! Whispem as a library.
+//!
+//! The CLI (`whispem`) is a thin consumer of this crate.  Hosts embed the
+//! language through [`Interpreter`]:
+//!
+//! ```
+//! let mut interp = whispem::Interpreter::new();
+//! interp.eval("let x = 2 + 3").unwrap();
+//! ```
+
+pub mod ast;
+pub mod chunk;
+pub mod compiler;
+pub mod convert;
+pub mod error;
+pub mod interpreter;
+pub mod lexer;
+pub mod limits;
+pub mod opcode;
+pub mod parser;
+pub mod permissions;
+pub mod rng;
+pub mod token;
+pub mod value;
+pub mod vm;
+
+pub use convert::{FromValue, IntoValue};
+pub use error::{ErrorKind, Span, Warning, WhispemError, WhispemResult};
+pub use interpreter::{Interpreter, Program};
+pub use limits::Limits;
+pub use permissions::Permissions;
+pub use value::{Range, Value};
+pub use vm::{InterruptHandle, Vm};
+
+
+#[cfg(test)]
+#[derive(Clone, Default)]
+struct SharedBuf(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+
+#[cfg(test)]
+impl std::io::Write for SharedBuf {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(data); Ok(data.len())
+    }
+    fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+}
+
+#[cfg(test)]
+impl SharedBuf {
+    fn lines(&self) -> Vec<String> {
+        String::from_utf8_lossy(&self.0.borrow()).lines().map(str::to_owned).collect()
+    }
+}
+
+#[cfg(test)]
+fn run_capturing(source: &str) -> Result<Vec<String>, String> {
+    run_capturing_with(source, |_| {})
+}
+
+#[cfg(test)]
+fn run_capturing_with(source: &str, setup: impl FnOnce(&mut Vm)) -> Result<Vec<String>, String> {
+    let buf = SharedBuf::default();
+    let mut vm = Vm::with_io(buf.clone(), std::io::empty());
+    setup(&mut vm);
+    Interpreter::with_vm(vm).eval(source).map_err(|e| e.to_string())?;
+    Ok(buf.lines())
+}
+
+#[cfg(test)]
+fn run_via_bytecode(source: &str) -> Result<Vec<String>, String> {
+    let buf = SharedBuf::default();
+    let mut interp = Interpreter::with_vm(Vm::with_io(buf.clone(), std::io::empty()));
+    let program    = interp.compile(source).map_err(|e| e.to_string())?;
+    let bytes      = program.to_bytes().map_err(|e| e.to_string())?;
+    let program    = Program::from_bytes(&bytes).map_err(|e| e.to_string())?;
+    interp.run(&program).map_err(|e| e.to_string())?;
+    Ok(buf.lines())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run_capturing, run_capturing_with, run_via_bytecode};
+
+    fn ok(src: &str)     -> Vec<String> { run_capturing(src).unwrap_or_else(|e| panic!("error: {}", e)) }
+    fn err_msg(src: &str) -> String     { run_capturing(src).expect_err("expected an error but succeeded") }
+    fn ok_bc(src: &str)  -> Vec<String> { run_via_bytecode(src).unwrap_or_else(|e| panic!("bytecode error: {}", e)) }
+
+    // ── Arithmetic ──────────────────────────────────────────────────────────
+    #[test] fn number_print()            { assert_eq!(ok("print 42"),          vec!["42"]); }
+    #[test] fn float_print()             { assert_eq!(ok("print 3.14"),        vec!["3.14"]); }
+    #[test] fn arithmetic_add()          { assert_eq!(ok("print 2 + 3"),       vec!["5"]); }
+    #[test] fn arithmetic_sub()          { assert_eq!(ok("print 10 - 4"),      vec!["6"]); }
+    #[test] fn arithmetic_mul()          { assert_eq!(ok("print 3 * 4"),       vec!["12"]); }
+    #[test] fn arithmetic_div()          { assert_eq!(ok("print 10 / 4"),      vec!["2.5"]); }
+    #[test] fn modulo_basic()            { assert_eq!(ok("print 10 % 3"),      vec!["1"]); }
+    #[test] fn modulo_exact()            { assert_eq!(ok("print 15 % 5"),      vec!["0"]); }
+    #[test] fn precedence_mul_over_add() { assert_eq!(ok("print 10 + 5 * 2"), vec!["20"]); }
+    #[test] fn precedence_parens()       { assert_eq!(ok("print (10+5)*2"),    vec!["30"]); }
+    #[test] fn unary_neg()               { assert_eq!(ok("let x=-7\nprint x"), vec!["-7"]); }
+    #[test] fn div_by_zero_error()       { assert!(err_msg("print 1/0").contains("Division by zero")); }
+
+    // ── Strings ─────────────────────────────────────────────────────────────
+    #[test] fn string_print()   { assert_eq!(ok("print \"hello\""),         vec!["hello"]); }
+    #[test] fn string_concat()  { assert_eq!(ok("print \"a\"+\"b\""),       vec!["ab"]); }
+    #[test] fn string_num_cat() { assert_eq!(ok("print \"n=\"+42"),         vec!["n=42"]); }
+    #[test] fn string_escape()  { assert_eq!(ok("print \"hi\\nthere\""),    vec!["hi","there"]); }
+    #[test] fn string_length()  { assert_eq!(ok("print length(\"hello\")"), vec!["5"]); }
+
+    // ── Variables ───────────────────────────────────────────────────────────
+    #[test] fn let_basic()  { assert_eq!(ok("let x=10\nprint x"),           vec!["10"]); }
+    #[test] fn let_update() { assert_eq!(ok("let x=1\nlet x=x+1\nprint x"), vec!["2"]); }
+
+    // ── Booleans & comparisons ───────────────────────────────────────────────
+    #[test] fn bool_true()   { assert_eq!(ok("print true"),  vec!["true"]); }
+    #[test] fn bool_false()  { assert_eq!(ok("print false"), vec!["false"]); }
+    #[test] fn cmp_lt()      { assert_eq!(ok("print 1<2"),   vec!["true"]); }
+    #[test] fn cmp_gt()      { assert_eq!(ok("print 2>1"),   vec!["true"]); }
+    #[test] fn cmp_eq()      { assert_eq!(ok("print 1==1"),  vec!["true"]); }
+    #[test] fn cmp_neq()     { assert_eq!(ok("print 1!=2"),  vec!["true"]); }
+    #[test] fn cmp_false()   { assert_eq!(ok("print 5<3"),   vec!["false"]); }
+
+    // ── Logic ────────────────────────────────────────────────────────────────
+    #[test] fn logic_and_ff()      { assert_eq!(ok("print true and false"), vec!["false"]); }
+    #[test] fn logic_and_tt()      { assert_eq!(ok("print true and true"),  vec!["true"]); }
+    #[test] fn logic_or_ft()       { assert_eq!(ok("print false or true"),  vec!["true"]); }
+    #[test] fn logic_not()         { assert_eq!(ok("print not true"),        vec!["false"]); }
+    #[test] fn short_circuit_and() { assert_eq!(ok("let r=false and (1==1)\nprint r"), vec!["false"]); }
+    #[test] fn short_circuit_or()  { assert_eq!(ok("let r=true or (1==1)\nprint r"),  vec!["true"]); }
+
+    // ── Conditionals ─────────────────────────────────────────────────────────
+    #[test] fn if_true()           { assert_eq!(ok("if true { print \"yes\" }"),      vec!["yes"]); }
+    #[test] fn if_false()          { assert_eq!(ok("if false { print \"yes\" }"),     Vec::<String>::new()); }
+    #[test] fn if_else_taken()     { assert_eq!(ok("if 10>5 { print \"big\" } else { print \"small\" }"), vec!["big"]); }
+    #[test] fn if_else_not_taken() { assert_eq!(ok("if 1>5  { print \"big\" } else { print \"small\" }"), vec!["small"]); }
+
+    // ── Loops ────────────────────────────────────────────────────────────────
+    #[test] fn while_basic() {
+        assert_eq!(ok("let i=0\nwhile i<3 { print i\ni=i+1 }"), vec!["0","1","2"]);
+    }
+    #[test] fn for_array()  { assert_eq!(ok("for n in [1,2,3] { print n }"),    vec!["1","2","3"]); }
+    #[test] fn for_range()  { assert_eq!(ok("for i in range(0,4) { print i }"), vec!["0","1","2","3"]); }
+    #[test] fn break_stops_loop() {
+        assert_eq!(ok("for n in range(1,10) { if n>3 { break }\nprint n }"), vec!["1","2","3"]);
+    }
+    #[test] fn continue_skips() {
+        assert_eq!(ok("for n in range(1,6) { if n==3 { continue }\nprint n }"), vec!["1","2","4","5"]);
+    }
+
+    // ── Functions ────────────────────────────────────────────────────────────
+    #[test] fn fn_basic() {
+        assert_eq!(ok("fn double(n) { return n*2 }\nprint double(7)"), vec!["14"]);
+    }
+    #[test] fn fn_void() {
+        assert_eq!(ok("fn say(x) { print x }\nsay(\"hi\")"), vec!["hi"]);
+    }
+    #[test] fn fn_recursion() {
+        assert_eq!(
+            ok("fn fact(n) { if n<=1 { return 1 }\nreturn n*fact(n-1) }\nprint fact(5)"),
+            vec!["120"]
+        );
+    }
+    #[test] fn fn_forward_call() {
+        assert_eq!(ok("print triple(3)\nfn triple(n) { return n*3 }"), vec!["9"]);
+    }
+    #[test] fn fn_reads_global() {
+        assert_eq!(
+            ok("let g=\"hi\"\nfn say(name) { print g+\" \"+name }\nsay(\"em\")"),
+            vec!["hi em"]
+        );
+    }
+    #[test] fn fn_multi_params() {
+        assert_eq!(ok("fn add(a,b) { return a+b }\nprint add(3,4)"), vec!["7"]);
+    }
+    #[test] fn fn_wrong_arity() {
+        assert!(err_msg("fn f(x) { return x }\nf(1,2)").contains("expected 1"));
+    }
+    #[test] fn fn_no_params() {
+        assert_eq!(ok("fn pi() { return 3 }\nprint pi()"), vec!["3"]);
+    }
+
+    // ── Arrays ───────────────────────────────────────────────────────────────
+    #[test] fn array_index()   { assert_eq!(ok("let a=[10,20,30]\nprint a[2]"),        vec!["30"]); }
+    #[test] fn array_assign()  { assert_eq!(ok("let a=[1,2,3]\na[1]=99\nprint a[1]"), vec!["99"]); }
+    #[test] fn array_length()  { assert_eq!(ok("print length([1,2,3,4])"),             vec!["4"]); }
+    #[test] fn array_push()    { assert_eq!(ok("let a=push([1,2],3)\nprint length(a)"),vec!["3"]); }
+    #[test] fn array_pop()     { assert_eq!(ok("print pop([1,2,3])"),                  vec!["3"]); }
+    #[test] fn array_reverse() { assert_eq!(ok("print reverse([1,2,3])"),              vec!["[3, 2, 1]"]); }
+    #[test] fn array_slice()   { assert_eq!(ok("print slice([10,20,30,40,50],1,4)"),   vec!["[20, 30, 40]"]); }
+    #[test] fn array_range()   { assert_eq!(ok("print range(0,5)"),                    vec!["[0, 1, 2, 3, 4]"]); }
+    #[test] fn array_oob()     { assert!(err_msg("let a=[1]\nprint a[5]").contains("out of bounds")); }
+    #[test] fn multiline_array_literal() {
+        assert_eq!(ok("let a = [\n  1,\n  2,\n  3\n]\nprint length(a)"), vec!["3"]);
+    }
+
+    // ── Dicts ────────────────────────────────────────────────────────────────
+    #[test] fn dict_access()      { assert_eq!(ok("let d={\"a\":1}\nprint d[\"a\"]"),               vec!["1"]); }
+    #[test] fn dict_assign()      { assert_eq!(ok("let d={\"x\":10}\nd[\"x\"]=99\nprint d[\"x\"]"), vec!["99"]); }
+    #[test] fn dict_new_key()     { assert_eq!(ok("let d={}\nd[\"k\"]=42\nprint d[\"k\"]"),          vec!["42"]); }
+    #[test] fn dict_has_key_t()   { assert_eq!(ok("print has_key({\"a\":1},\"a\")"),                 vec!["true"]); }
+    #[test] fn dict_has_key_f()   { assert_eq!(ok("print has_key({\"a\":1},\"z\")"),                 vec!["false"]); }
+    #[test] fn dict_keys_sorted() { assert_eq!(ok("print keys({\"b\":2,\"a\":1,\"c\":3})"),          vec!["[a, b, c]"]); }
+    #[test] fn dict_length()      { assert_eq!(ok("print length({\"a\":1,\"b\":2})"),                vec!["2"]); }
+    #[test] fn dict_missing_key_error() {
+        let e = err_msg("let d={\"a\":1}\nprint d[\"z\"]");
+        assert!(e.contains("\"z\" not found in dict"), "got: {}", e);
+    }
+
+    // ── Truthiness ───────────────────────────────────────────────────────────
+    #[test] fn falsy_zero()      { assert_eq!(ok("if 0 { print \"y\" } else { print \"n\" }"),    vec!["n"]); }
+    #[test] fn falsy_empty_str() { assert_eq!(ok("if \"\" { print \"y\" } else { print \"n\" }"), vec!["n"]); }
+    #[test] fn falsy_empty_arr() { assert_eq!(ok("if [] { print \"y\" } else { print \"n\" }"),   vec!["n"]); }
+    #[test] fn truthy_nonzero()  { assert_eq!(ok("if 1 { print \"y\" }"),                          vec!["y"]); }
+
+    // ── String builtins ──────────────────────────────────────────────────────
+    #[test] fn char_at_basic() {
+        assert_eq!(ok("print char_at(\"hello\", 0)"), vec!["h"]);
+        assert_eq!(ok("print char_at(\"hello\", 4)"), vec!["o"]);
+    }
+    #[test] fn substr_basic() {
+        assert_eq!(ok("print substr(\"hello world\", 6, 5)"), vec!["world"]);
+        assert_eq!(ok("print substr(\"abc\", 0, 2)"),         vec!["ab"]);
+    }
+    #[test] fn ord_basic() {
+        assert_eq!(ok("print ord(\"A\")"), vec!["65"]);
+        assert_eq!(ok("print ord(\"a\")"), vec!["97"]);
+    }
+    #[test] fn num_to_str_basic() {
+        assert_eq!(ok("print num_to_str(42)"),   vec!["42"]);
+        assert_eq!(ok("print num_to_str(3.14)"), vec!["3.14"]);
+    }
+    #[test] fn str_to_num_basic() {
+        assert_eq!(ok("print str_to_num(\"42\")"),   vec!["42"]);
+        assert_eq!(ok("print str_to_num(\"3.14\")"), vec!["3.14"]);
+    }
+
+    // ── String toolkit ────────────────────────────────────────────────────────
+    #[test] fn split_and_join() {
+        assert_eq!(ok("print split(\"a,b,,c\", \",\")"),          vec!["[a, b, , c]"]);
+        assert_eq!(ok("print split(\"héé\", \"\")"),              vec!["[h, é, é]"]);
+        assert_eq!(ok("print join([\"a\", \"b\", \"c\"], \"-\")"), vec!["a-b-c"]);
+        assert_eq!(ok("print join([1, 2.5, true], \", \")"),      vec!["1, 2.5, true"]);
+        assert_eq!(ok("print join(split(\"x y z\", \" \"), \"\")"), vec!["xyz"]);
+    }
+    #[test] fn trim_family() {
+        assert_eq!(ok("print \"[\" + trim(\"  hi \\t\\n\") + \"]\""),  vec!["[hi]"]);
+        assert_eq!(ok("print \"[\" + trim_start(\"  hi  \") + \"]\""), vec!["[hi  ]"]);
+        assert_eq!(ok("print \"[\" + trim_end(\"  hi  \") + \"]\""),   vec!["[  hi]"]);
+        assert_eq!(ok("print \"[\" + trim(\"\u{3000}hi\u{a0}\") + \"]\""), vec!["[hi]"]);
+    }
+    #[test] fn replace_and_search() {
+        assert_eq!(ok("print replace(\"a-b-c\", \"-\", \"+\")"), vec!["a+b+c"]);
+        assert_eq!(ok("print find(\"héllo héllo\", \"llo\")"),  vec!["2"]);
+        assert_eq!(ok("print rfind(\"héllo héllo\", \"llo\")"), vec!["8"]);
+        assert_eq!(ok("print find(\"abc\", \"z\")"),            vec!["-1"]);
+        assert_eq!(ok("print starts_with(\"whispem\", \"whis\")"), vec!["true"]);
+        assert_eq!(ok("print ends_with(\"whispem\", \"pem\")"),    vec!["true"]);
+        assert_eq!(ok("print contains(\"whispem\", \"isp\")"),     vec!["true"]);
+        assert_eq!(ok("print contains(\"whispem\", \"xyz\")"),     vec!["false"]);
+    }
+    #[test] fn case_repeat_pad_chr() {
+        assert_eq!(ok("print upper(\"straße été\")"), vec!["STRASSE ÉTÉ"]);
+        assert_eq!(ok("print lower(\"ÀÉÎ Abc\")"),    vec!["àéî abc"]);
+        assert_eq!(ok("print repeat(\"ab\", 3)"),     vec!["ababab"]);
+        assert_eq!(ok("print pad_left(\"7\", 3, \"0\")"), vec!["007"]);
+        assert_eq!(ok("print pad_right(\"é\", 3) + \"|\""), vec!["é  |"]);
+        assert_eq!(ok("print pad_left(\"long\", 2)"), vec!["long"]);
+        assert_eq!(ok("print chr(233) + chr(65)"),    vec!["éA"]);
+        assert_eq!(ok("print ord(chr(128512)) == 128512"), vec!["true"]);
+    }
+    #[test] fn string_toolkit_errors() {
+        assert!(err_msg("print split(1, \",\")").contains("expected string, found number"));
+        assert!(err_msg("print join(\"abc\", \",\")").contains("expected array, found string"));
+        assert!(err_msg("print replace(\"abc\", \"\", \"x\")").contains("expected non-empty string"));
+        assert!(err_msg("print repeat(\"a\", -1)").contains("expected non-negative integer, found -1"));
+        assert!(err_msg("print pad_left(\"a\", 3, \"ab\")").contains("expected single-character string"));
+        assert!(err_msg("print pad_left(\"a\")").contains("expected 2 to 3 arguments"));
+        assert!(err_msg("print chr(55296)").contains("expected Unicode code point, found 55296"));
+    }
+
+    // ── Math ──────────────────────────────────────────────────────────────────
+    #[test] fn abs_keeps_int_or_float() {
+        assert_eq!(ok("print abs(-5)\nprint abs(-2.5)\nprint type_of(abs(-5))"), vec!["5", "2.5", "number"]);
+        assert!(err_msg("print abs(-9223372036854775807 - 1)").contains("Integer overflow"));
+    }
+    #[test] fn rounding_returns_ints() {
+        assert_eq!(ok("print floor(3.7)\nprint ceil(3.2)\nprint round(-2.5)\nprint trunc(-2.7)"), vec!["3", "4", "-3", "-2"]);
+        assert_eq!(ok("let a = [10, 20, 30]\nprint a[floor(5 / 2)]"), vec!["30"]);
+    }
+    #[test] fn float_functions() {
+        assert_eq!(ok("print sqrt(16)\nprint exp(0)\nprint log(E)\nprint sin(0)"), vec!["4", "1", "1", "0"]);
+        assert_eq!(ok("print round(atan2(1, 1) * 4 * 1000)"), vec!["3142"]);
+        assert_eq!(ok("print is_nan(sqrt(-1))\nprint is_nan(1)"), vec!["true", "false"]);
+    }
+    #[test] fn pow_int_and_float() {
+        assert_eq!(ok("print pow(2, 10)\nprint pow(2, -1)\nprint pow(4, 0.5)"), vec!["1024", "0.5", "2"]);
+        assert!(err_msg("print pow(10, 19)").contains("Integer overflow: pow(10, 19)"));
+    }
+    #[test] fn min_max_over_arrays_and_args() {
+        assert_eq!(ok("print min([3, 1.5, 2])\nprint max([3, 1.5, 2])"), vec!["1.5", "3"]);
+        assert_eq!(ok("print max(4, 9, 2)\nprint min(7, -1)"), vec!["9", "-1"]);
+        assert!(err_msg("print min([])").contains("empty array"));
+        assert!(err_msg("print max([1, \"a\"])").contains("expected number, found string"));
+    }
+    #[test] fn min_max_compare_large_ints_exactly() {
+        assert_eq!(ok("print max([9007199254740993, 9007199254740992])"), vec!["9007199254740993"]);
+        assert_eq!(ok("print min(9007199254740993, 9007199254740992)"), vec!["9007199254740992"]);
+        assert_eq!(ok("print max(2, 2.5, 1)\nprint min(0.5, 1)"), vec!["2.5", "0.5"]);
+    }
+    #[test] fn pi_and_e_constants() {
+        assert_eq!(ok("print PI\nprint E"), vec!["3.141592653589793", "2.718281828459045"]);
+        assert_eq!(ok("fn area(r) { return PI * r * r }\nprint round(area(2))"), vec!["13"]);
+        assert_eq!(ok("let E = 5\nprint E"), vec!["5"]);
+    }
+    #[test] fn script_functions_override_builtins() {
+        assert_eq!(ok("fn round(x) { return \"mine\" }\nprint round(1.5)"), vec!["mine"]);
+        assert_eq!(ok("fn f() { let max = fn(a, b) { return 0 }\nreturn max(1, 2) }\nprint f()\nprint max(1, 2)"), vec!["0", "2"]);
+    }
+
+    // ── Random numbers ────────────────────────────────────────────────────────
+    #[test] fn seed_makes_random_reproducible() {
+        let draw = "print [random(), random_int(1, 6), random_int(-3, 3)]\n";
+        let out  = ok(&format!("seed(42)\n{}seed(42)\n{}", draw, draw));
+        assert_eq!(out[0], out[1]);
+        assert_eq!(ok("seed(42)\nprint random()"), vec!["0.7415648787718233"]);
+    }
+    #[test] fn random_ranges() {
+        let src = "seed(7)\nlet ok = true\nfor i in range(0, 500) {\n  let r = random()\n  let n = random_int(1, 3)\n  if r < 0 or r >= 1 or n < 1 or n > 3 { ok = false }\n}\nprint ok";
+        assert_eq!(ok(src), vec!["true"]);
+        assert_eq!(ok("print random_int(5, 5)"), vec!["5"]);
+    }
+    #[test] fn random_int_rejects_empty_range() {
+        let e = err_msg("print random_int(6, 1)");
+        assert!(e.contains("Function 'random_int' got an invalid argument: low 6 is greater than high 1"), "{}", e);
+        assert_eq!(ok("try { random_int(2, 1) } catch e { print e[\"kind\"] }"), vec!["InvalidArgument"]);
+    }
+
+    // ── Sorting ───────────────────────────────────────────────────────────────
+    #[test] fn sort_numbers_and_strings() {
+        assert_eq!(ok("print sort([3, 1.5, 2, -7, 10])"), vec!["[-7, 1.5, 2, 3, 10]"]);
+        assert_eq!(ok("print sort([\"pear\", \"Apple\", \"éclair\", \"apple\"])"), vec!["[Apple, apple, pear, éclair]"]);
+        assert_eq!(ok("print sort([1, sqrt(-1), -1])\nprint sort([])"), vec!["[-1, 1, NaN]", "[]"]);
+    }
+    #[test] fn sort_returns_a_new_array() {
+        assert_eq!(ok("let a = [2, 1]\nlet b = sort(a)\nprint a\nprint b"), vec!["[2, 1]", "[1, 2]"]);
+    }
+    #[test] fn sort_by_comparator() {
+        assert_eq!(ok("print sort_by([3, 1, 2], fn(a, b) { return b - a })"), vec!["[3, 2, 1]"]);
+        let src = "let words = [\"ccc\", \"a\", \"bb\", \"dd\", \"e\"]\nprint sort_by(words, fn(a, b) { return length(a) - length(b) })";
+        assert_eq!(ok(src), vec!["[a, e, bb, dd, ccc]"]);
+    }
+    #[test] fn sort_key_is_stable_and_calls_key_once() {
+        let src = "let calls = 0\nlet age = fn(p) {\n  calls = calls + 1\n  return p[1]\n}\n\
+                   let people = [[\"bo\", 30], [\"al\", 25], [\"cy\", 30], [\"di\", 25]]\n\
+                   print map(sort_key(people, age), fn(p) { return p[0] })\nprint calls";
+        assert_eq!(ok(src), vec!["[al, di, bo, cy]", "4"]);
+    }
+    #[test] fn sort_type_errors() {
+        assert!(err_msg("print sort([1, \"a\"])").contains("expected all numbers or all strings, found number and string"));
+        assert!(err_msg("print sort([true])").contains("expected all numbers or all strings, found bool"));
+        assert!(err_msg("print sort_key([1, 2], fn(x) { return [x] })").contains("found array"));
+        assert!(err_msg("print sort_by([1, 2], fn(a, b) { return a < b })").contains("expected number from comparator, found bool"));
+        assert!(err_msg("print sort(\"abc\")").contains("expected array, found string"));
+    }
+    #[test] fn sort_by_survives_inconsistent_comparators() {
+        assert_eq!(ok("seed(1)\nprint length(sort_by(range(0, 200), fn(a, b) { return random() - 0.5 }))"), vec!["200"]);
+        assert_eq!(ok("try { sort_by([1, 2], fn(a, b) { throw \"stop\" }) } catch e { print e[\"message\"] }"), vec!["stop"]);
+    }
+
+    // ── Generators ────────────────────────────────────────────────────────────
+    const COUNT: &str = "fn count(n) {\n  let i = 0\n  while i < n {\n    yield i\n    i = i + 1\n  }\n}\n";
+    #[test] fn generator_next_and_default() {
+        let src = format!("{}let g = count(2)\nprint type_of(g)\nprint next(g)\nprint next(g)\nprint next(g)\nprint next(g, \"done\")", COUNT);
+        assert_eq!(ok(&src), vec!["generator", "0", "1", "none", "done"]);
+    }
+    #[test] fn generator_body_runs_lazily() {
+        let src = "fn g() {\n  print \"start\"\n  yield 1\n}\nlet it = g()\nprint \"made\"\nprint next(it)";
+        assert_eq!(ok(src), vec!["made", "start", "1"]);
+    }
+    #[test] fn for_over_generator_streams() {
+        let src = format!("{}let total = 0\nfor x in count(100000) {{ total = total + x }}\nprint total", COUNT);
+        assert_eq!(ok(&src), vec!["4999950000"]);
+    }
+    #[test] fn for_over_generator_break_and_resume() {
+        let src = format!("{}let g = count(5)\nfor x in g {{\n  if x == 2 {{ break }}\n}}\nfor x in g {{ print x }}", COUNT);
+        assert_eq!(ok(&src), vec!["3", "4"]);
+    }
+    #[test] fn nested_generators() {
+        let src = format!("{}fn evens(n) {{\n  for x in count(n) {{\n    if x % 2 == 0 {{ yield x }}\n  }}\n}}\nprint map([1], fn(_) {{ return next(evens(9)) }})\nfor e in evens(7) {{ print e }}", COUNT);
+        assert_eq!(ok(&src), vec!["[0]", "0", "2", "4", "6"]);
+    }
+    #[test] fn lambda_generator() {
+        assert_eq!(ok("let g = fn(a) { yield a\n yield a * 2 }\nfor v in g(3) { print v }"), vec!["3", "6"]);
+    }
+    #[test] fn for_over_string_and_dict() {
+        assert_eq!(ok("for c in \"héy\" { print c }"), vec!["h", "é", "y"]);
+        assert_eq!(ok("for k in {\"b\": 1, \"a\": 2} { print k }"), vec!["a", "b"]);
+        assert_eq!(ok("for [a, b] in [[1, 2], [3, 4]] { print a + b }"), vec!["3", "7"]);
+    }
+    #[test] fn generator_try_across_yield() {
+        let src = "fn f() {\n  try {\n    yield 1\n    throw \"x\"\n  } catch e { yield \"caught \" + e[\"message\"] }\n}\nfor v in f() { print v }";
+        assert_eq!(ok(src), vec!["1", "caught x"]);
+    }
+    #[test] fn generator_error_finishes_it() {
+        let src = "fn f() {\n  yield 1\n  throw \"boom\"\n}\nlet g = f()\nprint next(g)\n\
+                   try { next(g) } catch e { print e[\"message\"] }\nprint next(g, \"done\")";
+        assert_eq!(ok(src), vec!["1", "boom", "done"]);
+    }
+    #[test] fn generator_errors() {
+        assert!(err_msg("yield 1").contains("'yield' used outside of a function"));
+        assert!(err_msg("for x in 5 { print x }").contains("expected array, string, dict or generator, found number"));
+        assert!(err_msg("print next([1])").contains("expected generator, found array"));
+        let src = "let g = none\nfn gen() { yield next(g) }\ng = gen()\nprint next(g)";
+        assert!(err_msg(src).contains("Generator 'gen' resumed while it is running"));
+    }
+    #[test] fn generator_via_bytecode() {
+        let src = format!("{}for x in count(3) {{ print x }}\nfor c in \"ab\" {{ print c }}", COUNT);
+        assert_eq!(ok_bc(&src), vec!["0", "1", "2", "a", "b"]);
+    }
+    #[test] fn native_overrides_builtin() {
+        let src = "print next(1)";
+        let out = run_capturing_with(src, |vm| {
+            vm.register_native("next", 1, |_, args| Ok(args[0].clone()));
+        });
+        assert_eq!(out.unwrap(), vec!["1"]);
+    }
+
+    // ── Ranges ────────────────────────────────────────────────────────────────
+    #[test] fn range_steps() {
+        assert_eq!(ok("print range(0, 10, 3)\nprint range(5, 0, -2)\nprint range(3, 3)\nprint range(0, 3, -1)"),
+                   vec!["[0, 3, 6, 9]", "[5, 3, 1]", "[]", "[]"]);
+        assert_eq!(ok("print range(0, 1, 0.25)\nprint range(1, 0, -0.5)\nprint range(0.5, 3)"),
+                   vec!["[0, 0.25, 0.5, 0.75]", "[1, 0.5]", "[0.5, 1.5, 2.5]"]);
+        assert_eq!(ok("print length(range(0, 1, 0.1))\nprint range(0, 1, 0.1)[9]"), vec!["10", "0.9"]);
+    }
+    #[test] fn range_is_lazy() {
+        assert_eq!(ok("let r = range(0, 1000000000000)\nprint length(r)\nprint r[999999999999]\nprint type_of(r)"),
+                   vec!["1000000000000", "999999999999", "array"]);
+        let limits = crate::Limits { max_heap_bytes: Some(1 << 20), ..crate::Limits::none() };
+        let out = run_capturing_with("let t = 0\nfor i in range(0, 200000) { t += i }\nprint t", |vm| vm.limits = limits);
+        assert_eq!(out.unwrap(), vec!["19999900000"]);
+    }
+    #[test] fn range_reverse_and_index() {
+        assert_eq!(ok("let r = reverse(range(0, 10, 3))\nprint r\nprint r[0]\nprint reverse(r)"),
+                   vec!["[9, 6, 3, 0]", "9", "[0, 3, 6, 9]"]);
+        assert_eq!(ok("print reverse(range(0, 0.3, 0.1))"), vec!["[0.2, 0.1, 0]"]);
+        assert!(err_msg("print range(0, 5)[5]").contains("Array index 5 out of bounds (length: 5)"));
+    }
+    #[test] fn range_slice_and_pop_are_lazy() {
+        let start = std::time::Instant::now();
+        assert_eq!(ok("let r = slice(range(0, 10000000000), 0, 3)\nprint r\nprint type_of(r)\nprint pop(range(0, 10000000000))"),
+                   vec!["[0, 1, 2]", "array", "9999999999"]);
+        assert!(start.elapsed() < std::time::Duration::from_secs(5));
+        assert_eq!(ok("print slice(range(0, 20, 2), 2, 5)\nprint slice(reverse(range(0, 10)), 1, 4)\nprint slice(range(0, 1, 0.25), 1, 3)"),
+                   vec!["[4, 6, 8]", "[8, 7, 6]", "[0.25, 0.5]"]);
+        assert_eq!(ok("let r = slice(range(10, 0, -1), 2, 8)\nprint length(r)\nprint r[0]\nprint reverse(r)\nprint slice(r, 1, 3)"),
+                   vec!["6", "8", "[3, 4, 5, 6, 7, 8]", "[7, 6]"]);
+        assert!(err_msg("print slice(range(0, 5), 2, 6)").contains("slice() end index 6 out of bounds (length: 5)"));
+        assert!(err_msg("print pop(range(3, 3))").contains("Cannot pop from an empty array"));
+    }
+    #[test] fn range_works_as_an_array() {
+        assert_eq!(ok("print map(range(0, 3), fn(x) { return x * 2 })\nprint push(range(0, 2), 9)\nprint join(range(1, 4), \"-\")"),
+                   vec!["[0, 2, 4]", "[0, 1, 9]", "1-2-3"]);
+        assert_eq!(ok("let r = range(0, 3)\nr[0] = 7\nprint r\nlet [a, ..rest] = range(0, 4)\nprint rest"),
+                   vec!["[7, 1, 2]", "[1, 2, 3]"]);
+        assert_eq!(ok("print match range(0, 2) { [x, y] => x + y\n _ => -1 }\nprint range(0, 0) ? \"y\" : \"n\""), vec!["1", "n"]);
+    }
+    #[test] fn range_errors() {
+        assert!(err_msg("print range(0, 5, 0)").contains("step must not be zero"));
+        assert!(err_msg("print range(0, 5, 0.0)").contains("step must not be zero"));
+        assert!(err_msg("print range(0, \"5\")").contains("expected number, found string"));
+        assert!(err_msg("print range(1)").contains("expected 2 to 3 arguments, got 1"));
+        let limits = crate::Limits { max_heap_bytes: Some(1 << 20), ..crate::Limits::none() };
+        let e = run_capturing_with("print push(range(0, 1000000000), 1)", |vm| vm.limits = limits).unwrap_err();
+        assert!(e.contains("Memory limit"), "got: {}", e);
+    }
+
+    // ── Error spans ──────────────────────────────────────────────────────────
+    #[test] fn error_has_span() {
+        let e = err_msg("let x=1\nprint undefined_var");
+        assert!(e.contains("line 2"), "Expected 'line 2' in: {}", e);
+    }
+
+    // ── else if ──────────────────────────────────────────────────────────────
+    #[test] fn else_if_basic() {
+        let src = "let x=2\nif x==1 { print \"one\" }\nelse if x==2 { print \"two\" }\nelse { print \"other\" }";
+        assert_eq!(ok(src), vec!["two"]);
+    }
+    #[test] fn else_if_chain_last() {
+        let src = "let x=3\nif x==1 { print \"one\" }\nelse if x==2 { print \"two\" }\nelse if x==3 { print \"three\" }\nelse { print \"other\" }";
+        assert_eq!(ok(src), vec!["three"]);
+    }
+    #[test] fn else_if_falls_to_else() {
+        let src = "let x=99\nif x==1 { print \"one\" }\nelse if x==2 { print \"two\" }\nelse { print \"other\" }";
+        assert_eq!(ok(src), vec!["other"]);
+    }
+    #[test] fn else_if_no_else() {
+        let src = "let x=5\nif x==1 { print \"one\" }\nelse if x==2 { print \"two\" }";
+        assert_eq!(ok(src), Vec::<String>::new());
+    }
+    #[test] fn else_if_fizzbuzz() {
+        let src = "\
+for n in range(1,16) {
+    if n % 15 == 0 { print \"FizzBuzz\" }
+    else if n % 3 == 0 { print \"Fizz\" }
+    else if n % 5 == 0 { print \"Buzz\" }
+    else { print n }
+}";
+        let expected: Vec<&str> = vec![
+            "1","2","Fizz","4","Buzz","Fizz","7","8","Fizz","Buzz",
+            "11","Fizz","13","14","FizzBuzz",
+        ];
+        assert_eq!(ok(src), expected);
+    }
+
+    // ── assert / type_of / exit ───────────────────────────────────────────────
+    #[test] fn assert_passes()           { assert_eq!(ok("assert(1==1,\"bad\")\nprint \"ok\""), vec!["ok"]); }
+    #[test] fn assert_passes_no_message(){ assert_eq!(ok("assert(true)\nprint \"ok\""),         vec!["ok"]); }
+    #[test] fn assert_fails_with_message(){
+        let e = err_msg("assert(1==2, \"one is not two\")");
+        assert!(e.contains("one is not two") && e.contains("Assertion failed"), "got: {}", e);
+    }
+    #[test] fn assert_fails_default()   { assert!(err_msg("assert(false)").contains("Assertion failed")); }
+    #[test] fn assert_falsy_values()    {
+        assert!(err_msg("assert(0)").contains("Assertion failed"));
+        assert!(err_msg("assert(\"\")").contains("Assertion failed"));
+        assert!(err_msg("assert([])").contains("Assertion failed"));
+    }
+    #[test] fn type_of_primitives() {
+        assert_eq!(ok("print type_of(42)"),     vec!["number"]);
+        assert_eq!(ok("print type_of(\"hi\")"), vec!["string"]);
+        assert_eq!(ok("print type_of(true)"),   vec!["bool"]);
+    }
+    #[test] fn type_of_collections() {
+        assert_eq!(ok("print type_of([1,2])"),     vec!["array"]);
+        assert_eq!(ok("print type_of({\"a\":1})"), vec!["dict"]);
+    }
+    #[test] fn type_of_none()     { assert_eq!(ok("fn f() {}\nprint type_of(f())"), vec!["none"]); }
+    #[test] fn type_of_function() { assert_eq!(ok("let f=fn(x){return x}\nprint type_of(f)"), vec!["function"]); }
+    #[test] fn exit_stops_execution() {
+        match run_capturing("print \"before\"\nexit(0)\nprint \"after\"") {
+            Ok(lines) => panic!("expected exit error, got {:?}", lines),
+            Err(e)    => assert!(e.contains("exit(0)"), "got: {}", e),
+        }
+    }
+    #[test] fn exit_with_code() {
+        match run_capturing("exit(1)") {
+            Ok(_)  => panic!("expected exit error"),
+            Err(e) => assert!(e.contains("exit(1)"), "got: {}", e),
+        }
+    }
+
+    // ── if expressions and ?: ─────────────────────────────────────────────────
+    #[test] fn if_expression_values() {
+        assert_eq!(ok("let n = 5\nlet k = if n > 3 { \"big\" } else { \"small\" }\nprint k"), vec!["big"]);
+        assert_eq!(ok("fn sign(n) { return if n < 0 { -1 } else if n == 0 { 0 } else { 1 } }\nprint [sign(-4), sign(0), sign(9)]"), vec!["[-1, 0, 1]"]);
+        assert_eq!(ok("print (if true { 10 } else { 20 }) + 1"), vec!["11"]);
+    }
+    #[test] fn if_expression_block_value_is_last_expression() {
+        let src = "let p = 10\nlet t = if true {\n    let cut = p * 2\n    cut + 1\n} else {\n    0\n}\nprint t";
+        assert_eq!(ok(src), vec!["21"]);
+        assert_eq!(ok("let x = 0\nlet v = if true { x = 5 }\nprint v\nprint x"), vec!["none", "5"]);
+        assert_eq!(ok("print if false { 1 }"), vec!["none"]);
+        assert_eq!(ok("print if true { if false { 1 } else { 2 } } else { 3 }"), vec!["2"]);
+    }
+    #[test] fn if_expression_scopes_its_branches() {
+        assert_eq!(ok("let t = 1\nlet v = if true { let t = 2\nt } else { 0 }\nprint [v, t]"), vec!["[2, 1]"]);
+    }
+    #[test] fn conditional_operator() {
+        assert_eq!(ok("let n = 7\nprint n % 2 == 0 ? \"even\" : \"odd\""), vec!["odd"]);
+        assert_eq!(ok("print true ? false ? 1 : 2 : 3\nprint false ? 1 : true ? 2 : 3"), vec!["2", "2"]);
+        assert_eq!(ok("print 1 > 2 or 3 > 2 ? \"yes\" : \"no\""), vec!["yes"]);
+        assert_eq!(ok("print {\"k\": 1 < 2 ? \"a\" : \"b\"}"), vec!["{\"k\": a}"]);
+    }
+    #[test] fn conditional_evaluates_one_side() {
+        assert_eq!(ok("fn boom() { throw \"no\" }\nprint true ? 1 : boom()\nprint false ? boom() : 2"), vec!["1", "2"]);
+    }
+    #[test] fn conditionals_in_fstrings_and_lambdas() {
+        assert_eq!(ok("let c = 1\nprint f\"{c} item{c == 1 ? \"\" : \"s\"}\""), vec!["1 item"]);
+        assert_eq!(ok("let c = 2\nprint f\"{if c > 1 { \"many\" } else { \"one\" }}\""), vec!["many"]);
+        assert_eq!(ok("print map([3, -1, 0], fn(v) { return v > 0 ? \"+\" : v < 0 ? \"-\" : \"0\" })"), vec!["[+, -, 0]"]);
+    }
+    #[test] fn conditional_errors() {
+        assert!(err_msg("print true ? 1").contains("Expected ':'"));
+        assert!(err_msg("if true { 1 }").contains("Expected statement"));
+    }
+    #[test] fn conditionals_roundtrip_through_bytecode() {
+        assert_eq!(ok_bc("let n = 3\nprint if n > 2 { \"hi\" } else { \"lo\" }\nprint n > 5 ? 1 : 0"), vec!["hi", "0"]);
+    }
+
+    // ── none ──────────────────────────────────────────────────────────────────
+    #[test] fn none_literal_prints()   { assert_eq!(ok("let x = none\nprint x"), vec!["none"]); }
+    #[test] fn none_type_of()          { assert_eq!(ok("print type_of(none)"),   vec!["none"]); }
+    #[test] fn none_equality() {
+        assert_eq!(ok("fn f() {}\nprint f() == none\nprint none != 0\nprint none == false"), vec!["true", "true", "false"]);
+    }
+    #[test] fn none_is_falsy()         { assert_eq!(ok("if none { print \"y\" } else { print \"n\" }"), vec!["n"]); }
+    #[test] fn none_explicit_return()  { assert_eq!(ok("fn f(x) { if x > 0 { return x }\nreturn none }\nprint f(0)"), vec!["none"]); }
+    #[test] fn none_in_collections() {
+        assert_eq!(ok("print [1, none]\nprint {\"a\": none}"), vec!["[1, none]", "{\"a\": none}"]);
+    }
+    #[test] fn none_in_strings() {
+        assert_eq!(ok("print \"v: \" + none\nlet x = none\nprint f\"x={x}\""), vec!["v: none", "x=none"]);
+    }
+    #[test] fn none_bytecode_roundtrip() {
+        let src = "let x = none\nprint x\nprint x == none";
+        assert_eq!(ok_bc(src), ok(src));
+    }
+
+    // ── try / catch / throw ───────────────────────────────────────────────────
+    #[test] fn try_without_error() {
+        assert_eq!(ok("try { print 1 } catch e { print \"no\" }\nprint 2"), vec!["1", "2"]);
+    }
+    #[test] fn catch_runtime_error() {
+        let src = "try {\n  print 1 / 0\n} catch err {\n  print err[\"kind\"]\n  print err[\"message\"]\n  print err[\"line\"]\n}";
+        assert_eq!(ok(src), vec!["DivisionByZero", "Division by zero", "2"]);
+    }
+    #[test] fn catch_missing_dict_key() {
+        let src = "let d = {\"a\": 1}\ntry { print d[\"b\"] } catch e { print \"missing\" }\nprint \"after\"";
+        assert_eq!(ok(src), vec!["missing", "after"]);
+        let src = "let d = {\"a\": 1}\ntry { print d[\"b\"] } catch e { print e[\"kind\"]\nprint e[\"message\"] }";
+        assert_eq!(ok(src), vec!["KeyNotFound", "Key \"b\" not found in dict"]);
+    }
+    #[test] fn catch_failed_read_file() {
+        let src = "try { let s = read_file(\"/no/such/file.txt\") } catch e { print e[\"kind\"] }";
+        assert_eq!(ok(src), vec!["FileRead"]);
+    }
+    #[test] fn throw_string() {
+        let src = "try { throw \"bad input\" } catch e { print e[\"kind\"]\nprint e[\"message\"]\nprint e[\"value\"] }";
+        assert_eq!(ok(src), vec!["Thrown", "bad input", "bad input"]);
+    }
+    #[test] fn throw_error_dict_passes_through() {
+        let src = "try { throw {\"kind\": \"Parse\", \"message\": \"oops\"} } catch e { print e[\"kind\"] + \" \" + e[\"message\"] + \" \" + e[\"line\"] }";
+        assert_eq!(ok(src), vec!["Parse oops 1"]);
+    }
+    #[test] fn rethrow_keeps_original_error() {
+        let src = "try {\n  try { print 1 / 0 } catch e { throw e }\n} catch outer { print outer[\"kind\"] }";
+        assert_eq!(ok(src), vec!["DivisionByZero"]);
+    }
+    #[test] fn uncaught_throw_is_error() {
+        let e = err_msg("throw \"boom\"");
+        assert!(e.contains("Uncaught exception: boom"), "{}", e);
+    }
+    #[test] fn catch_across_function_calls() {
+        let src = "fn inner(x) { if x > 2 { throw \"too big\" }\nreturn x }\nfn outer(x) { return inner(x) * 10 }\n\
+                   try { print outer(1)\nprint outer(5) } catch e { print e[\"message\"] }\nprint outer(2)";
+        assert_eq!(ok(src), vec!["10", "too big", "20"]);
+    }
+    #[test] fn catch_inside_function_returns_normally() {
+        let src = "fn safe_div(a, b) { try { return a / b } catch e { return none } }\nprint safe_div(6, 3)\nprint safe_div(1, 0)\nprint 1 / 1";
+        assert_eq!(ok(src), vec!["2", "none", "1"]);
+    }
+    #[test] fn return_from_try_drops_handler() {
+        let src = "fn f() { try { return 1 } catch e { print \"wrong\" } }\nlet x = f()\nprint 1 / 0";
+        assert!(err_msg(src).contains("Division by zero"));
+    }
+    #[test] fn break_out_of_try_drops_handler() {
+        let src = "for i in [1, 2, 3] { try { if i == 2 { break } } catch e { print \"wrong\" } }\nprint 1 / 0";
+        assert!(err_msg(src).contains("Division by zero"));
+        let src = "for i in [1, 2, 3] { try { if i == 2 { continue }\nprint i } catch e { print \"wrong\" } }";
+        assert_eq!(ok(src), vec!["1", "3"]);
+    }
+    #[test] fn catch_inside_callbacks() {
+        let src = "let r = map([1, 0, 2], fn(x) { try { return 6 / x } catch e { return -1 } })\nprint r";
+        assert_eq!(ok(src), vec!["[6, -1, 3]"]);
+        let src = "try { let r = map([1, 0], fn(x) { return 6 / x }) } catch e { print e[\"kind\"] }\nprint \"ok\"";
+        assert_eq!(ok(src), vec!["DivisionByZero", "ok"]);
+    }
+    #[test] fn catch_in_loop_keeps_going() {
+        let src = "let total = 0\nfor x in [1, \"a\", 3] { try { total = total + x * 2 } catch e { print e[\"kind\"] } }\nprint total";
+        assert_eq!(ok(src), vec!["TypeError", "8"]);
+    }
+    #[test] fn exit_is_not_catchable() {
+        match run_capturing("try { exit(3) } catch e { print \"caught\" }") {
+            Err(msg) => assert!(msg.contains("exit(3)")),
+            Ok(out)  => panic!("exit was caught: {:?}", out),
+        }
+    }
+    #[test] fn step_limit_is_not_catchable() {
+        let limits = crate::Limits { max_steps: Some(1_000), ..crate::Limits::none() };
+        let e = super::run_capturing_with("while true { try { while true {} } catch e { print \"caught\" } }", |vm| vm.limits = limits);
+        assert!(e.unwrap_err().contains("Step limit exceeded"));
+    }
+    #[test] fn try_catch_bytecode_roundtrip() {
+        let src = "try { throw \"x\" } catch e { print e[\"message\"] }\ntry { print 1 / 0 } catch e { print e[\"kind\"] }";
+        assert_eq!(ok_bc(src), ok(src));
+    }
+    #[test] fn break_in_nested_function_is_rejected() {
+        assert!(err_msg("for i in [1] { let f = fn() { break } }").contains("'break' used outside of a loop"));
+    }
+    #[test] fn catch_requires_name() {
+        assert!(run_capturing("try { print 1 } catch { print 2 }").is_err());
+    }
+
+    // ── Structs ───────────────────────────────────────────────────────────────
+    const POINT: &str = "struct Point { x, y }\n";
+    #[test] fn struct_positional_and_named() {
+        assert_eq!(ok(&format!("{}let p = Point(1, 2)\nlet q = Point {{ y: 4, x: 3 }}\nprint p\nprint q.x + q.y", POINT)),
+                   vec!["Point { x: 1, y: 2 }", "7"]);
+    }
+    #[test] fn struct_field_assignment() {
+        assert_eq!(ok(&format!("{}let p = Point(1, 2)\np.y = 9\nprint p.y", POINT)), vec!["9"]);
+    }
+    #[test] fn struct_is_a_value() {
+        let src = format!("{}let p = Point(1, 2)\nlet q = p\nq.x = 5\nfn bump(pt) {{\n    pt.x = pt.x + 1\n    return pt.x\n}}\nprint bump(p)\nprint p.x\nprint q.x", POINT);
+        assert_eq!(ok(&src), vec!["2", "1", "5"]);
+    }
+    #[test] fn struct_equality_compares_fields() {
+        let src = format!("{}struct Pair {{ x, y }}\nlet p = Point(1, [2])\nprint p == p\nprint p == Point {{ x: 1, y: [2] }}\nprint p == Point(1, [3])\nprint p != Pair(1, [2])", POINT);
+        assert_eq!(ok(&src), vec!["true", "true", "false", "true"]);
+    }
+    #[test] fn array_and_dict_equality_compare_contents() {
+        assert_eq!(ok("print [1, [2, \"a\"]] == [1, [2, \"a\"]]\nprint [1, 2] == [1, 2, 3]\nprint [1, 2.0] == [1, 2]"), vec!["true", "false", "true"]);
+        assert_eq!(ok("print {\"a\": [1]} == {\"a\": [1]}\nprint {\"a\": 1} != {\"b\": 1}"), vec!["true", "true"]);
+        assert_eq!(ok("print range(0, 3) == [0, 1, 2]\nprint reverse(range(0, 3)) == range(2, -1, -1)\nprint [] == {}"), vec!["true", "true", "false"]);
+    }
+    #[test] fn equality_of_deeply_nested_arrays() {
+        let src = "let a = []\nlet b = []\nlet i = 0\nwhile i < 100000 {\n    a = [a]\n    b = [b]\n    i = i + 1\n}\nprint a == b";
+        assert_eq!(ok(src), vec!["true"]);
+    }
+    #[test] fn struct_type_of() {
+        assert_eq!(ok(&format!("{}print type_of(Point(0, 0))", POINT)), vec!["Point"]);
+    }
+    #[test] fn struct_declared_after_use_and_nested() {
+        let src = "fn origin() { return Line(Point(0, 0), Point(1, 1)) }\nprint origin().to.y\nstruct Line { from, to }\nstruct Point {\n    x\n    y\n}";
+        assert_eq!(ok(src), vec!["1"]);
+    }
+    #[test] fn struct_literal_not_confused_with_block() {
+        assert_eq!(ok("let ready = true\nif ready {\n    print \"go\"\n}"), vec!["go"]);
+    }
+    #[test] fn struct_unknown_field_errors() {
+        assert!(err_msg(&format!("{}print Point {{ x: 1, z: 2 }}", POINT)).contains("Struct 'Point' has no field 'z'"));
+        assert!(err_msg(&format!("{}print Point {{ x: 1 }}", POINT)).contains("Struct 'Point' is missing field 'y'"));
+        assert!(err_msg(&format!("{}let p = Point(1, 2)\nprint p.z", POINT)).contains("Struct 'Point' has no field 'z'"));
+        assert!(err_msg(&format!("{}let p = Point(1, 2)\np.z = 1", POINT)).contains("Struct 'Point' has no field 'z'"));
+        assert!(err_msg("print Nope { a: 1 }").contains("Unknown struct 'Nope'"));
+        assert!(err_msg(&format!("{}print Point(1)", POINT)).contains("expected 2 arguments, got 1"));
+        assert!(err_msg("let d = {\"x\": 1}\nprint d.x").contains("expected struct"));
+    }
+    #[test] fn struct_unknown_field_is_catchable() {
+        let src = format!("{}try {{\n    print Point(1, 2).w\n}} catch e {{\n    print e[\"kind\"]\n}}", POINT);
+        assert_eq!(ok(&src), vec!["UnknownField"]);
+    }
+    #[test] fn struct_bytecode_roundtrip() {
+        assert_eq!(ok_bc(&format!("{}let p = Point {{ x: 1, y: 2 }}\np.x = 7\nprint p\nprint type_of(p)", POINT)),
+                   vec!["Point { x: 7, y: 2 }", "Point"]);
+    }
+    #[test] fn struct_from_module() {
+        let dir = module_dir("structs", &[("geo.wsp", POINT)]);
+        assert_eq!(run_in(&dir, "import \"geo.wsp\" as geo\nlet p = geo.Point(1, 2)\nprint p.x", false).unwrap(), vec!["1"]);
+        assert_eq!(run_in(&dir, "import \"geo.wsp\"\nprint Point { x: 3, y: 4 }", true).unwrap(), vec!["Point { x: 3, y: 4 }"]);
+    }
+
+    // ── match ─────────────────────────────────────────────────────────────────
+    #[test] fn match_literals_and_wildcard() {
+        let src = "fn name(n) {\n    return match n {\n        1 => \"one\"\n        -1 => \"minus one\"\n        \"x\" => \"ex\"\n        true => \"yes\"\n        _ => \"many\"\n    }\n}\nprint name(1)\nprint name(-1)\nprint name(\"x\")\nprint name(true)\nprint name(9)";
+        assert_eq!(ok(src), vec!["one", "minus one", "ex", "yes", "many"]);
+    }
+    #[test] fn match_binding_and_guard() {
+        let src = "fn sign(n) {\n    return match n {\n        0 => \"zero\"\n        x if x < 0 => \"neg\"\n        x => f\"pos {x}\"\n    }\n}\nprint sign(0)\nprint sign(-3)\nprint sign(4)";
+        assert_eq!(ok(src), vec!["zero", "neg", "pos 4"]);
+    }
+    #[test] fn match_type_patterns() {
+        let src = "struct P { a }\nfn kind(v) {\n    return match v {\n        n: number => n + 1\n        s: string => s + \"!\"\n        _: none => \"none\"\n        p: P => p.a\n        _ => type_of(v)\n    }\n}\nprint kind(1)\nprint kind(\"a\")\nprint kind(none)\nprint kind(P(7))\nprint kind([])";
+        assert_eq!(ok(src), vec!["2", "a!", "none", "7", "array"]);
+    }
+    #[test] fn match_array_patterns() {
+        let src = "fn f(xs) {\n    return match xs {\n        [] => \"empty\"\n        [only] => f\"one {only}\"\n        [a, [b, c]] => a + b + c\n        [first, ..rest] => f\"{first} then {rest}\"\n    }\n}\nprint f([])\nprint f([5])\nprint f([1, [2, 3]])\nprint f([1, 2, 3])";
+        assert_eq!(ok(src), vec!["empty", "one 5", "6", "1 then [2, 3]"]);
+    }
+    #[test] fn match_dict_patterns() {
+        let src = "fn area(s) {\n    return match s {\n        {\"kind\": \"square\", \"side\": n} => n * n\n        {\"kind\": k} => \"unknown \" + k\n        _ => \"not a shape\"\n    }\n}\nprint area({\"kind\": \"square\", \"side\": 3})\nprint area({\"kind\": \"blob\"})\nprint area({})\nprint area(4)";
+        assert_eq!(ok(src), vec!["9", "unknown blob", "not a shape", "not a shape"]);
+    }
+    #[test] fn match_block_arms_as_statement() {
+        let src = "for v in [1, 2] {\n    match v {\n        1 => {\n            print \"first\"\n        }\n        _ => {\n            print \"rest\"\n        }\n    }\n}";
+        assert_eq!(ok(src), vec!["first", "rest"]);
+    }
+    #[test] fn match_subject_evaluated_once() {
+        let calls = std::rc::Rc::new(std::cell::Cell::new(0));
+        let counter = calls.clone();
+        let out = run_capturing_with("print match next() { 5 => \"five\", n => n }", |vm| {
+            vm.register_native("next", 0, move |_, _| {
+                counter.set(counter.get() + 1);
+                Ok(crate::Value::Number(counter.get() as f64))
+            });
+        }).unwrap();
+        assert_eq!(out, vec!["1"]);
+        assert_eq!(calls.get(), 1);
+    }
+    #[test] fn match_non_exhaustive_error() {
+        assert!(err_msg("let x = match 3 { 1 => \"one\" }").contains("No match arm matches 3"));
+        let src = "try {\n    let x = match [1] { [] => 0 }\n} catch e {\n    print e[\"kind\"]\n}";
+        assert_eq!(ok(src), vec!["NonExhaustiveMatch"]);
+    }
+    #[test] fn match_literal_arms_compile_to_plain_jumps() {
+        let program = crate::Interpreter::new().compile("let x = match 2 { 1 => \"a\", 2 => \"b\" }").unwrap();
+        let code = &program.main.code;
+        assert!(!code.contains(&(crate::opcode::OpCode::Call as u8)));
+    }
+    #[test] fn match_bytecode_roundtrip() {
+        assert_eq!(ok_bc("print match [1, 2] { [a, ..r] => a + length(r), _ => 0 }"), vec!["2"]);
+    }
+
+    // ── Integers ──────────────────────────────────────────────────────────────
+    #[test] fn int_exact_above_2_pow_53() {
+        assert_eq!(ok("print 9007199254740992 + 1"), vec!["9007199254740993"]);
+        assert_eq!(ok("print 1000000000000000000"),  vec!["1000000000000000000"]);
+        assert_eq!(ok("print 9007199254740993 > 9007199254740992"), vec!["true"]);
+    }
+    #[test] fn int_overflow_is_an_error() {
+        assert!(err_msg("print 9223372036854775807 + 1").contains("Integer overflow: 9223372036854775807 + 1"));
+        assert!(err_msg("print 4294967296 * 4294967296").contains("Integer overflow"));
+        assert!(err_msg("let m = -9223372036854775807 - 1\nprint -m").contains("Integer overflow"));
+        assert_eq!(ok("try { print 9223372036854775807 * 2 } catch e { print e[\"kind\"] }"), vec!["IntegerOverflow"]);
+    }
+    #[test] fn int_mixed_with_float_promotes() {
+        assert_eq!(ok("print 1 + 0.5\nprint 2 * 1.5\nprint type_of(1) == type_of(1.5)"), vec!["1.5", "3", "true"]);
+        assert_eq!(ok("print 9223372036854775807 + 1.0"), vec!["9223372036854776000"]);
+    }
+    #[test] fn int_division_and_modulo() {
+        assert_eq!(ok("print 10 / 2\nprint 7 / 2\nprint 7 // 2\nprint -7 // 2\nprint -7 % 2\nprint 7.5 // 2"),
+                   vec!["5", "3.5", "3", "-3", "-1", "3"]);
+        assert!(err_msg("print 1 // 0").contains("Division by zero"));
+        assert!(err_msg("print 1 % 0").contains("Division by zero"));
+    }
+    #[test] fn int_equals_integral_float() {
+        assert_eq!(ok("print 1 == 1.0\nprint 2 != 2.5\nlet d = {}\nd[1] = \"a\"\nprint d[1.0]"), vec!["true", "true", "a"]);
+        assert_eq!(ok("print 9007199254740993 == 9007199254740992.0"), vec!["false"]);
+    }
+    #[test] fn int_indices_must_be_non_negative_integers() {
+        assert!(err_msg("let a = [1, 2]\nprint a[-1]").contains("non-negative integer"));
+        assert!(err_msg("let a = [1, 2]\nprint a[1.5]").contains("non-negative integer"));
+        assert!(err_msg("print char_at(\"ab\", -1)").contains("non-negative integer"));
+        assert_eq!(ok("let a = [1, 2]\nprint a[1.0]"), vec!["2"]);
+    }
+    #[test] fn int_builtins_return_ints() {
+        assert_eq!(ok("print str_to_num(\"12345678901234567\") + 1"), vec!["12345678901234568"]);
+        assert_eq!(ok("for i in range(0, 3) { print i / 2 }"), vec!["0", "0.5", "1"]);
+        assert_eq!(ok("print length([1, 2, 3]) // 2"), vec!["1"]);
+    }
+    #[test] fn int_constants_roundtrip_through_bytecode() {
+        let src = "print 9007199254740993\nprint 9007199254740993 * 1\nprint 0.5";
+        assert_eq!(ok_bc(src), ok(src));
+        let bytes = crate::Interpreter::new().compile(src).unwrap().to_bytes().unwrap();
+        assert!(bytes.windows(9).any(|w| w == [5, 0, 0x20, 0, 0, 0, 0, 0, 1]), "no int constant tag");
+    }
+    #[test] fn int_conversions() {
+        use crate::{FromValue, IntoValue, Value};
+        assert!(matches!(i64::MAX.into_value(), Value::Int(i64::MAX)));
+        assert_eq!(i64::from_value(Value::Int(i64::MAX)).unwrap(), i64::MAX);
+        assert_eq!(u8::from_value(Value::Number(7.0)).unwrap(), 7);
+        assert!(u8::from_value(Value::Int(300)).is_err());
+        assert_eq!(f64::from_value(Value::Int(3)).unwrap(), 3.0);
+    }
+
+    // ── Assignment ────────────────────────────────────────────────────────────
+    #[test] fn assign_nested_index() {
+        assert_eq!(ok("let grid = [[0, 0], [0, 0]]\ngrid[1][0] = 5\nprint grid"), vec!["[[0, 0], [5, 0]]"]);
+        assert_eq!(ok("let c = {\"a\": {\"b\": [1, 2]}}\nc[\"a\"][\"b\"][1] = 9\nc[\"a\"][\"n\"] = 0\nprint c"),
+                   vec![r#"{"a": {"b": [1, 9], "n": 0}}"#]);
+    }
+    #[test] fn assign_nested_is_copy_on_write() {
+        let src = "let a = [[1, 2]]\nlet b = a\nlet row = a[0]\na[0][0] = 9\nprint a\nprint b\nprint row";
+        assert_eq!(ok(src), vec!["[[9, 2]]", "[[1, 2]]", "[1, 2]"]);
+    }
+    #[test] fn assign_mixes_fields_and_indices() {
+        let src = "struct Box { items }\nlet bs = [Box([1, 2])]\nbs[0].items[1] = 5\nprint bs[0].items";
+        assert_eq!(ok(src), vec!["[1, 5]"]);
+    }
+    #[test] fn compound_assign_variables() {
+        assert_eq!(ok("let n = 10\nn += 5\nn -= 3\nn *= 2\nprint n\nn /= 4\nprint n\nn %= 4\nprint n"), vec!["24", "6", "2"]);
+        assert_eq!(ok("let s = \"a\"\ns += \"b\"\nprint s"), vec!["ab"]);
+    }
+    #[test] fn compound_assign_targets() {
+        assert_eq!(ok("let a = [[1, 2], [3, 4]]\nlet i = 1\na[i][i - 1] += 10\nprint a"), vec!["[[1, 2], [13, 4]]"]);
+        assert_eq!(ok("let d = {\"hits\": 0}\nfor w in [1, 2, 3] { d[\"hits\"] += w }\nprint d"), vec![r#"{"hits": 6}"#]);
+        assert_eq!(ok("struct C { n }\nlet c = C(1)\nc.n *= 7\nprint c.n"), vec!["7"]);
+    }
+    #[test] fn compound_assign_in_functions_and_closures() {
+        assert_eq!(ok("fn f(a) { a[0][0] += 1\nreturn a }\nlet x = [[1]]\nprint f(x)\nprint x"), vec!["[[2]]", "[[1]]"]);
+        let src = "fn counter() {\n    let c = 0\n    return fn() { c += 1\n return c }\n}\nlet k = counter()\nk()\nprint k()";
+        assert_eq!(ok(src), vec!["2"]);
+    }
+    #[test] fn assign_keys_evaluated_once() {
+        let calls = std::rc::Rc::new(std::cell::Cell::new(0));
+        let counter = calls.clone();
+        let out = run_capturing_with("let a = [[0], [0]]\na[next()][0] += 5\nprint a", |vm| {
+            vm.register_native("next", 0, move |_, _| {
+                counter.set(counter.get() + 1);
+                Ok(crate::Value::Int(counter.get()))
+            });
+        }).unwrap();
+        assert_eq!(out, vec!["[[0], [5]]"]);
+        assert_eq!(calls.get(), 1);
+    }
+    #[test] fn assign_invalid_targets() {
+        assert!(err_msg("fn f() { return [1] }\nf()[0] = 2").contains("Expected a variable, index or field"));
+        assert!(err_msg("let x = 1\nx + 1 = 2").contains("or an assignment"));
+        assert!(err_msg("let a = [1]\na[3] += 1").contains("out of bounds"));
+    }
+    #[test] fn assign_roundtrips_through_bytecode() {
+        let src = "let g = [[1, 2], [3, 4]]\nlet j = 1\ng[j][0] *= 3\ng[0][j] = 0\nprint g";
+        assert_eq!(ok_bc(src), vec!["[[1, 0], [9, 4]]"]);
+    }
+
+    // ── Destructuring ─────────────────────────────────────────────────────────
+    #[test] fn destructure_array() {
+        assert_eq!(ok("fn pair() { return [1, \"one\"] }\nlet [n, word] = pair()\nprint n\nprint word"), vec!["1", "one"]);
+        assert_eq!(ok("let [head, ..tail] = [1, 2, 3]\nprint head\nprint tail"), vec!["1", "[2, 3]"]);
+        assert_eq!(ok("let [_, second, .._] = [1, 2, 3, 4]\nprint second"), vec!["2"]);
+    }
+    #[test] fn destructure_dict_and_struct() {
+        let src = "let person = {\"name\": \"Em\", \"age\": 26}\nlet {name, age} = person\nprint name\nprint age\nlet {name: who} = person\nprint who";
+        assert_eq!(ok(src), vec!["Em", "26", "Em"]);
+        assert_eq!(ok("struct P { x, y }\nlet {y, x} = P(1, 2)\nprint x - y"), vec!["-1"]);
+    }
+    #[test] fn destructure_nested() {
+        assert_eq!(ok("let [[a, b], {\"c\": c}] = [[1, 2], {\"c\": 3}]\nprint a + b + c"), vec!["6"]);
+    }
+    #[test] fn destructure_for_loop() {
+        assert_eq!(ok("for [k, v] in [[\"a\", 1], [\"b\", 2]] { print k + v }"), vec!["a1", "b2"]);
+        assert_eq!(ok("for {id} in [{\"id\": 7}, {\"id\": 8}] { print id }"), vec!["7", "8"]);
+        assert_eq!(ok("fn total(pairs) {\n    let t = 0\n    for [_, n] in pairs { t += n }\n    return t\n}\nprint total([[1, 2], [3, 4]])"), vec!["6"]);
+    }
+    #[test] fn destructure_binds_globals_for_functions() {
+        assert_eq!(ok("fn f() { return a + b }\nlet [a, b] = [1, 2]\nprint f()"), vec!["3"]);
+    }
+    #[test] fn destructure_length_mismatch() {
+        assert!(err_msg("let [a, b] = [1, 2, 3]").contains("Cannot unpack 3 values into 2 names"));
+        assert!(err_msg("let [a, ..r] = []").contains("Cannot unpack 0 values into at least 1 name"));
+        assert_eq!(ok("try { let [a, b] = [1] } catch e { print e[\"kind\"] }"), vec!["UnpackLength"]);
+    }
+    #[test] fn destructure_missing_key_and_wrong_type() {
+        assert!(err_msg("let {name} = {\"nom\": 1}").contains("Key \"name\" not found in dict"));
+        assert_eq!(ok("try { let {name} = {} } catch e { print e[\"kind\"] }"), vec!["KeyNotFound"]);
+        assert!(err_msg("let [a] = 5").contains("expected array, found number"));
+        assert!(err_msg("struct P { x }\nlet {z} = P(1)").contains("Struct 'P' has no field 'z'"));
+    }
+    #[test] fn destructure_roundtrips_through_bytecode() {
+        let src = "let [a, ..rest] = [1, 2, 3]\nlet {k} = {\"k\": a}\nprint rest\nprint k";
+        assert_eq!(ok_bc(src), vec!["[2, 3]", "1"]);
+    }
+
+    // ── Parameters ────────────────────────────────────────────────────────────
+    #[test] fn param_defaults() {
+        let src = "fn greet(name, greeting = \"hi\") { return greeting + \", \" + name }\nprint greet(\"Em\")\nprint greet(\"Em\", \"yo\")";
+        assert_eq!(ok(src), vec!["hi, Em", "yo, Em"]);
+    }
+    #[test] fn param_default_sees_earlier_params() {
+        assert_eq!(ok("fn box(w, h = w * 2) { return [w, h] }\nprint box(3)\nprint box(3, 1)"), vec!["[3, 6]", "[3, 1]"]);
+        assert_eq!(ok("let base = 100\nfn f(x, y = base + x) { return y }\nprint f(1)"), vec!["101"]);
+    }
+    #[test] fn param_rest() {
+        let src = "fn log(level, ..parts) { print level\nprint parts }\nlog(\"info\", 1, 2)\nlog(\"warn\")";
+        assert_eq!(ok(src), vec!["info", "[1, 2]", "warn", "[]"]);
+    }
+    #[test] fn named_arguments() {
+        let src = "fn greet(name, greeting = \"hi\") { return greeting + \", \" + name }\nprint greet(greeting: \"hey\", name: \"Em\")\nprint greet(\"Em\", greeting: \"yo\")";
+        assert_eq!(ok(src), vec!["hey, Em", "yo, Em"]);
+        assert_eq!(ok("fn f(a, b = 2, c = 3) { return [a, b, c] }\nprint f(1, c: 9)"), vec!["[1, 2, 9]"]);
+        assert_eq!(ok("let f = fn(x, y = 10) { return x - y }\nprint f(y: 1, x: 5)"), vec!["4"]);
+        assert_eq!(ok("struct P { x, y }\nprint P(1, y: 2)"), vec!["P { x: 1, y: 2 }"]);
+    }
+    #[test] fn defaults_in_callbacks() {
+        assert_eq!(ok("print map([1, 2], fn(x, step = 10) { return x + step })"), vec!["[11, 12]"]);
+    }
+    #[test] fn argument_count_describes_ranges() {
+        let greet = "fn greet(name, greeting = \"hi\") { return name }\n";
+        assert!(err_msg(&format!("{}greet()", greet)).contains("Function 'greet' expected 1 to 2 arguments, got 0"));
+        assert!(err_msg("fn log(level, ..parts) { }\nlog()").contains("Function 'log' expected at least 1 argument, got 0"));
+        assert!(err_msg("fn f(a) { }\nf(1, 2)").contains("Function 'f' expected 1 argument, got 2"));
+        assert!(err_msg("assert(true, 1, 2)").contains("Function 'assert' expected 1 to 2 arguments, got 3"));
+    }
+    #[test] fn named_argument_errors() {
+        let greet = "fn greet(name, greeting = \"hi\") { return name }\n";
+        assert!(err_msg(&format!("{}greet(\"a\", nme: 1)", greet)).contains("Function 'greet' has no parameter 'nme'"));
+        assert!(err_msg(&format!("{}greet(\"a\", name: 1)", greet)).contains("Function 'greet' got more than one value for 'name'"));
+        assert!(err_msg(&format!("{}greet(greeting: \"yo\")", greet)).contains("Function 'greet' is missing an argument for 'name'"));
+        assert!(err_msg("let n = length(x: [1])").contains("Function 'length' has no parameter 'x'"));
+        assert!(err_msg("fn f(a) { }\nf(a: 1, 2)").contains("positional argument after a named one"));
+        assert!(err_msg("fn f(a = 1, b) { }").contains("a default for 'b'"));
+    }
+    #[test] fn params_roundtrip_through_bytecode() {
+        let src = "fn f(a, b = a + 1, ..more) { return [a, b, more] }\nprint f(1)\nprint f(b: 5, a: 0)\nprint f(1, 2, 3)";
+        assert_eq!(ok_bc(src), vec!["[1, 2, []]", "[0, 5, []]", "[1, 2, [3]]"]);
+    }
+
+    // ── Block scoping ─────────────────────────────────────────────────────────
+    #[test] fn block_let_shadows_outer() {
+        assert_eq!(ok("let x = 1\nif true { let x = 2\nprint x }\nprint x"), vec!["2", "1"]);
+        assert_eq!(ok("fn f() { let x = 1\nwhile x < 2 { let x = 5\nprint x\nbreak }\nreturn x }\nprint f()"), vec!["5", "1"]);
+        assert_eq!(ok("let x = 1\nif true { let x = x + 1\nif true { let x = x * 10\nprint x }\nprint x }"), vec!["20", "2"]);
+    }
+    #[test] fn block_names_are_dropped() {
+        assert!(err_msg("if true { let y = 1 }\nprint y").contains("Undefined variable: 'y'"));
+        assert!(err_msg("for i in [1, 2] { }\nprint i").contains("Undefined variable: 'i'"));
+        assert!(err_msg("fn f() { for i in [1] { let t = i }\nreturn t }\nf()").contains("Undefined variable: 't'"));
+        assert!(err_msg("try { let t = 1\nthrow \"x\" } catch e { }\nprint t").contains("Undefined variable: 't'"));
+        assert!(err_msg("try { throw \"x\" } catch e { }\nprint e").contains("Undefined variable: 'e'"));
+        assert!(err_msg("match 1 { 1 => { let m = 2 } }\nprint m").contains("Undefined variable: 'm'"));
+    }
+    #[test] fn assignment_updates_outer_binding() {
+        assert_eq!(ok("let n = 0\nfor i in range(0, 4) { n = n + i }\nprint n"), vec!["6"]);
+        assert_eq!(ok("fn f() { let n = 0\nif true { if true { n += 5 } }\nreturn n }\nprint f()"), vec!["5"]);
+        assert_eq!(ok("let x = 1\nif true { let x = 2\nx = 3\nprint x }\nprint x"), vec!["3", "1"]);
+    }
+    #[test] fn loop_closures_capture_each_iteration() {
+        let src = "let fs = []\nfor i in range(0, 3) { fs = push(fs, fn() { return i }) }\nprint map(fs, fn(f) { return f() })";
+        assert_eq!(ok(src), vec!["[0, 1, 2]"]);
+        let src = "fn make() { let fs = []\nlet n = 0\nwhile n < 3 { let k = n * 10\nfs = push(fs, fn() { return k })\nn += 1 }\nreturn fs }\nprint map(make(), fn(f) { return f() })";
+        assert_eq!(ok(src), vec!["[0, 10, 20]"]);
+        let src = "let fs = []\nfor i in range(0, 4) { if i % 2 == 0 { continue }\nlet j = i\nfs = push(fs, fn() { return j }) }\nprint map(fs, fn(f) { return f() })";
+        assert_eq!(ok(src), vec!["[1, 3]"]);
+    }
+    #[test] fn loop_closures_keep_their_own_state() {
+        let src = "let cs = []\nfor i in range(0, 2) { let n = i * 100\ncs = push(cs, fn() { n += 1\nreturn n }) }\nprint cs[0]()\nprint cs[0]()\nprint cs[1]()";
+        assert_eq!(ok(src), vec!["1", "2", "101"]);
+    }
+    #[test] fn block_let_reading_outer_name_warns() {
+        let w = warnings("let i = 0\nwhile i < 3 {\n    print i\n    let i = i + 1\n}");
+        assert_eq!(w, vec!["[line 4, col 0] Warning: 'let i' in a block declares a new 'i' that shadows the outer 'i' it reads; \
+                            use 'i = ...' to update the outer variable"]);
+        assert_eq!(warnings("fn f(n) { if n > 0 { let n = n * 2\nreturn n } }").len(), 1);
+        assert_eq!(warnings("for x in [1] { let y = 0\nif true { let y = [y, x] } }").len(), 1);
+        assert_eq!(warnings("let t = 0\nfn f() { print t\nif true { let t = t + 1 } }").len(), 1);
+        assert!(warnings("let i = 0\nwhile i < 3 { let j = i + 1\ni = j }").is_empty());
+        assert!(warnings("if true { let k = 1\nlet k = k + 1 }").is_empty());
+        assert!(warnings("let x = 1\nif true { let x = 2 }\nfn f(x) { let x = x + 1 }").is_empty());
+    }
+    #[test] fn block_scoping_roundtrips_through_bytecode() {
+        let src = "let x = 1\nlet fs = []\nfor i in range(0, 2) { let x = i\nfs = push(fs, fn() { return x }) }\nprint x\nprint fs[1]()";
+        assert_eq!(ok_bc(src), vec!["1", "1"]);
+    }
+
+    // ── Globals ───────────────────────────────────────────────────────────────
+    fn warnings(src: &str) -> Vec<String> {
+        crate::Interpreter::new().compile(src).unwrap().warnings.iter().map(|w| w.to_string()).collect()
+    }
+    #[test] fn global_declaration_writes_the_global() {
+        let src = "let counter = 0\nfn inc() { global counter\nlet counter = counter + 1 }\ninc()\ninc()\nprint counter";
+        assert_eq!(ok(src), vec!["2"]);
+        assert_eq!(ok("fn setup() { global config\nconfig = {\"debug\": true} }\nsetup()\nprint config"), vec!["{\"debug\": true}"]);
+        assert_eq!(ok("let a = 1\nlet b = 2\nfn swap() { global a, b\nlet [x, y] = [b, a]\na = x\nb = y }\nswap()\nprint [a, b]"), vec!["[2, 1]"]);
+    }
+    #[test] fn assignment_in_function_updates_global() {
+        assert_eq!(ok("let n = 1\nfn bump() { n = n + 1\nn *= 10 }\nbump()\nprint n"), vec!["20"]);
+        assert_eq!(ok("let n = 1\nfn f() { if true { n += 1 } }\nf()\nprint n"), vec!["2"]);
+    }
+    #[test] fn let_in_function_stays_local() {
+        assert_eq!(ok("let n = 1\nfn f() { let n = n + 5\nreturn n }\nprint f()\nprint n"), vec!["6", "1"]);
+    }
+    #[test] fn shadowing_a_read_global_warns() {
+        let w = warnings("let total = 0\nfn add(x) {\nlet total = total + x\n}");
+        assert_eq!(w.len(), 1);
+        assert!(w[0].contains("[line 3, col 0] Warning: 'let total' declares a local that shadows the global 'total'"), "{}", w[0]);
+        assert!(warnings("let total = 0\nfn f() { print total\nif true { let total = 1 } }").len() == 1);
+        assert!(warnings("let total = 0\nfn f() { let total = 1\nprint total }").is_empty());
+        assert!(warnings("let total = 0\nfn f() { global total\nlet total = total + 1 }").is_empty());
+        assert!(warnings("let total = 0\nfn f() { total = total + 1 }").is_empty());
+    }
+    #[test] fn global_errors() {
+        assert!(err_msg("global x").contains("'global' used outside of a function"));
+        assert!(err_msg("fn f(x) { global x }").contains("'x' is already a local here and cannot be declared global"));
+        assert!(err_msg("fn f() { let y = 1\nglobal y }").contains("'y' is already a local here"));
+        assert!(err_msg("fn f() { global }").contains("Expected identifier"));
+    }
+    #[test] fn globals_roundtrip_through_bytecode() {
+        assert_eq!(ok_bc("let n = 1\nfn f() { global n\nlet n = n + 1\nn += 1 }\nf()\nprint n"), vec!["3"]);
+    }
+
+    // ── Integration programs ──────────────────────────────────────────────────
+    #[test] fn fizzbuzz_1_to_15() {
+        let src = "\
+for n in range(1,16) {
+  if n%15==0 { print \"FizzBuzz\" } else {
+  if n%3==0  { print \"Fizz\"     } else {
+  if n%5==0  { print \"Buzz\"     } else { print n } } } }";
+        let expected: Vec<&str> = vec![
+            "1","2","Fizz","4","Buzz","Fizz","7","8","Fizz","Buzz",
+            "11","Fizz","13","14","FizzBuzz",
+        ];
+        assert_eq!(ok(src), expected);
+    }
+    #[test] fn word_counter() {
+        let src = "\
+fn count(words) {
+  let c={}
+  for w in words {
+    if has_key(c,w) { c[w]=c[w]+1 } else { c[w]=1 }
+  }
+  return c
+}
+let r=count([\"a\",\"b\",\"a\",\"a\"])
+print r[\"a\"]
+print r[\"b\"]";
+        assert_eq!(ok(src), vec!["3","1"]);
+    }
+    #[test] fn fibonacci() {
+        let src = "fn fib(n) { if n<=1 { return n }\nreturn fib(n-1)+fib(n-2) }\nprint fib(10)";
+        assert_eq!(ok(src), vec!["55"]);
+    }
+
+    // ── Bytecode roundtrips ───────────────────────────────────────────────────
+    #[test] fn bytecode_roundtrip_hello()      { assert_eq!(ok_bc("print \"hello\""),          vec!["hello"]); }
+    #[test] fn bytecode_roundtrip_arithmetic() { assert_eq!(ok_bc("print 2+3"),                vec!["5"]); }
+    #[test] fn bytecode_roundtrip_variable()   { assert_eq!(ok_bc("let x=42\nprint x"),        vec!["42"]); }
+    #[test] fn bytecode_roundtrip_function()   { assert_eq!(ok_bc("fn double(n){ return n*2 }\nprint double(7)"), vec!["14"]); }
+    #[test] fn bytecode_roundtrip_loop()       { assert_eq!(ok_bc("for i in range(0,3){ print i }"), vec!["0","1","2"]); }
+    #[test] fn bytecode_roundtrip_array()      { assert_eq!(ok_bc("print reverse([1,2,3])"),   vec!["[3, 2, 1]"]); }
+    #[test] fn bytecode_roundtrip_dict()       { assert_eq!(ok_bc("let d={\"a\":1}\nprint d[\"a\"]"), vec!["1"]); }
+    #[test] fn bytecode_roundtrip_global_in_fn() {
+        assert_eq!(
+            ok_bc("let g=\"world\"\nfn greet(){ print \"hello \"+g }\ngreet()"),
+            vec!["hello world"]
+        );
+    }
+    #[test] fn bytecode_roundtrip_fizzbuzz() {
+        let src = "\
+for n in range(1,16) {
+  if n%15==0 { print \"FizzBuzz\" } else {
+  if n%3==0  { print \"Fizz\"     } else {
+  if n%5==0  { print \"Buzz\"     } else { print n } } } }";
+        let expected: Vec<&str> = vec![
+            "1","2","Fizz","4","Buzz","Fizz","7","8","Fizz","Buzz",
+            "11","Fizz","13","14","FizzBuzz",
+        ];
+        assert_eq!(ok_bc(src), expected);
+    }
+    #[test] fn bytecode_magic_bad() {
+        use crate::chunk::deserialise;
+        assert!(deserialise(b"BADC\x04\x00\x01").is_err());
+    }
+    #[test] fn bytecode_version_bad() {
+        use crate::chunk::deserialise;
+        let mut bad = b"WHBC\x03\x00\x01".to_vec();
+        bad.extend_from_slice(&[0u8; 20]);
+        assert!(deserialise(&bad).is_err());
+    }
+    #[test] fn bytecode_truncated() {
+        use crate::chunk::deserialise;
+        assert!(deserialise(b"WHBC").is_err());
+    }
+
+    // ── F-strings ─────────────────────────────────────────────────────────────
+    #[test] fn fstr_literal_only()    { assert_eq!(ok("print f\"hello\""),                           vec!["hello"]); }
+    #[test] fn fstr_single_expr()     { assert_eq!(ok("let name=\"Em\"\nprint f\"Hello, {name}!\""), vec!["Hello, Em!"]); }
+    #[test] fn fstr_number_expr()     { assert_eq!(ok("let x=42\nprint f\"x = {x}\""),              vec!["x = 42"]); }
+    #[test] fn fstr_arithmetic_expr() { assert_eq!(ok("let a=3\nlet b=4\nprint f\"{a+b}\""),         vec!["7"]); }
+    #[test] fn fstr_multiple_parts()  {
+        assert_eq!(ok("let a=\"foo\"\nlet b=\"bar\"\nprint f\"{a} and {b}\""), vec!["foo and bar"]);
+    }
+    #[test] fn fstr_empty()          { assert_eq!(ok("print f\"\""),           vec![""]); }
+    #[test] fn fstr_no_interpolation(){ assert_eq!(ok("print f\"just text\""), vec!["just text"]); }
+    #[test] fn fstr_call_in_expr()   { assert_eq!(ok("print f\"{length([1,2,3])} items\""), vec!["3 items"]); }
+    #[test] fn fstr_bytecode_roundtrip() {
+        assert_eq!(ok_bc("let name=\"world\"\nprint f\"hello {name}\""), vec!["hello world"]);
+    }
+
+    // ── Lambdas ───────────────────────────────────────────────────────────────
+    #[test] fn lambda_immediate_call()  { assert_eq!(ok("print fn(x) { return x * 2 }(7)"), vec!["14"]); }
+    #[test] fn lambda_stored_in_var()   { assert_eq!(ok("let f=fn(x){ return x+1 }\nprint f(10)"), vec!["11"]); }
+    #[test] fn lambda_as_argument() {
+        let src = "fn apply(f, x) { return f(x) }\nprint apply(fn(n) { return n*n }, 5)";
+        assert_eq!(ok(src), vec!["25"]);
+    }
+    #[test] fn lambda_stored_in_array() {
+        let src = "let fns = [fn(x) { return x + 1 }, fn(x) { return x * 2 }]\nprint fns[0](10)\nprint fns[1](10)";
+        assert_eq!(ok(src), vec!["11","20"]);
+    }
+    #[test] fn lambda_returned_from_fn() {
+        let src = "fn make_double() { return fn(x) { return x*2 } }\nprint make_double()(7)";
+        assert_eq!(ok(src), vec!["14"]);
+    }
+    #[test] fn lambda_type_of() {
+        assert_eq!(ok("let f=fn(x){return x}\nprint type_of(f)"), vec!["function"]);
+    }
+
+    // ── Closures ──────────────────────────────────────────────────────────────
+    #[test] fn closure_basic() {
+        let src = "fn make_adder(n) { return fn(x) { return x + n } }\nlet add5 = make_adder(5)\nprint add5(3)";
+        assert_eq!(ok(src), vec!["8"]);
+    }
+    #[test] fn closure_counter() {
+        let src = "\
+fn make_counter() {
+    let count = 0
+    return fn() { let count = count + 1\n return count }
+}
+let c = make_counter()
+print c()
+print c()
+print c()";
+        assert_eq!(ok(src), vec!["1","2","3"]);
+    }
+    #[test] fn closure_two_closures_share_state() {
+        let src = "\
+fn make_pair() {
+    let n = 0
+    let inc = fn() { let n = n + 1 }
+    let get = fn() { return n }
+    return [inc, get]
+}
+let p = make_pair()
+p[0]()
+p[0]()
+print p[1]()";
+        assert_eq!(ok(src), vec!["2"]);
+    }
+    #[test] fn closure_nested() {
+        let src = "\
+fn outer(a) {
+    return fn(b) { return fn(c) { return a + b + c } }
+}
+print outer(1)(2)(3)";
+        assert_eq!(ok(src), vec!["6"]);
+    }
+    #[test] fn closure_multiple_independent() {
+        let src = "\
+fn make_adder(n) { return fn(x) { return x + n } }
+let add1  = make_adder(1)
+let add10 = make_adder(10)
+print add1(5)
+print add10(5)";
+        assert_eq!(ok(src), vec!["6","15"]);
+    }
+
+    // ── v6: map ───────────────────────────────────────────────────────────────
+    #[test] fn map_doubles_array() {
+        let src = "print map([1, 2, 3, 4], fn(x) { return x * 2 })";
+        assert_eq!(ok(src), vec!["[2, 4, 6, 8]"]);
+    }
+    #[test] fn map_empty_array() {
+        let src = "print map([], fn(x) { return x })";
+        assert_eq!(ok(src), vec!["[]"]);
+    }
+    #[test] fn map_with_closure() {
+        let src = "\
+fn make_multiplier(n) { return fn(x) { return x * n } }
+let triple = make_multiplier(3)
+print map([1, 2, 3], triple)";
+        assert_eq!(ok(src), vec!["[3, 6, 9]"]);
+    }
+    #[test] fn map_strings() {
+        let src = "print map([\"a\", \"b\", \"c\"], fn(s) { return s + \"!\" })";
+        assert_eq!(ok(src), vec!["[a!, b!, c!]"]);
+    }
+    #[test] fn map_type_error() {
+        assert!(err_msg("map(42, fn(x) { return x })").contains("Type error"));
+    }
+    #[test] fn map_arity_error() {
+        assert!(err_msg("map([1,2])").contains("expected 2"));
+    }
+    #[test] fn map_bytecode_roundtrip() {
+        assert_eq!(ok_bc("print map([1,2,3], fn(x){return x+10})"), vec!["[11, 12, 13]"]);
+    }
+
+    // ── v6: filter ────────────────────────────────────────────────────────────
+    #[test] fn filter_evens() {
+        let src = "print filter([1, 2, 3, 4, 5, 6], fn(n) { return n % 2 == 0 })";
+        assert_eq!(ok(src), vec!["[2, 4, 6]"]);
+    }
+    #[test] fn filter_empty_array() {
+        let src = "print filter([], fn(x) { return true })";
+        assert_eq!(ok(src), vec!["[]"]);
+    }
+    #[test] fn filter_none_pass() {
+        let src = "print filter([1, 2, 3], fn(n) { return n > 10 })";
+        assert_eq!(ok(src), vec!["[]"]);
+    }
+    #[test] fn filter_all_pass() {
+        let src = "print filter([1, 2, 3], fn(n) { return n > 0 })";
+        assert_eq!(ok(src), vec!["[1, 2, 3]"]);
+    }
+    #[test] fn filter_with_closure() {
+        let src = "\
+fn make_gt(threshold) { return fn(n) { return n > threshold } }
+print filter([1, 5, 3, 8, 2, 7], make_gt(4))";
+        assert_eq!(ok(src), vec!["[5, 8, 7]"]);
+    }
+    #[test] fn filter_type_error() {
+        assert!(err_msg("filter(\"hello\", fn(x) { return true })").contains("Type error"));
+    }
+    #[test] fn filter_bytecode_roundtrip() {
+        assert_eq!(ok_bc("print filter([1,2,3,4,5], fn(n){return n%2==1})"), vec!["[1, 3, 5]"]);
+    }
+
+    // ── v6: reduce ────────────────────────────────────────────────────────────
+    #[test] fn reduce_sum() {
+        let src = "print reduce([1, 2, 3, 4, 5], fn(acc, n) { return acc + n }, 0)";
+        assert_eq!(ok(src), vec!["15"]);
+    }
+    #[test] fn reduce_product() {
+        let src = "print reduce([1, 2, 3, 4], fn(acc, n) { return acc * n }, 1)";
+        assert_eq!(ok(src), vec!["24"]);
+    }
+    #[test] fn reduce_empty_returns_initial() {
+        let src = "print reduce([], fn(acc, n) { return acc + n }, 42)";
+        assert_eq!(ok(src), vec!["42"]);
+    }
+    #[test] fn reduce_string_concat() {
+        let src = "print reduce([\"b\", \"c\", \"d\"], fn(acc, s) { return acc + s }, \"a\")";
+        assert_eq!(ok(src), vec!["abcd"]);
+    }
+    #[test] fn reduce_max() {
+        let src = "print reduce([3, 1, 4, 1, 5, 9, 2], fn(acc, n) { if n > acc { return n } return acc }, 0)";
+        assert_eq!(ok(src), vec!["9"]);
+    }
+    #[test] fn reduce_type_error() {
+        assert!(err_msg("reduce(99, fn(a,b){return a+b}, 0)").contains("Type error"));
+    }
+    #[test] fn reduce_arity_error() {
+        assert!(err_msg("reduce([1,2,3], fn(a,b){return a+b})").contains("expected 3"));
+    }
+    #[test] fn reduce_bytecode_roundtrip() {
+        assert_eq!(ok_bc("print reduce([1,2,3,4,5], fn(a,n){return a+n}, 0)"), vec!["15"]);
+    }
+
+    // ── v6: map/filter/reduce composed ───────────────────────────────────────
+    #[test] fn map_filter_reduce_pipeline() {
+        // Sum of squares of even numbers in [1..10]
+        let src = "\
+let nums   = range(1, 11)
+let evens  = filter(nums, fn(n) { return n % 2 == 0 })
+let squares = map(evens, fn(n) { return n * n })
+let total  = reduce(squares, fn(acc, n) { return acc + n }, 0)
+print total";
+        // evens: 2,4,6,8,10  →  squares: 4,16,36,64,100  →  sum: 220
+        assert_eq!(ok(src), vec!["220"]);
+    }
+    #[test] fn map_filter_reduce_pipeline_bytecode() {
+        let src = "\
+let nums    = range(1, 11)
+let evens   = filter(nums, fn(n) { return n % 2 == 0 })
+let squares = map(evens, fn(n) { return n * n })
+let total   = reduce(squares, fn(acc, n) { return acc + n }, 0)
+print total";
+        assert_eq!(ok_bc(src), vec!["220"]);
+    }
+
+    // ── Embedding API ─────────────────────────────────────────────────────────
+    #[test] fn interpreter_keeps_globals_between_evals() {
+        let buf = super::SharedBuf::default();
+        let mut interp = crate::Interpreter::with_vm(crate::Vm::with_io(buf.clone(), std::io::empty()));
+        interp.eval("let x = 40\nfn inc(n) { return n + 1 }").unwrap();
+        interp.eval("print inc(x) + 1").unwrap();
+        assert_eq!(buf.lines(), vec!["42"]);
+    }
+    #[test] fn interpreter_compile_reports_parse_errors() {
+        let e = crate::Interpreter::new().compile("let = 1").expect_err("expected a parse error");
+        assert!(matches!(e.kind, crate::ErrorKind::UnexpectedToken { .. }), "got: {}", e);
+    }
+    #[test] fn program_bytes_roundtrip() {
+        let interp  = crate::Interpreter::new();
+        let program = interp.compile("fn f(x) { return x }\nprint f(1)").unwrap();
+        let decoded = crate::Program::from_bytes(&program.to_bytes().unwrap()).unwrap();
+        assert_eq!(decoded.main.code, program.main.code);
+        assert_eq!(decoded.functions.len(), program.functions.len());
+    }
+
+    // ── Native functions ──────────────────────────────────────────────────────
+    fn with_natives(vm: &mut crate::Vm) {
+        use crate::{ErrorKind, Value, WhispemError};
+        vm.register_native("twice", 1, |_, args| match args[0].as_f64() {
+            Some(n) => Ok(Value::Number(n * 2.0)),
+            None    => Err(WhispemError::runtime(ErrorKind::TypeError {
+                expected: "number".into(), found: args[0].type_name().into(),
+            })),
+        });
+        vm.register_native("join2", 2, |_, args| Ok(Value::Str(format!("{}-{}", args[0], args[1]))));
+    }
+    fn ok_native(src: &str) -> Vec<String> {
+        run_capturing_with(src, with_natives).unwrap_or_else(|e| panic!("error: {}", e))
+    }
+    fn err_native(src: &str) -> String {
+        run_capturing_with(src, with_natives).expect_err("expected an error but succeeded")
+    }
+    #[test] fn native_call_by_name()    { assert_eq!(ok_native("print twice(21)"),                 vec!["42"]); }
+    #[test] fn native_call_statement()  { assert_eq!(ok_native("twice(1)\nprint \"ok\""),          vec!["ok"]); }
+    #[test] fn native_in_function()     { assert_eq!(ok_native("fn f() { return twice(4) }\nprint f()"), vec!["8"]); }
+    #[test] fn native_as_value()        { assert_eq!(ok_native("print map([1,2], twice)"),          vec!["[2, 4]"]); }
+    #[test] fn native_stored_in_var()   { assert_eq!(ok_native("let f = join2\nprint f(\"a\", 1)"),  vec!["a-1"]); }
+    #[test] fn native_type_of()         { assert_eq!(ok_native("print type_of(twice)"),             vec!["function"]); }
+    #[test] fn native_arity_error() {
+        let e = err_native("join2(1)");
+        assert!(e.contains("'join2' expected 2 arguments, got 1"), "got: {}", e);
+    }
+    #[test] fn native_error_gets_line() {
+        let e = err_native("let x = 1\ntwice(\"no\")");
+        assert!(e.contains("line 2") && e.contains("Type error"), "got: {}", e);
+    }
+    #[test] fn native_shadowed_by_script_fn() {
+        assert_eq!(ok_native("fn twice(n) { return n }\nprint twice(5)"), vec!["5"]);
+    }
+    #[test] fn native_unknown_without_registration() {
+        assert!(err_msg("twice(1)").contains("Undefined function"));
+    }
+
+    // ── Calling into scripts from Rust ────────────────────────────────────────
+    fn loaded(src: &str) -> crate::Interpreter {
+        let mut interp = crate::Interpreter::new();
+        interp.eval(src).unwrap_or_else(|e| panic!("error: {}", e));
+        interp
+    }
+    #[test] fn vm_call_named_function() {
+        use crate::Value;
+        let mut interp = loaded("fn add(a, b) { return a + b }");
+        let r = interp.vm_mut().call("add", vec![Value::Number(1.0), Value::Number(2.0)]).unwrap();
+        assert_eq!(r.format(), "3");
+    }
+    #[test] fn vm_call_closure_keeps_state() {
+        let mut interp = loaded("\
+fn make_counter() {
+    let n = 0
+    return fn() { let n = n + 1\n return n }
+}
+let counter = make_counter()");
+        assert_eq!(interp.vm_mut().call("counter", vec![]).unwrap().format(), "1");
+        assert_eq!(interp.vm_mut().call("counter", vec![]).unwrap().format(), "2");
+    }
+    #[test] fn vm_call_closure_value_from_script() {
+        use crate::Value;
+        use std::cell::RefCell;
+        use std::rc::Rc;
+        let handler: Rc<RefCell<Option<Value>>> = Rc::new(RefCell::new(None));
+        let slot = Rc::clone(&handler);
+        let mut interp = crate::Interpreter::new();
+        interp.vm_mut().register_native("on_event", 1, move |_, args| {
+            *slot.borrow_mut() = Some(args[0].clone());
+            Ok(Value::None)
+        });
+        interp.eval("let prefix = \"got \"\non_event(fn(e) { return prefix + e })").unwrap();
+        let f = handler.borrow_mut().take().unwrap();
+        let r = interp.vm_mut().call(f, vec![Value::Str("click".into())]).unwrap();
+        assert_eq!(r.format(), "got click");
+    }
+    #[test] fn vm_call_builtin_by_name() {
+        use crate::Value;
+        let mut interp = crate::Interpreter::new();
+        let arr = Value::Array(std::rc::Rc::new(vec![Value::Number(1.0), Value::Number(2.0)]));
+        assert_eq!(interp.vm_mut().call("length", vec![arr]).unwrap().format(), "2");
+    }
+    #[test] fn vm_call_errors_leave_vm_usable() {
+        use crate::{ErrorKind, Value};
+        let mut interp = loaded("fn boom() { return 1 / 0 }\nfn id(x) { return x }");
+        assert!(interp.vm_mut().call("boom", vec![]).is_err());
+        let e = interp.vm_mut().call("missing", vec![]).unwrap_err();
+        assert!(matches!(e.kind, ErrorKind::UndefinedFunction(_)), "got: {}", e);
+        let e = interp.vm_mut().call("id", vec![]).unwrap_err();
+        assert!(matches!(e.kind, ErrorKind::ArgumentCount { .. }), "got: {}", e);
+        assert_eq!(interp.vm_mut().call("id", vec![Value::Bool(true)]).unwrap().format(), "true");
+    }
+    #[test] fn run_after_error_keeps_globals() {
+        let mut interp = loaded("let g = 5");
+        assert!(interp.eval("print nope").is_err());
+        interp.eval("let h = g + 1").unwrap();
+        interp.eval("fn get() { return h }").unwrap();
+        assert_eq!(interp.vm_mut().call("get", vec![]).unwrap().format(), "6");
+    }
+
+    // ── FromValue / IntoValue ─────────────────────────────────────────────────
+    #[test] fn convert_scalars_roundtrip() {
+        use crate::{FromValue, IntoValue};
+        assert_eq!(f64::from_value(2.5f64.into_value()).unwrap(), 2.5);
+        assert_eq!(i64::from_value((-7i64).into_value()).unwrap(), -7);
+        assert!(bool::from_value(true.into_value()).unwrap());
+        assert_eq!(String::from_value("hi".into_value()).unwrap(), "hi");
+    }
+    #[test] fn convert_collections_roundtrip() {
+        use crate::{FromValue, IntoValue};
+        use std::collections::HashMap;
+        let v = vec![1u32, 2, 3].into_value();
+        assert_eq!(v.format(), "[1, 2, 3]");
+        assert_eq!(Vec::<u32>::from_value(v).unwrap(), vec![1, 2, 3]);
+        let mut m = HashMap::new();
+        m.insert("a".to_string(), vec![true]);
+        let back = HashMap::<String, Vec<bool>>::from_value(m.clone().into_value()).unwrap();
+        assert_eq!(back, m);
+        let t = ("x", 1.5f64, Some(2i32)).into_value();
+        assert_eq!(t.format(), "[x, 1.5, 2]");
+        assert_eq!(<(String, f64, Option<i32>)>::from_value(t).unwrap(), ("x".to_string(), 1.5, Some(2)));
+        assert_eq!(Option::<i32>::from_value(crate::Value::None).unwrap(), None);
+    }
+    #[test] fn convert_errors_are_type_errors() {
+        use crate::{ErrorKind, FromValue, IntoValue};
+        let e = String::from_value(3.0f64.into_value()).unwrap_err();
+        assert!(matches!(&e.kind, ErrorKind::TypeError { expected, found } if expected == "string" && found == "number"), "got: {}", e);
+        assert!(i32::from_value(1.5f64.into_value()).is_err());
+        assert!(u8::from_value((-1i32).into_value()).is_err());
+        assert!(<(i32, i32)>::from_value(vec![1, 2, 3].into_value()).unwrap_err().to_string().contains("array of length 2"));
+        assert!(Vec::<bool>::from_value(vec![1].into_value()).is_err());
+    }
+    #[test] fn convert_in_natives_and_calls() {
+        use crate::{FromValue, IntoValue};
+        let mut interp = crate::Interpreter::new();
+        interp.vm_mut().register_native("sum", 1, |_, args| {
+            let xs = Vec::<f64>::from_value(args.into_iter().next().unwrap())?;
+            Ok(xs.iter().sum::<f64>().into_value())
+        });
+        interp.eval("fn words(n) { let out = []\nfor i in range(0, n) { out = push(out, \"w\" + i) }\nreturn out }").unwrap();
+        let total = f64::from_value(interp.vm_mut().call("sum", vec![vec![1, 2, 3].into_value()]).unwrap()).unwrap();
+        assert_eq!(total, 6.0);
+        let words = Vec::<String>::from_value(interp.vm_mut().call("words", vec![2.into_value()]).unwrap()).unwrap();
+        assert_eq!(words, vec!["w0", "w1"]);
+    }
+
+    // ── Pluggable I/O ─────────────────────────────────────────────────────────
+    fn run_with_input(src: &str, input: &'static str) -> Result<String, String> {
+        let buf = super::SharedBuf::default();
+        let mut interp = crate::Interpreter::with_vm(crate::Vm::with_io(buf.clone(), input.as_bytes()));
+        let result = interp.eval(src).map_err(|e| e.to_string());
+        let transcript = String::from_utf8_lossy(&buf.0.borrow()).into_owned();
+        result.map(|_| transcript)
+    }
+    #[test] fn input_reads_injected_lines() {
+        let out = run_with_input("let a = input()\nlet b = input()\nprint b + a", "x\r\ny\n").unwrap();
+        assert_eq!(out, "yx\n");
+    }
+    #[test] fn input_prompt_goes_to_output() {
+        let out = run_with_input("let n = input(\"name? \")\nprint \"hi \" + n", "Em\n").unwrap();
+        assert_eq!(out, "name? hi Em\n");
+    }
+    #[test] fn input_at_eof_is_empty() {
+        assert_eq!(run_with_input("print length(input())", "").unwrap(), "0\n");
+    }
+    #[test] fn interactive_game_transcript() {
+        let src = include_str!("../examples/interactive_game.wsp");
+        let out = run_with_input(src, "3\n9\n7\n").unwrap();
+        assert_eq!(out, "\
+=== Number Guessing Game ===
+I'm thinking of a number between 1 and 10
+Your guess: Too low! Try again.
+Your guess: Too high! Try again.
+Your guess: Correct! You found it in 3 guesses.
+");
+    }
+
+    // ── Sandbox ───────────────────────────────────────────────────────────────
+    fn sandbox_dir(tag: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("whispem_sandbox_{}_{}", tag, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("inside")).unwrap();
+        std::fs::write(dir.join("inside/data.txt"), "secret").unwrap();
+        std::fs::write(dir.join("outside.txt"), "other").unwrap();
+        dir
+    }
+    fn sandboxed(src: &str, perms: crate::Permissions) -> Result<Vec<String>, String> {
+        super::run_capturing_with(src, |vm| vm.permissions = perms)
+    }
+    fn quoted(path: &std::path::Path) -> String { format!("\"{}\"", path.display()) }
+
+    #[test] fn sandbox_default_is_unrestricted() {
+        let dir = sandbox_dir("default");
+        let src = format!("print read_file({})", quoted(&dir.join("outside.txt")));
+        assert_eq!(ok(&src), vec!["other"]);
+    }
+    #[test] fn sandbox_denies_read() {
+        let dir = sandbox_dir("read_denied");
+        let src = format!("print read_file({})", quoted(&dir.join("inside/data.txt")));
+        let e = sandboxed(&src, crate::Permissions::sandboxed()).unwrap_err();
+        assert!(e.contains("Permission denied: 'read_file'"), "{}", e);
+    }
+    #[test] fn sandbox_allows_read_inside_dir() {
+        let dir = sandbox_dir("read_allowed");
+        let perms = crate::Permissions { fs_read: Some(vec![dir.join("inside")]), ..crate::Permissions::sandboxed() };
+        let src = format!("print read_file({})", quoted(&dir.join("inside/data.txt")));
+        assert_eq!(sandboxed(&src, perms.clone()).unwrap(), vec!["secret"]);
+        let src = format!("print read_file({})", quoted(&dir.join("outside.txt")));
+        assert!(sandboxed(&src, perms.clone()).unwrap_err().contains("Permission denied"));
+        let src = format!("print read_file({})", quoted(&dir.join("inside/../outside.txt")));
+        assert!(sandboxed(&src, perms).unwrap_err().contains("Permission denied"));
+    }
+    #[test] fn sandbox_write_permissions() {
+        let dir = sandbox_dir("write");
+        let perms = crate::Permissions { fs_write: Some(vec![dir.join("inside")]), ..crate::Permissions::sandboxed() };
+        let src = format!("write_file({}, \"ok\")", quoted(&dir.join("inside/new.txt")));
+        sandboxed(&src, perms.clone()).unwrap();
+        assert_eq!(std::fs::read_to_string(dir.join("inside/new.txt")).unwrap(), "ok");
+        let src = format!("write_file({}, \"no\")", quoted(&dir.join("new.txt")));
+        assert!(sandboxed(&src, perms.clone()).unwrap_err().contains("Permission denied: 'write_file'"));
+        let src = format!("write_hex({}, \"41\")", quoted(&dir.join("new.bin")));
+        assert!(sandboxed(&src, perms).unwrap_err().contains("Permission denied: 'write_hex'"));
+        assert!(!dir.join("new.txt").exists() && !dir.join("new.bin").exists());
+    }
+    #[cfg(unix)]
+    #[test] fn sandbox_rejects_dangling_symlink() {
+        let dir = sandbox_dir("symlink");
+        let perms = crate::Permissions { fs_write: Some(vec![dir.join("inside")]), ..crate::Permissions::sandboxed() };
+        std::os::unix::fs::symlink(dir.join("escaped.txt"), dir.join("inside/link.txt")).unwrap();
+        let src = format!("write_file({}, \"no\")", quoted(&dir.join("inside/link.txt")));
+        assert!(sandboxed(&src, perms).unwrap_err().contains("Permission denied: 'write_file'"));
+        assert!(!dir.join("escaped.txt").exists());
+    }
+    #[test] fn sandbox_denies_input_args_exit() {
+        let p = crate::Permissions::sandboxed;
+        assert!(sandboxed("let x = input()", p()).unwrap_err().contains("'input' may not access 'stdin'"));
+        assert!(sandboxed("let a = args()", p()).unwrap_err().contains("'args'"));
+        assert!(sandboxed("exit(0)", p()).unwrap_err().contains("'exit'"));
+    }
+    #[test] fn sandbox_error_is_permission_denied_kind() {
+        let mut interp = crate::Interpreter::new();
+        interp.vm_mut().permissions = crate::Permissions::sandboxed();
+        let e = interp.eval("let a = args()").unwrap_err();
+        assert!(matches!(e.kind, crate::ErrorKind::PermissionDenied { ref builtin, .. } if builtin == "args"));
+    }
+
+    // ── Resource limits ───────────────────────────────────────────────────────
+    fn limited(src: &str, limits: crate::Limits) -> Result<Vec<String>, String> {
+        super::run_capturing_with(src, |vm| vm.limits = limits)
+    }
+    #[test] fn step_limit_stops_infinite_loop() {
+        let limits = crate::Limits { max_steps: Some(10_000), ..crate::Limits::none() };
+        let e = limited("let i = 0\nwhile true { i = i + 1 }", limits).unwrap_err();
+        assert!(e.contains("Step limit exceeded: more than 10000 instructions"), "{}", e);
+    }
+    #[test] fn step_limit_allows_small_programs() {
+        let limits = crate::Limits { max_steps: Some(10_000), ..crate::Limits::none() };
+        assert_eq!(limited("let t = 0\nfor i in range(0, 10) { t = t + i }\nprint t", limits).unwrap(), vec!["45"]);
+    }
+    #[test] fn step_budget_resets_per_run() {
+        let mut interp = crate::Interpreter::with_vm(crate::Vm::with_io(std::io::sink(), std::io::empty()));
+        interp.vm_mut().limits.max_steps = Some(200);
+        for _ in 0..10 { interp.eval("let t = 0\nfor i in range(0, 5) { t = t + i }").unwrap(); }
+    }
+    #[test] fn frame_limit_stops_runaway_recursion() {
+        let limits = crate::Limits { max_frames: Some(100), ..crate::Limits::none() };
+        let e = limited("fn down(n) { return down(n + 1) }\ndown(0)", limits).unwrap_err();
+        assert!(e.contains("Stack overflow: call depth exceeded 100"), "{}", e);
+        assert_eq!(limited("fn f(n) { if n == 0 { return 0 }\nreturn f(n - 1) }\nprint f(50)", limits).unwrap(), vec!["0"]);
+    }
+    #[test] fn memory_limit_refuses_huge_repeat() {
+        let limits = crate::Limits { max_heap_bytes: Some(1 << 20), ..crate::Limits::none() };
+        let e = limited("print length(repeat(\"abc\", 1000000000000))", limits).unwrap_err();
+        assert!(e.contains("Memory limit exceeded"), "{}", e);
+    }
+    #[test] fn stack_limit() {
+        let limits = crate::Limits { max_stack: Some(16), ..crate::Limits::none() };
+        let e = limited("print [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20]", limits).unwrap_err();
+        assert!(e.contains("Stack overflow: value stack size exceeded 16"), "{}", e);
+    }
+    #[test] fn memory_limit_stops_string_doubling() {
+        let limits = crate::Limits { max_heap_bytes: Some(1 << 20), ..crate::Limits::none() };
+        let e = limited("let s = \"x\"\nwhile true { s = s + s }", limits).unwrap_err();
+        assert!(e.contains("Memory limit exceeded"), "{}", e);
+    }
+    #[test] fn memory_limit_counts_live_values_only() {
+        let limits = crate::Limits { max_heap_bytes: Some(64 * 1024), ..crate::Limits::none() };
+        let src = "for i in range(0, 2000) { let xs = [i, i, i, i, i, i, i, i] }\nprint \"done\"";
+        assert_eq!(limited(src, limits).unwrap(), vec!["done"]);
+        let src = "let xs = []\nwhile true { xs = push(xs, \"item\") }";
+        assert!(limited(src, limits).unwrap_err().contains("Memory limit exceeded"));
+    }
+    #[test] fn memory_limit_measures_deep_nesting() {
+        let limits = crate::Limits { max_heap_bytes: Some(1 << 20), ..crate::Limits::none() };
+        let e = limited("let x = []\nwhile true { x = [x] }", limits).unwrap_err();
+        assert!(e.contains("Memory limit exceeded"), "{}", e);
+    }
+    #[test] fn nested_callbacks_overflow_cleanly() {
+        let limits = crate::Limits { max_callback_depth: 20, ..crate::Limits::none() };
+        let e = limited("fn deep(n) { return map([n], fn(x) { return deep(x + 1) }) }\ndeep(0)", limits).unwrap_err();
+        assert!(e.contains("Stack overflow: nested callback depth exceeded 20"), "{}", e);
+        let e = limited("fn gen() { yield next(gen()) }\nprint next(gen())", limits).unwrap_err();
+        assert!(e.contains("Stack overflow: nested callback depth exceeded 20"), "{}", e);
+    }
+    #[test] fn nested_callbacks_default_depth_fits_spawned_thread() {
+        let run = |src: &'static str| std::thread::spawn(move || super::run_capturing(src)).join().unwrap();
+        let e = run("fn deep(n) { return map([n], fn(x) { return deep(x + 1) }) }\ndeep(0)").unwrap_err();
+        assert!(e.contains("Stack overflow: nested callback depth exceeded 24"), "{}", e);
+        let e = run("fn gen(n) { yield next(gen(n + 1)) }\nprint next(gen(0))").unwrap_err();
+        assert!(e.contains("Stack overflow: nested callback depth exceeded 24"), "{}", e);
+    }
+    #[test] fn nested_callbacks_opt_into_more_depth() {
+        let limits = crate::Limits { max_callback_depth: 1000, ..crate::Limits::none() };
+        let deep = move |src: &'static str| std::thread::Builder::new().stack_size(256 << 20)
+            .spawn(move || limited(src, limits)).unwrap().join().unwrap();
+        let src = "fn deep(n) { if n == 0 { return 0 }\nreturn map([n], fn(x) { return deep(x - 1) })[0] }\nprint deep(100)";
+        assert_eq!(deep(src).unwrap(), vec!["0"]);
+        let src = "fn count(n) { if n > 0 { yield next(count(n - 1)) }\nyield n }\nprint next(count(100))";
+        assert_eq!(deep(src).unwrap(), vec!["0"]);
+    }
+    #[test] fn limit_error_kinds() {
+        let mut interp = crate::Interpreter::new();
+        interp.vm_mut().limits.max_steps = Some(50);
+        let e = interp.eval("while true {}").unwrap_err();
+        assert!(matches!(e.kind, crate::ErrorKind::StepLimitExceeded(50)));
+        interp.vm_mut().limits = crate::Limits { max_frames: Some(10), ..crate::Limits::none() };
+        let e = interp.eval("fn f() { return f() }\nf()").unwrap_err();
+        assert!(matches!(e.kind, crate::ErrorKind::StackOverflow { limit: 10, .. }));
+    }
+
+    // ── Interruption ──────────────────────────────────────────────────────────
+    #[test] fn interrupt_from_another_thread() {
+        let mut interp = crate::Interpreter::with_vm(crate::Vm::with_io(super::SharedBuf::default(), std::io::empty()));
+        let handle = interp.vm().interrupt_handle();
+        let stopper = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            handle.interrupt();
+        });
+        let e = interp.eval("let i = 0\nwhile true { i = i + 1 }").unwrap_err();
+        stopper.join().unwrap();
+        assert!(matches!(e.kind, crate::ErrorKind::Interrupted));
+        assert!(e.to_string().contains("Interrupted"));
+    }
+    #[test] fn vm_reusable_after_interrupt() {
+        let buf = super::SharedBuf::default();
+        let mut interp = crate::Interpreter::with_vm(crate::Vm::with_io(buf.clone(), std::io::empty()));
+        interp.vm().interrupt_handle().interrupt();
+        let e = interp.eval("fn spin(n) { return spin(n + 1) }\nspin(0)").unwrap_err();
+        assert!(matches!(e.kind, crate::ErrorKind::Interrupted));
+        interp.eval("let x = 21\nprint x * 2").unwrap();
+        assert_eq!(buf.lines(), vec!["42"]);
+    }
+    #[test] fn interrupt_checked_in_callbacks() {
+        let mut interp = crate::Interpreter::with_vm(crate::Vm::with_io(super::SharedBuf::default(), std::io::empty()));
+        let handle = interp.vm().interrupt_handle();
+        interp.vm_mut().register_native("stop", 0, move |_, _| { handle.interrupt(); Ok(crate::Value::None) });
+        let e = interp.eval("let xs = map([1, 2, 3], fn(x) { stop()\nreturn x })").unwrap_err();
+        assert!(matches!(e.kind, crate::ErrorKind::Interrupted));
+        assert!(!interp.vm().interrupt_handle().take());
+    }
+    #[test] fn take_clears_pending_interrupt() {
+        let vm = crate::Vm::new();
+        let handle = vm.interrupt_handle();
+        assert!(!handle.take());
+        handle.interrupt();
+        assert!(handle.take());
+        assert!(!handle.take());
+    }
+
+    // ── Modules ───────────────────────────────────────────────────────────────
+    fn module_dir(tag: &str, files: &[(&str, &str)]) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("whispem_modules_{}_{}", tag, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        for (name, src) in files { std::fs::write(dir.join(name), src).unwrap(); }
+        dir
+    }
+    fn run_in(dir: &std::path::Path, src: &str, via_bytecode: bool) -> Result<Vec<String>, String> {
+        let buf = super::SharedBuf::default();
+        let mut interp = crate::Interpreter::with_vm(crate::Vm::with_io(buf.clone(), std::io::empty()));
+        let mut program = interp.compile_in(src, dir).map_err(|e| e.to_string())?;
+        if via_bytecode {
+            program = crate::Program::from_bytes(&program.to_bytes().unwrap()).map_err(|e| e.to_string())?;
+        }
+        interp.run(&program).map_err(|e| e.to_string())?;
+        Ok(buf.lines())
+    }
+    const GREET: &str = "let greeting = \"hello\"\nfn greet(name) {\n    return greeting + \" \" + name\n}\n";
+
+    #[test] fn import_unqualified() {
+        let dir = module_dir("plain", &[("greet.wsp", GREET)]);
+        assert_eq!(run_in(&dir, "import \"greet.wsp\"\nprint greet(\"ada\")\nprint greeting", false).unwrap(),
+                   vec!["hello ada", "hello"]);
+    }
+    #[test] fn import_with_alias() {
+        let dir = module_dir("alias", &[("greet.wsp", GREET)]);
+        assert_eq!(run_in(&dir, "import \"greet.wsp\" as g\nprint g.greet(\"bob\")\nprint g.greeting", false).unwrap(),
+                   vec!["hello bob", "hello"]);
+    }
+    #[test] fn module_globals_are_separate() {
+        let dir = module_dir("globals", &[("greet.wsp", GREET)]);
+        let src = "import \"greet.wsp\" as g\nlet greeting = \"bye\"\nprint g.greet(\"x\")\nprint greeting";
+        assert_eq!(run_in(&dir, src, false).unwrap(), vec!["hello x", "bye"]);
+    }
+    #[test] fn importer_names_win_over_unqualified_imports() {
+        let dir = module_dir("shadow", &[("greet.wsp", GREET)]);
+        let src = "import \"greet.wsp\"\nfn greet(n) { return \"own \" + n }\nprint greet(\"y\")";
+        assert_eq!(run_in(&dir, src, false).unwrap(), vec!["own y"]);
+    }
+    #[test] fn module_runs_once_when_imported_twice() {
+        let dir = module_dir("cache", &[
+            ("lib/counter.wsp", "print \"loading counter\"\nlet start = 10\n"),
+            ("lib/a.wsp", "import \"counter.wsp\" as c\nfn a() { return c.start + 1 }\n"),
+            ("b.wsp", "import \"lib/counter.wsp\"\nfn b() { return start + 2 }\n"),
+        ]);
+        let src = "import \"lib/a.wsp\"\nimport \"b.wsp\"\nprint a()\nprint b()";
+        assert_eq!(run_in(&dir, src, false).unwrap(), vec!["loading counter", "11", "12"]);
+    }
+    #[test] fn import_cycle_is_reported() {
+        let dir = module_dir("cycle", &[
+            ("a.wsp", "import \"b.wsp\"\n"),
+            ("b.wsp", "import \"c.wsp\"\n"),
+            ("c.wsp", "import \"a.wsp\"\n"),
+        ]);
+        let e = run_in(&dir, "import \"a.wsp\"", false).unwrap_err();
+        assert!(e.contains("Import cycle: a.wsp -> b.wsp -> c.wsp -> a.wsp"), "{}", e);
+    }
+    #[test] fn import_errors() {
+        let dir = module_dir("errors", &[("greet.wsp", GREET), ("broken.wsp", "let x = (\n")]);
+        assert!(run_in(&dir, "import \"missing.wsp\"", false).unwrap_err().contains("Failed to read"));
+        assert!(run_in(&dir, "import \"greet.wsp\" as g\nprint g.nope", false).unwrap_err()
+            .contains("module 'g' has no member 'nope'"));
+        assert!(run_in(&dir, "if true {\n    import \"greet.wsp\"\n}", false).unwrap_err()
+            .contains("only allowed at the top level"));
+        let e = run_in(&dir, "import \"broken.wsp\"", false).unwrap_err();
+        assert!(e.contains("in 'broken.wsp' at line 1"), "{}", e);
+    }
+    #[test] fn imports_run_before_importer_code() {
+        let dir = module_dir("order", &[("noisy.wsp", "print \"module\"\n")]);
+        assert_eq!(run_in(&dir, "import \"noisy.wsp\"\nprint \"main\"", false).unwrap(), vec!["module", "main"]);
+        let e = run_in(&dir, "print \"main\"\nimport \"noisy.wsp\"", false).unwrap_err();
+        assert!(e.contains("line 2") && e.contains("'import' must come before other top-level statements"), "{}", e);
+    }
+    #[test] fn sandbox_restricts_imports() {
+        let dir = module_dir("sandbox", &[("greet.wsp", GREET), ("lib/util.wsp", "let x = 1\n")]);
+        let compile = |perms: crate::Permissions, src: &str| {
+            let mut interp = crate::Interpreter::new();
+            interp.vm_mut().permissions = perms;
+            interp.compile_in(src, &dir).map(|_| ()).map_err(|e| e.to_string())
+        };
+        let e = compile(crate::Permissions::sandboxed(), "import \"greet.wsp\"").unwrap_err();
+        assert!(e.contains("Permission denied: 'import'"), "{}", e);
+        let e = compile(crate::Permissions::sandboxed(), "import \"missing.wsp\"").unwrap_err();
+        assert!(e.contains("Permission denied: 'import'"), "{}", e);
+        let perms = crate::Permissions { fs_read: Some(vec![dir.join("lib")]), ..crate::Permissions::sandboxed() };
+        compile(perms.clone(), "import \"lib/util.wsp\"").unwrap();
+        assert!(compile(perms, "import \"lib/../greet.wsp\"").unwrap_err().contains("Permission denied: 'import'"));
+    }
+    #[test] fn imports_serialise_into_one_program() {
+        let dir = module_dir("bytecode", &[("greet.wsp", GREET)]);
+        assert_eq!(run_in(&dir, "import \"greet.wsp\" as g\nprint g.greet(\"bc\")", true).unwrap(), vec!["hello bc"]);
+    }
+    #[test] fn repl_keeps_imports_between_inputs() {
+        let dir = module_dir("repl", &[("greet.wsp", "print \"loaded\"\nfn hi() { return \"hi\" }\n")]);
+        let buf = super::SharedBuf::default();
+        let mut interp = crate::Interpreter::with_vm(crate::Vm::with_io(buf.clone(), std::io::empty()));
+        for src in ["import \"greet.wsp\" as g", "print g.hi()", "import \"greet.wsp\"", "print hi()"] {
+            let program = interp.compile_in(src, &dir).unwrap();
+            interp.run(&program).unwrap();
+        }
+        assert_eq!(buf.lines(), vec!["loaded", "hi", "hi"]);
+    }
+}
@@ -0,0 +1,125 @@
+use crate::value::Value;
+use std::collections::HashSet;
+use std::mem::size_of;
+use std::rc::Rc;
+
+/// Execution budgets enforced by the VM.  `None` means unlimited, which is
+/// the default.
+///
+/// - `max_steps`          — instructions executed per top-level `run` / `call`
+/// - `max_frames`         — call-frame depth
+/// - `max_stack`          — value-stack slots
+/// - `max_heap_bytes`     — approximate bytes held by strings, arrays and dicts
+/// - `max_callback_depth` — nesting of callbacks run from builtins (`map`,
+///   `filter`, `reduce`, `sort_by`, `next`, ...)
+///
+/// Each nested callback recurses on the native stack: roughly 64 KiB per
+/// level in debug builds and 8 KiB in release builds.  `max_callback_depth`
+/// is therefore always enforced.  The default, [`DEFAULT_CALLBACK_DEPTH`],
+/// fits the 2 MiB stack of a thread from `std::thread::spawn`; hosts that
+/// run the VM on a larger stack may raise it, as the CLI does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    pub max_steps:          Option<u64>,
+    pub max_frames:         Option<usize>,
+    pub max_stack:          Option<usize>,
+    pub max_heap_bytes:     Option<usize>,
+    pub max_callback_depth: usize,
+}
+
+/// Default for [`Limits::max_callback_depth`].
+pub const DEFAULT_CALLBACK_DEPTH: usize = 24;
+
+impl Limits {
+    /// No limits beyond the default callback depth.
+    pub fn none() -> Self { Self::default() }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_steps:          None,
+            max_frames:         None,
+            max_stack:          None,
+            max_heap_bytes:     None,
+            max_callback_depth: DEFAULT_CALLBACK_DEPTH,
+        }
+    }
+}
+
+/// Bytes a freshly built value adds on its own, not counting the elements
+/// it shares with existing values.
+pub(crate) fn shallow_size(v: &Value) -> usize {
+    match v {
+        Value::Str(s)   => s.len(),
+        Value::Array(a) => a.len() * size_of::<Value>(),
+        Value::Dict(d)  => d.keys().map(|k| k.len() + size_of::<String>() + size_of::<Value>()).sum(),
+        Value::Struct(st) => st.fields.len() * size_of::<Value>(),
+        _               => 0,
+    }
+}
+
+/// Measures the heap reachable from a set of roots.  Arrays, dicts and structs
+/// shared through `Rc` are counted once.
+#[derive(Default)]
+pub(crate) struct HeapMeter {
+    seen:  HashSet<*const ()>,
+    pub total: usize,
+}
+
+impl HeapMeter {
+    // Walks with an explicit worklist rather than recursion: the meter exists
+    // to stop runaway scripts, and a deeply nested value must not overflow
+    // the native stack while it is being measured.
+    pub fn add(&mut self, v: &Value) {
+        let mut work = vec![v.clone()];
+        while let Some(v) = work.pop() {
+            match &v {
+                Value::Str(s) => self.total += s.len(),
+                Value::Array(a) => {
+                    if !self.seen.insert(Rc::as_ptr(a) as *const ()) { continue; }
+                    self.total += a.len() * size_of::<Value>();
+                    work.extend(a.iter().cloned());
+                }
+                Value::Dict(d) => {
+                    if !self.seen.insert(Rc::as_ptr(d) as *const ()) { continue; }
+                    for (k, item) in d.iter() {
+                        self.total += k.len() + size_of::<String>() + size_of::<Value>();
+                        work.push(item.clone());
+                    }
+                }
+                Value::Struct(st) => {
+                    if !self.seen.insert(Rc::as_ptr(st) as *const ()) { continue; }
+                    self.total += st.fields.len() * size_of::<Value>();
+                    work.extend(st.fields.iter().cloned());
+                }
+                Value::Generator(g) => {
+                    if !self.seen.insert(Rc::as_ptr(g) as *const ()) { continue; }
+                    g.borrow().for_each_value(|v| work.push(v.clone()));
+                }
+                Value::Closure { upvalues, .. } => {
+                    for uv in upvalues {
+                        if !self.seen.insert(Rc::as_ptr(uv) as *const ()) { continue; }
+                        work.push((*uv.borrow().0).clone());
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Drops `values` without recursing through nested arrays, dicts and structs,
+/// so tearing down a value nested thousands of levels deep (which a memory
+/// limit still admits) cannot overflow the native stack.
+pub(crate) fn release(values: impl IntoIterator<Item = Value>) {
+    let mut work: Vec<Value> = values.into_iter().collect();
+    while let Some(v) = work.pop() {
+        match v {
+            Value::Array(a) => if let Ok(items) = Rc::try_unwrap(a) { work.extend(items) },
+            Value::Dict(d)  => if let Ok(map) = Rc::try_unwrap(d) { work.extend(map.into_values()) },
+            Value::Struct(st) => if let Ok(st) = Rc::try_unwrap(st) { work.extend(st.fields) },
+            _ => {}
+        }
+    }
+}
@@ -12,6 +12,8 @@ pub enum OpCode {
     LoadUpvalue  = 0x13,
     StoreUpvalue = 0x14,
     CloseUpvalue = 0x15,
+    HasArg       = 0x16,
+    StoreGlobal  = 0x17,
 
     Add          = 0x20,
     Sub          = 0x21,
@@ -19,6 +21,7 @@ pub enum OpCode {
     Div          = 0x23,
     Mod          = 0x24,
     Neg          = 0x25,
+    IntDiv       = 0x26,
 
     Eq           = 0x30,
     Neq          = 0x31,
@@ -38,15 +41,31 @@ pub enum OpCode {
     Return       = 0x51,
     ReturnNone   = 0x52,
     MakeClosure  = 0x53,
+    CallNamed    = 0x54,
 
     MakeArray    = 0x60,
     MakeDict     = 0x61,
     GetIndex     = 0x62,
     SetIndex     = 0x63,
+    MakeStruct   = 0x64,
+    GetField     = 0x65,
+    SetField     = 0x66,
+    UnpackArray  = 0x67,
+    UnpackDict   = 0x68,
 
     Print        = 0x70,
     Pop          = 0x71,
 
+    TryBegin     = 0x80,
+    TryEnd       = 0x81,
+    Throw        = 0x82,
+    MatchFail    = 0x83,
+
+    Iter          = 0x90,
+    IterNext      = 0x91,
+    MakeGenerator = 0x92,
+    Yield         = 0x93,
+
     Halt         = 0xFF,
 }
 
@@ -63,12 +82,15 @@ impl OpCode {
             0x13 => Some(Self::LoadUpvalue),
             0x14 => Some(Self::StoreUpvalue),
             0x15 => Some(Self::CloseUpvalue),
+            0x16 => Some(Self::HasArg),
+            0x17 => Some(Self::StoreGlobal),
             0x20 => Some(Self::Add),
             0x21 => Some(Self::Sub),
             0x22 => Some(Self::Mul),
             0x23 => Some(Self::Div),
             0x24 => Some(Self::Mod),
             0x25 => Some(Self::Neg),
+            0x26 => Some(Self::IntDiv),
             0x30 => Some(Self::Eq),
             0x31 => Some(Self::Neq),
             0x32 => Some(Self::Lt),
@@ -85,12 +107,26 @@ impl OpCode {
             0x51 => Some(Self::Return),
             0x52 => Some(Self::ReturnNone),
             0x53 => Some(Self::MakeClosure),
+            0x54 => Some(Self::CallNamed),
             0x60 => Some(Self::MakeArray),
             0x61 => Some(Self::MakeDict),
             0x62 => Some(Self::GetIndex),
             0x63 => Some(Self::SetIndex),
+            0x64 => Some(Self::MakeStruct),
+            0x65 => Some(Self::GetField),
+            0x66 => Some(Self::SetField),
+            0x67 => Some(Self::UnpackArray),
+            0x68 => Some(Self::UnpackDict),
             0x70 => Some(Self::Print),
             0x71 => Some(Self::Pop),
+            0x80 => Some(Self::TryBegin),
+            0x81 => Some(Self::TryEnd),
+            0x82 => Some(Self::Throw),
+            0x83 => Some(Self::MatchFail),
+            0x90 => Some(Self::Iter),
+            0x91 => Some(Self::IterNext),
+            0x92 => Some(Self::MakeGenerator),
+            0x93 => Some(Self::Yield),
             0xFF => Some(Self::Halt),
             _    => None,
         }
@@ -108,12 +144,15 @@ impl OpCode {
             Self::LoadUpvalue      => "LOAD_UPVALUE",
             Self::StoreUpvalue     => "STORE_UPVALUE",
             Self::CloseUpvalue     => "CLOSE_UPVALUE",
+            Self::HasArg           => "HAS_ARG",
+            Self::StoreGlobal      => "STORE_GLOBAL",
             Self::Add              => "ADD",
             Self::Sub              => "SUB",
             Self::Mul              => "MUL",
             Self::Div              => "DIV",
             Self::Mod              => "MOD",
             Self::Neg              => "NEG",
+            Self::IntDiv           => "INT_DIV",
             Self::Eq               => "EQ",
             Self::Neq              => "NEQ",
             Self::Lt               => "LT",
@@ -130,12 +169,26 @@ impl OpCode {
             Self::Return           => "RETURN",
             Self::ReturnNone       => "RETURN_NONE",
             Self::MakeClosure      => "MAKE_CLOSURE",
+            Self::CallNamed        => "CALL_NAMED",
             Self::MakeArray        => "MAKE_ARRAY",
             Self::MakeDict         => "MAKE_DICT",
             Self::GetIndex         => "GET_INDEX",
             Self::SetIndex         => "SET_INDEX",
+            Self::MakeStruct       => "MAKE_STRUCT",
+            Self::GetField         => "GET_FIELD",
+            Self::SetField         => "SET_FIELD",
+            Self::UnpackArray      => "UNPACK_ARRAY",
+            Self::UnpackDict       => "UNPACK_DICT",
             Self::Print            => "PRINT",
             Self::Pop              => "POP",
+            Self::TryBegin         => "TRY_BEGIN",
+            Self::TryEnd           => "TRY_END",
+            Self::Throw            => "THROW",
+            Self::MatchFail        => "MATCH_FAIL",
+            Self::Iter             => "ITER",
+            Self::IterNext         => "ITER_NEXT",
+            Self::MakeGenerator    => "MAKE_GENERATOR",
+            Self::Yield            => "YIELD",
             Self::Halt             => "HALT",
         }
     }
@@ -149,15 +202,24 @@ impl OpCode {
             | Self::LoadUpvalue
             | Self::StoreUpvalue
             | Self::CloseUpvalue
+            | Self::HasArg
+            | Self::StoreGlobal
             | Self::MakeArray
-            | Self::MakeDict  => 1,
+            | Self::MakeDict
+            | Self::MakeStruct
+            | Self::GetField
+            | Self::SetField
+            | Self::UnpackDict => 1,
             Self::Jump
             | Self::JumpIfFalse
             | Self::JumpIfTrue
             | Self::PeekJumpIfFalse
             | Self::PeekJumpIfTrue
+            | Self::TryBegin
+            | Self::UnpackArray
             | Self::Call      => 2,
             Self::MakeClosure => 2,
+            Self::CallNamed   => 3,
             _                 => 0,
         }
     }
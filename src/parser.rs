@@ -1,15 +1,19 @@
-use crate::ast::{BinaryOp, Expr, LogicalOp, Stmt, UnaryOp};
+use crate::ast::{ArmBody, BinaryOp, Expr, LogicalOp, MatchArm, Param, Pattern, Stmt, UnaryOp};
 use crate::error::{ErrorKind, Span, WhispemError, WhispemResult};
 use crate::lexer::Lexer;
 use crate::token::{FStrPart as TokenFStrPart, Spanned, Token};
 
+type CallArgs = (Vec<Expr>, Vec<(String, Expr)>);
+
 pub struct Parser {
     tokens:   Vec<Spanned>,
     position: usize,
+    // Set while parsing a `match` subject, whose `{` opens the arms.
+    no_struct_literal: bool,
 }
 
 impl Parser {
-    pub fn new(tokens: Vec<Spanned>) -> Self { Self { tokens, position: 0 } }
+    pub fn new(tokens: Vec<Spanned>) -> Self { Self { tokens, position: 0, no_struct_literal: false } }
 
     fn cur(&self) -> &Spanned {
         &self.tokens[self.position.min(self.tokens.len() - 1)]
@@ -54,10 +58,20 @@ impl Parser {
     }
 
     pub fn parse_program(&mut self) -> WhispemResult<Vec<Stmt>> {
-        let mut stmts = Vec::new();
+        let mut stmts: Vec<Stmt> = Vec::new();
         loop {
             self.skip_nl();
             if self.cur().token == Token::Eof { break; }
+            // Modules run before the importing file's own code, so imports
+            // must come first for the source order to match.
+            if self.cur().token == Token::Import
+                && stmts.last().is_some_and(|s| !matches!(s, Stmt::Import { .. }))
+            {
+                return Err(WhispemError::new(
+                    ErrorKind::ImportError("'import' must come before other top-level statements".to_string()),
+                    self.span(),
+                ));
+            }
             stmts.push(self.parse_stmt()?);
         }
         Ok(stmts)
@@ -72,8 +86,21 @@ impl Parser {
             Token::For      => self.parse_for(),
             Token::Fn       => self.parse_fn(),
             Token::Return   => self.parse_return(),
+            Token::Yield    => self.parse_yield(),
             Token::Break    => { let l = self.line(); self.advance(); Ok(Stmt::Break { line: l }) }
             Token::Continue => { let l = self.line(); self.advance(); Ok(Stmt::Continue { line: l }) }
+            Token::Try      => self.parse_try(),
+            Token::Import   => self.parse_import(),
+            Token::Global   => self.parse_global(),
+            Token::Struct   => self.parse_struct(),
+            Token::Match    => {
+                let line = self.line();
+                Ok(Stmt::Expression { expr: self.parse_expr()?, line })
+            }
+            Token::Throw    => {
+                let line = self.line(); self.advance();
+                Ok(Stmt::Throw { value: self.parse_expr()?, line })
+            }
             Token::Assert   => self.parse_assert_stmt(),
             Token::Exit     => self.parse_exit_stmt(),
             Token::WriteFile | Token::ReadFile | Token::WriteHex | Token::Args => {
@@ -85,8 +112,8 @@ impl Parser {
                     _                => "read_file",
                 }.to_string();
                 self.advance();
-                let args = self.parse_call_args()?;
-                Ok(Stmt::Expression { expr: Expr::Call { name, arguments: args, line }, line })
+                let (args, named) = self.parse_call_args()?;
+                Ok(Stmt::Expression { expr: Expr::Call { name, arguments: args, named, line }, line })
             }
             Token::Map | Token::Filter | Token::Reduce => {
                 let line = self.line();
@@ -96,8 +123,8 @@ impl Parser {
                     _             => "reduce",
                 }.to_string();
                 self.advance();
-                let args = self.parse_call_args()?;
-                Ok(Stmt::Expression { expr: Expr::Call { name, arguments: args, line }, line })
+                let (args, named) = self.parse_call_args()?;
+                Ok(Stmt::Expression { expr: Expr::Call { name, arguments: args, named, line }, line })
             }
             Token::Identifier(_) => self.parse_ident_stmt(),
             _ => Err(WhispemError::new(
@@ -112,6 +139,12 @@ impl Parser {
 
     fn parse_let(&mut self) -> WhispemResult<Stmt> {
         let line = self.line(); self.advance();
+        if matches!(self.cur().token, Token::LeftBracket | Token::LeftBrace) {
+            let pattern = self.parse_binding()?;
+            self.consume(Token::Equals)?;
+            let value = self.parse_expr()?;
+            return Ok(Stmt::Destructure { pattern, value, line });
+        }
         let name = self.consume_ident()?;
         self.consume(Token::Equals)?;
         let value = self.parse_expr()?;
@@ -146,6 +179,58 @@ impl Parser {
         }
     }
 
+    // `if` in an expression.  Its branches may end in a bare expression,
+    // which is the branch's value.
+    fn parse_if_expr(&mut self) -> WhispemResult<Expr> {
+        let line = self.line(); self.advance();
+        let condition   = self.parse_expr()?;
+        let then_branch = self.parse_value_block()?;
+        self.skip_nl();
+        let else_branch = match self.cur().token {
+            Token::ElseIf => {
+                let line = self.line();
+                Some(vec![Stmt::Expression { expr: self.parse_if_expr()?, line }])
+            }
+            Token::Else => { self.advance(); Some(self.parse_value_block()?) }
+            _           => None,
+        };
+        Ok(Expr::If { condition: Box::new(condition), then_branch, else_branch, line })
+    }
+
+    fn parse_value_block(&mut self) -> WhispemResult<Vec<Stmt>> {
+        self.consume(Token::LeftBrace)?;
+        let mut stmts = Vec::new();
+        loop {
+            self.skip_nl();
+            if self.cur().token == Token::RightBrace { break; }
+            if self.cur().token == Token::Eof {
+                return Err(WhispemError::new(ErrorKind::UnexpectedEof, self.span()));
+            }
+            stmts.push(self.parse_value_item()?);
+        }
+        self.consume(Token::RightBrace)?;
+        Ok(stmts)
+    }
+
+    // A statement, or any expression standing on its own.
+    fn parse_value_item(&mut self) -> WhispemResult<Stmt> {
+        let named_fn = self.cur().token == Token::Fn
+            && matches!(self.tokens.get(self.position + 1).map(|t| &t.token), Some(Token::Identifier(_)));
+        let statement = matches!(self.cur().token,
+            Token::Let | Token::Print | Token::While | Token::For | Token::Return | Token::Yield
+            | Token::Break | Token::Continue | Token::Try | Token::Import | Token::Global | Token::Struct
+            | Token::Throw | Token::Assert | Token::Exit);
+        if statement || named_fn { return self.parse_stmt(); }
+        let line  = self.line();
+        let start = self.position;
+        let expr  = self.parse_expr()?;
+        if self.cur().token == Token::Equals || self.compound_op().is_some() {
+            self.position = start;
+            return self.parse_stmt();
+        }
+        Ok(Stmt::Expression { expr, line })
+    }
+
     fn parse_while(&mut self) -> WhispemResult<Stmt> {
         let line = self.line(); self.advance();
         let cond = self.parse_expr()?;
@@ -155,13 +240,67 @@ impl Parser {
 
     fn parse_for(&mut self) -> WhispemResult<Stmt> {
         let line = self.line(); self.advance();
-        let var  = self.consume_ident()?;
+        let var  = self.parse_binding()?;
         self.consume(Token::In)?;
         let iter = self.parse_expr()?;
         let body = self.parse_block()?;
         Ok(Stmt::For { variable: var, iterable: iter, body, line })
     }
 
+    fn parse_try(&mut self) -> WhispemResult<Stmt> {
+        let line = self.line(); self.advance();
+        let body = self.parse_block()?;
+        self.skip_nl();
+        self.consume(Token::Catch)?;
+        let error_name = self.consume_ident()?;
+        let handler    = self.parse_block()?;
+        Ok(Stmt::Try { body, error_name, handler, line })
+    }
+
+    fn parse_import(&mut self) -> WhispemResult<Stmt> {
+        let line = self.line(); self.advance();
+        let path = match &self.cur().token {
+            Token::Str(p) => p.clone(),
+            other => return Err(WhispemError::new(
+                ErrorKind::UnexpectedToken { expected: "module path string".to_string(), found: other.to_string() },
+                self.span(),
+            )),
+        };
+        self.advance();
+        let alias = if self.cur().token == Token::As {
+            self.advance();
+            Some(self.consume_ident()?)
+        } else {
+            None
+        };
+        Ok(Stmt::Import { path, alias, line })
+    }
+
+    // `global a, b`
+    fn parse_global(&mut self) -> WhispemResult<Stmt> {
+        let line = self.line(); self.advance();
+        let mut names = vec![self.consume_ident()?];
+        while self.cur().token == Token::Comma {
+            self.advance();
+            names.push(self.consume_ident()?);
+        }
+        Ok(Stmt::Global { names, line })
+    }
+
+    fn parse_struct(&mut self) -> WhispemResult<Stmt> {
+        let line = self.line(); self.advance();
+        let name = self.consume_ident()?;
+        self.consume(Token::LeftBrace)?;
+        let mut fields = Vec::new();
+        loop {
+            while matches!(self.cur().token, Token::Newline | Token::Comma) { self.advance(); }
+            if self.cur().token == Token::RightBrace { break; }
+            fields.push(self.consume_ident()?);
+        }
+        self.consume(Token::RightBrace)?;
+        Ok(Stmt::Struct { name, fields, line })
+    }
+
     fn parse_fn(&mut self) -> WhispemResult<Stmt> {
         let line   = self.line(); self.advance();
         let name   = self.consume_ident()?;
@@ -170,12 +309,37 @@ impl Parser {
         Ok(Stmt::Function { name, params, body, line })
     }
 
-    fn parse_param_list(&mut self) -> WhispemResult<Vec<String>> {
+    // `(a, b = default, ..rest)`.  Once one parameter has a default every
+    // later one needs one too, and the rest parameter comes last.
+    fn parse_param_list(&mut self) -> WhispemResult<Vec<Param>> {
         self.consume(Token::LParen)?;
-        let mut params = Vec::new();
+        let mut params: Vec<Param> = Vec::new();
         if self.cur().token != Token::RParen {
             loop {
-                params.push(self.consume_ident()?);
+                let span = self.span();
+                if self.cur().token == Token::Dot {
+                    self.advance();
+                    self.consume(Token::Dot)?;
+                    params.push(Param { name: self.consume_ident()?, default: None, rest: true });
+                    break;
+                }
+                let name    = self.consume_ident()?;
+                let default = if self.cur().token == Token::Equals {
+                    self.advance();
+                    Some(self.parse_expr()?)
+                } else {
+                    None
+                };
+                if default.is_none() && params.iter().any(|p| p.default.is_some()) {
+                    return Err(WhispemError::new(
+                        ErrorKind::UnexpectedToken {
+                            expected: format!("a default for '{}'", name),
+                            found:    "a required parameter after an optional one".to_string(),
+                        },
+                        span,
+                    ));
+                }
+                params.push(Param { name, default, rest: false });
                 if self.cur().token == Token::Comma { self.advance(); }
                 else { break; }
             }
@@ -194,17 +358,27 @@ impl Parser {
         Ok(Stmt::Return { value, line })
     }
 
+    fn parse_yield(&mut self) -> WhispemResult<Stmt> {
+        let line = self.line(); self.advance();
+        let value = if matches!(self.cur().token, Token::Newline | Token::RightBrace | Token::Eof) {
+            None
+        } else {
+            Some(self.parse_expr()?)
+        };
+        Ok(Stmt::Yield { value, line })
+    }
+
     fn parse_assert_stmt(&mut self) -> WhispemResult<Stmt> {
         let line = self.line(); self.advance();
-        let args = self.parse_call_args()?;
-        Ok(Stmt::Expression { expr: Expr::Call { name: "assert".to_string(), arguments: args, line }, line })
+        let (args, named) = self.parse_call_args()?;
+        Ok(Stmt::Expression { expr: Expr::Call { name: "assert".to_string(), arguments: args, named, line }, line })
     }
 
     fn parse_exit_stmt(&mut self) -> WhispemResult<Stmt> {
         let line = self.line(); self.advance();
-        let args = if self.cur().token == Token::LParen { self.parse_call_args()? }
-                   else { vec![] };
-        Ok(Stmt::Expression { expr: Expr::Call { name: "exit".to_string(), arguments: args, line }, line })
+        let (args, named) = if self.cur().token == Token::LParen { self.parse_call_args()? }
+                            else { (vec![], vec![]) };
+        Ok(Stmt::Expression { expr: Expr::Call { name: "exit".to_string(), arguments: args, named, line }, line })
     }
 
     fn parse_block(&mut self) -> WhispemResult<Vec<Stmt>> {
@@ -223,70 +397,122 @@ impl Parser {
     }
 
     fn parse_ident_stmt(&mut self) -> WhispemResult<Stmt> {
-        let line = self.line();
-        let name = self.consume_ident()?;
+        let line  = self.line();
+        let start = self.position;
+        let name  = self.consume_ident()?;
 
-        if self.cur().token == Token::LeftBracket {
+        if self.cur().token == Token::Equals || self.compound_op().is_some() {
+            let op = self.compound_op();
             self.advance();
-            let idx = self.parse_expr()?;
-            self.consume(Token::RightBracket)?;
-
-            if self.cur().token == Token::Equals {
-                self.advance();
-                let val = self.parse_expr()?;
-                return Ok(Stmt::IndexAssign { object: name, index: idx, value: val, line });
-            }
-
-            let obj     = Expr::Variable(name);
-            let indexed = Expr::Index { object: Box::new(obj), index: Box::new(idx) };
-            let mut e   = indexed;
-            while self.cur().token == Token::LParen || self.cur().token == Token::LeftBracket {
-                match self.cur().token {
-                    Token::LParen => {
-                        let args = self.parse_call_args()?;
-                        e = Expr::CallExpr { callee: Box::new(e), arguments: args, line };
-                    }
-                    Token::LeftBracket => {
-                        self.advance();
-                        let inner_idx = self.parse_expr()?;
-                        self.consume(Token::RightBracket)?;
-                        e = Expr::Index { object: Box::new(e), index: Box::new(inner_idx) };
-                    }
-                    _ => break,
-                }
-            }
-            return Ok(Stmt::Expression { expr: e, line });
+            let value = self.parse_expr()?;
+            return Ok(Stmt::Assign { target: Expr::Variable(name), op, value, line });
         }
 
-        if self.cur().token == Token::LParen {
-            let args = self.parse_call_args()?;
-            return Ok(Stmt::Expression { expr: Expr::Call { name, arguments: args, line }, line });
+        if matches!(self.cur().token, Token::Dot | Token::LeftBracket | Token::LParen) {
+            self.position = start;
+            let target = self.parse_postfix()?;
+            let op = match self.cur().token {
+                Token::Equals => None,
+                _ => match self.compound_op() {
+                    Some(op) => Some(op),
+                    None     => return Ok(Stmt::Expression { expr: target, line }),
+                },
+            };
+            if !Self::is_assignable(&target) {
+                return Err(WhispemError::new(
+                    ErrorKind::UnexpectedToken {
+                        expected: "a variable, index or field before the assignment".to_string(),
+                        found:    self.cur().token.to_string(),
+                    },
+                    self.span(),
+                ));
+            }
+            self.advance();
+            let value = self.parse_expr()?;
+            return Ok(Stmt::Assign { target, op, value, line });
         }
 
         Err(WhispemError::new(
             ErrorKind::UnexpectedToken {
-                expected: "'(' or '['".to_string(),
+                expected: "'(', '[', '.' or an assignment".to_string(),
                 found:    self.cur().token.to_string(),
             },
             self.span(),
         ))
     }
 
-    fn parse_call_args(&mut self) -> WhispemResult<Vec<Expr>> {
+    fn compound_op(&self) -> Option<BinaryOp> {
+        match self.cur().token {
+            Token::PlusEqual    => Some(BinaryOp::Add),
+            Token::MinusEqual   => Some(BinaryOp::Sub),
+            Token::StarEqual    => Some(BinaryOp::Mul),
+            Token::SlashEqual   => Some(BinaryOp::Div),
+            Token::PercentEqual => Some(BinaryOp::Mod),
+            _                   => None,
+        }
+    }
+
+    // A variable followed by any chain of `[index]` and `.field`.
+    fn is_assignable(target: &Expr) -> bool {
+        match target {
+            Expr::Variable(_) => true,
+            Expr::Index { object, .. } | Expr::Member { object, .. } => Self::is_assignable(object),
+            _ => false,
+        }
+    }
+
+    // Positional arguments, then any `name: value` ones.
+    fn parse_call_args(&mut self) -> WhispemResult<CallArgs> {
         self.consume(Token::LParen)?;
-        let mut args = Vec::new();
+        let mut args  = Vec::new();
+        let mut named = Vec::new();
         if self.cur().token != Token::RParen {
             loop {
-                args.push(self.parse_expr()?);
+                let span = self.span();
+                if self.at_named_arg() {
+                    let name = self.consume_ident()?;
+                    self.advance();
+                    named.push((name, self.parse_expr()?));
+                } else if named.is_empty() {
+                    args.push(self.parse_expr()?);
+                } else {
+                    return Err(WhispemError::new(
+                        ErrorKind::UnexpectedToken {
+                            expected: "a named argument".to_string(),
+                            found:    "a positional argument after a named one".to_string(),
+                        },
+                        span,
+                    ));
+                }
                 if self.cur().token == Token::Comma { self.advance(); }
                 else { break; }
             }
         }
         self.consume(Token::RParen)?;
-        Ok(args)
+        Ok((args, named))
     }
 
-    fn parse_expr(&mut self) -> WhispemResult<Expr> { self.parse_or() }
+    fn at_named_arg(&self) -> bool {
+        matches!(self.cur().token, Token::Identifier(_))
+            && self.tokens.get(self.position + 1).map(|t| &t.token) == Some(&Token::Colon)
+    }
+
+    fn parse_expr(&mut self) -> WhispemResult<Expr> { self.parse_conditional() }
+
+    // `cond ? a : b`, the loosest binding operator; it nests to the right.
+    fn parse_conditional(&mut self) -> WhispemResult<Expr> {
+        let condition = self.parse_or()?;
+        if self.cur().token != Token::Question { return Ok(condition); }
+        self.advance();
+        let then_value = self.parse_expr()?;
+        self.consume(Token::Colon)?;
+        let else_value = self.parse_conditional()?;
+        Ok(Expr::Conditional {
+            condition:  Box::new(condition),
+            then_value: Box::new(then_value),
+            else_value: Box::new(else_value),
+        })
+    }
 
     fn parse_or(&mut self) -> WhispemResult<Expr> {
         let mut e = self.parse_and()?;
@@ -346,10 +572,11 @@ impl Parser {
         let mut e = self.parse_unary()?;
         loop {
             let op = match self.cur().token {
-                Token::Star    => BinaryOp::Mul,
-                Token::Slash   => BinaryOp::Div,
-                Token::Percent => BinaryOp::Mod,
-                _              => break,
+                Token::Star       => BinaryOp::Mul,
+                Token::Slash      => BinaryOp::Div,
+                Token::SlashSlash => BinaryOp::IntDiv,
+                Token::Percent    => BinaryOp::Mod,
+                _                 => break,
             };
             self.advance();
             let r = self.parse_unary()?;
@@ -386,13 +613,18 @@ impl Parser {
                     let line = self.line();
                     if let Expr::Variable(ref name) = e {
                         let name = name.clone();
-                        let args = self.parse_call_args()?;
-                        e = Expr::Call { name, arguments: args, line };
+                        let (args, named) = self.parse_call_args()?;
+                        e = Expr::Call { name, arguments: args, named, line };
                     } else {
-                        let args = self.parse_call_args()?;
-                        e = Expr::CallExpr { callee: Box::new(e), arguments: args, line };
+                        let (args, named) = self.parse_call_args()?;
+                        e = Expr::CallExpr { callee: Box::new(e), arguments: args, named, line };
                     }
                 }
+                Token::Dot => {
+                    let line = self.line(); self.advance();
+                    let name = self.consume_ident()?;
+                    e = Expr::Member { object: Box::new(e), name, line };
+                }
                 _ => break,
             }
         }
@@ -402,6 +634,7 @@ impl Parser {
     fn parse_primary(&mut self) -> WhispemResult<Expr> {
         let s = self.cur().clone();
         match &s.token {
+            Token::Int(n)      => { let v = *n; self.advance(); Ok(Expr::Int(v)) }
             Token::Number(n)   => { let v = *n; self.advance(); Ok(Expr::Number(v)) }
             Token::Str(st)     => { let v = st.clone(); self.advance(); Ok(Expr::Str(v)) }
             Token::FStr(parts) => {
@@ -410,6 +643,7 @@ impl Parser {
             }
             Token::True  => { self.advance(); Ok(Expr::Bool(true)) }
             Token::False => { self.advance(); Ok(Expr::Bool(false)) }
+            Token::None  => { self.advance(); Ok(Expr::None) }
 
             Token::Fn => {
                 let line = s.line; self.advance();
@@ -417,6 +651,8 @@ impl Parser {
                 let body   = self.parse_block()?;
                 Ok(Expr::Lambda { params, body, line })
             }
+            Token::Match => self.parse_match(),
+            Token::If => self.parse_if_expr(),
 
             Token::Length    => { self.advance(); Ok(Expr::Variable("length".to_string())) }
             Token::Push      => { self.advance(); Ok(Expr::Variable("push".to_string())) }
@@ -479,10 +715,31 @@ impl Parser {
                 self.consume(Token::RightBrace)?;
                 Ok(Expr::Dict(pairs))
             }
+            Token::Identifier(name) if self.at_struct_literal() => {
+                let name = name.clone();
+                self.advance(); self.advance();
+                let mut fields = Vec::new();
+                loop {
+                    self.skip_nl();
+                    if self.cur().token == Token::RightBrace { break; }
+                    let field = self.consume_ident()?;
+                    self.consume(Token::Colon)?;
+                    fields.push((field, self.parse_expr()?));
+                    self.skip_nl();
+                    if self.cur().token == Token::Comma { self.advance(); }
+                    else { break; }
+                }
+                self.skip_nl();
+                self.consume(Token::RightBrace)?;
+                Ok(Expr::StructLit { name, fields, line: s.line })
+            }
             Token::Identifier(name) => { let v = name.clone(); self.advance(); Ok(Expr::Variable(v)) }
             Token::LParen => {
                 self.advance();
-                let e = self.parse_expr()?;
+                let outer = std::mem::replace(&mut self.no_struct_literal, false);
+                let e = self.parse_expr();
+                self.no_struct_literal = outer;
+                let e = e?;
                 self.consume(Token::RParen)?;
                 Ok(e)
             }
@@ -496,6 +753,166 @@ impl Parser {
         }
     }
 
+    fn parse_match(&mut self) -> WhispemResult<Expr> {
+        let line  = self.line(); self.advance();
+        let outer = std::mem::replace(&mut self.no_struct_literal, true);
+        let subject = self.parse_expr();
+        self.no_struct_literal = outer;
+        let subject = subject?;
+        self.consume(Token::LeftBrace)?;
+        let mut arms = Vec::new();
+        loop {
+            while matches!(self.cur().token, Token::Newline | Token::Comma) { self.advance(); }
+            if self.cur().token == Token::RightBrace { break; }
+            let pattern = self.parse_pattern()?;
+            let guard = if self.cur().token == Token::If {
+                self.advance();
+                Some(self.parse_expr()?)
+            } else {
+                None
+            };
+            self.consume(Token::FatArrow)?;
+            let body = if self.cur().token == Token::LeftBrace {
+                ArmBody::Block(self.parse_block()?)
+            } else {
+                ArmBody::Expr(self.parse_expr()?)
+            };
+            arms.push(MatchArm { pattern, guard, body });
+        }
+        self.consume(Token::RightBrace)?;
+        Ok(Expr::Match { subject: Box::new(subject), arms, line })
+    }
+
+    fn parse_pattern(&mut self) -> WhispemResult<Pattern> {
+        let tok = self.cur().token.clone();
+        match tok {
+            Token::Identifier(name) => {
+                self.advance();
+                if self.cur().token == Token::Colon {
+                    self.advance();
+                    let type_name = match &self.cur().token {
+                        Token::None => { self.advance(); "none".to_string() }
+                        _           => self.consume_ident()?,
+                    };
+                    Ok(Pattern::Typed { name, type_name })
+                } else if name == "_" {
+                    Ok(Pattern::Wildcard)
+                } else {
+                    Ok(Pattern::Bind(name))
+                }
+            }
+            Token::Int(n)    => { self.advance(); Ok(Pattern::Literal(Expr::Int(n))) }
+            Token::Number(n) => { self.advance(); Ok(Pattern::Literal(Expr::Number(n))) }
+            Token::Minus => {
+                self.advance();
+                match self.cur().token {
+                    Token::Int(n)    => { self.advance(); Ok(Pattern::Literal(Expr::Int(-n))) }
+                    Token::Number(n) => { self.advance(); Ok(Pattern::Literal(Expr::Number(-n))) }
+                    _ => Err(WhispemError::new(
+                        ErrorKind::UnexpectedToken { expected: "number".to_string(), found: self.cur().token.to_string() },
+                        self.span(),
+                    )),
+                }
+            }
+            Token::Str(st) => { self.advance(); Ok(Pattern::Literal(Expr::Str(st))) }
+            Token::True    => { self.advance(); Ok(Pattern::Literal(Expr::Bool(true))) }
+            Token::False   => { self.advance(); Ok(Pattern::Literal(Expr::Bool(false))) }
+            Token::None    => { self.advance(); Ok(Pattern::Literal(Expr::None)) }
+            Token::LeftBracket => self.parse_array_pattern(Self::parse_pattern),
+            Token::LeftBrace   => self.parse_dict_pattern(false),
+            other => Err(WhispemError::new(
+                ErrorKind::UnexpectedToken { expected: "pattern".to_string(), found: other.to_string() },
+                self.span(),
+            )),
+        }
+    }
+
+    // A `let` / `for` destructuring target: a name, `_`, `[a, ..rest]` or
+    // `{name, key: target, "key": target}`.  It holds no literals or type
+    // tests, so it always matches; a value of the wrong shape is an error.
+    fn parse_binding(&mut self) -> WhispemResult<Pattern> {
+        match self.cur().token.clone() {
+            Token::Identifier(name) => {
+                self.advance();
+                Ok(if name == "_" { Pattern::Wildcard } else { Pattern::Bind(name) })
+            }
+            Token::LeftBracket => self.parse_array_pattern(Self::parse_binding),
+            Token::LeftBrace   => self.parse_dict_pattern(true),
+            other => Err(WhispemError::new(
+                ErrorKind::UnexpectedToken { expected: "name, '[' or '{'".to_string(), found: other.to_string() },
+                self.span(),
+            )),
+        }
+    }
+
+    fn parse_array_pattern(
+        &mut self, element: fn(&mut Self) -> WhispemResult<Pattern>,
+    ) -> WhispemResult<Pattern> {
+        self.consume(Token::LeftBracket)?;
+        let mut elements = Vec::new();
+        let mut rest     = None;
+        loop {
+            self.skip_nl();
+            if self.cur().token == Token::RightBracket { break; }
+            if self.cur().token == Token::Dot {
+                self.advance();
+                self.consume(Token::Dot)?;
+                rest = Some(self.consume_ident()?);
+                self.skip_nl();
+                break;
+            }
+            elements.push(element(self)?);
+            self.skip_nl();
+            if self.cur().token == Token::Comma { self.advance(); }
+            else { break; }
+        }
+        self.consume(Token::RightBracket)?;
+        Ok(Pattern::Array { elements, rest })
+    }
+
+    // Match patterns key dicts by string; bindings also take `{name}`,
+    // short for `{"name": name}`, and `{name: target}`.
+    fn parse_dict_pattern(&mut self, binding: bool) -> WhispemResult<Pattern> {
+        self.consume(Token::LeftBrace)?;
+        let mut entries = Vec::new();
+        loop {
+            self.skip_nl();
+            if self.cur().token == Token::RightBrace { break; }
+            let (key, shorthand) = match &self.cur().token {
+                Token::Str(k)                  => (k.clone(), false),
+                Token::Identifier(k) if binding => (k.clone(), true),
+                other => return Err(WhispemError::new(
+                    ErrorKind::UnexpectedToken { expected: "string key".to_string(), found: other.to_string() },
+                    self.span(),
+                )),
+            };
+            self.advance();
+            let sub = if shorthand && self.cur().token != Token::Colon {
+                Pattern::Bind(key.clone())
+            } else {
+                self.consume(Token::Colon)?;
+                if binding { self.parse_binding()? } else { self.parse_pattern()? }
+            };
+            entries.push((key, sub));
+            self.skip_nl();
+            if self.cur().token == Token::Comma { self.advance(); }
+            else { break; }
+        }
+        self.consume(Token::RightBrace)?;
+        Ok(Pattern::Dict(entries))
+    }
+
+    // `Name { field: ...`: an identifier, a brace, then `ident :`.  The
+    // `ident :` lookahead keeps `if ready { ... }` a block.
+    fn at_struct_literal(&self) -> bool {
+        if self.no_struct_literal { return false; }
+        let tok = |k: usize| self.tokens.get(self.position + k).map(|t| &t.token);
+        if tok(1) != Some(&Token::LeftBrace) { return false; }
+        let mut k = 2;
+        while tok(k) == Some(&Token::Newline) { k += 1; }
+        matches!(tok(k), Some(Token::Identifier(_))) && tok(k + 1) == Some(&Token::Colon)
+    }
+
     fn desugar_fstr(&self, parts: Vec<TokenFStrPart>, line: usize) -> WhispemResult<Expr> {
         if parts.is_empty() { return Ok(Expr::Str(String::new())); }
 
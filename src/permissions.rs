@@ -0,0 +1,59 @@
+use std::path::{Path, PathBuf};
+
+/// Capabilities a script has outside the VM, checked by the builtins that
+/// touch the host (`read_file`, `write_file`, `write_hex`, `input`, `args`,
+/// `exit`).
+///
+/// `fs_read` / `fs_write` are `None` for unrestricted access, or the list
+/// of directories a path must resolve inside of.  `Some(vec![])` forbids
+/// the operation entirely.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Permissions {
+    pub fs_read:  Option<Vec<PathBuf>>,
+    pub fs_write: Option<Vec<PathBuf>>,
+    pub stdin:    bool,
+    pub args:     bool,
+    pub exit:     bool,
+}
+
+impl Permissions {
+    /// Everything allowed — the default for trusted scripts.
+    pub fn all() -> Self {
+        Self { fs_read: None, fs_write: None, stdin: true, args: true, exit: true }
+    }
+
+    /// Nothing allowed; grant capabilities back field by field.
+    pub fn sandboxed() -> Self {
+        Self { fs_read: Some(vec![]), fs_write: Some(vec![]), stdin: false, args: false, exit: false }
+    }
+
+    pub fn can_read(&self, path: &Path)  -> bool { allowed(path, &self.fs_read) }
+    pub fn can_write(&self, path: &Path) -> bool { allowed(path, &self.fs_write) }
+}
+
+impl Default for Permissions {
+    fn default() -> Self { Self::all() }
+}
+
+fn allowed(path: &Path, dirs: &Option<Vec<PathBuf>>) -> bool {
+    let Some(dirs) = dirs else { return true };
+    let Some(target) = resolve(path) else { return false };
+    dirs.iter()
+        .filter_map(|d| d.canonicalize().ok())
+        .any(|d| target.starts_with(d))
+}
+
+// Canonical form of `path`, so `..` and symlinks cannot escape an allowed
+// directory.  A file that does not exist yet (a write target) resolves
+// through its parent directory.  A dangling symlink is refused: writing
+// through it would create its target, wherever that is.
+fn resolve(path: &Path) -> Option<PathBuf> {
+    if let Ok(p) = path.canonicalize() { return Some(p); }
+    if path.symlink_metadata().is_ok() { return None; }
+    let name   = path.file_name()?;
+    let parent = match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p.to_path_buf(),
+        _                                    => PathBuf::from("."),
+    };
+    parent.canonicalize().ok().map(|p| p.join(name))
+}
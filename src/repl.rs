@@ -1,52 +1,55 @@
-use crate::compiler::Compiler;
-use crate::error::ErrorKind;
-use crate::lexer::Lexer;
-use crate::parser::Parser;
-use crate::vm::Vm;
+use whispem::{ErrorKind, Interpreter};
+
+use crate::RunOptions;
 use std::io::{self, Write};
 
-pub fn run_repl() {
+pub fn run_repl(options: RunOptions) {
     println!("Whispem v6.0.0 — REPL");
     println!("Type 'exit' or press Ctrl-D to quit.\n");
 
-    let mut vm = Vm::new();
+    let mut interp = Interpreter::new();
+    options.apply(interp.vm_mut());
+    let interrupt = interp.vm().interrupt_handle();
+    sigint::install(interrupt.clone());
 
+    let mut source = String::new();
     loop {
-        print!(">>> ");
+        print!("{}", if source.is_empty() { ">>> " } else { "... " });
         io::stdout().flush().unwrap();
 
         let mut line = String::new();
-        match io::stdin().read_line(&mut line) {
+        match sigint::read_line(&mut line) {
             Ok(0)  => { println!(); break; }
             Err(e) => { eprintln!("Input error: {}", e); break; }
             Ok(_)  => {}
         }
+        // Ctrl-C while typing dropped the partial line and any unfinished
+        // block; what was read since starts afresh.
+        if interrupt.take() { source.clear(); }
 
-        let trimmed = line.trim();
-        if trimmed == "exit" || trimmed == "quit" { break; }
-        if trimmed.is_empty() { continue; }
-
-        let mut source = line.clone();
-        if trimmed.ends_with('{') {
-            loop {
-                print!("... ");
-                io::stdout().flush().unwrap();
-                let mut cont = String::new();
-                match io::stdin().read_line(&mut cont) {
-                    Ok(0) | Err(_) => break,
-                    Ok(_)          => {}
-                }
-                source.push_str(&cont);
-                if cont.trim() == "}" { break; }
-            }
+        if source.is_empty() {
+            let trimmed = line.trim();
+            if trimmed == "exit" || trimmed == "quit" { break; }
+            if trimmed.is_empty() { continue; }
+            let open_block = trimmed.ends_with('{');
+            source = line;
+            if open_block { continue; }
+        } else {
+            source.push_str(&line);
+            if line.trim() != "}" { continue; }
         }
 
-        if let Err(e) = run_source(&source, &mut vm) {
+        let result = interp.compile(&std::mem::take(&mut source)).and_then(|program| {
+            for w in &program.warnings { eprintln!("{}", w); }
+            interp.run(&program)
+        });
+        if let Err(e) = result {
             match e.kind {
                 ErrorKind::Exit(code) => {
                     println!("Bye!");
                     std::process::exit(code as i32);
                 }
+                ErrorKind::Interrupted => eprintln!("Interrupted"),
                 _ => eprintln!("{}", e),
             }
         }
@@ -55,13 +58,57 @@ pub fn run_repl() {
     println!("Bye!");
 }
 
-fn run_source(source: &str, vm: &mut Vm) -> Result<(), crate::error::WhispemError> {
-    let mut lexer  = Lexer::new(source);
-    let tokens     = lexer.tokenize()?;
-    let mut parser = Parser::new(tokens);
-    let program    = parser.parse_program()?;
-    let compiler   = Compiler::new();
-    let (main_chunk, fn_chunks) = compiler.compile(program)?;
-    for (name, chunk) in fn_chunks { vm.functions.insert(name, chunk); }
-    vm.run(main_chunk)
-}
\ No newline at end of file
+// Ctrl-C handling.  SIGINT sets the VM's interrupt flag, which stops a
+// running script at its next check.  While the REPL is waiting for input
+// the handler also starts a fresh prompt, since the terminal has thrown
+// away the partly typed line.
+#[cfg(unix)]
+mod sigint {
+    use std::io;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::OnceLock;
+    use whispem::InterruptHandle;
+
+    static HANDLE:  OnceLock<InterruptHandle> = OnceLock::new();
+    static READING: AtomicBool = AtomicBool::new(false);
+
+    const SIGINT: i32 = 2;
+
+    extern "C" {
+        fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+        fn write(fd: i32, buf: *const u8, count: usize) -> isize;
+    }
+
+    extern "C" fn on_sigint(_: i32) {
+        if let Some(handle) = HANDLE.get() { handle.interrupt(); }
+        if READING.load(Ordering::SeqCst) {
+            let prompt = b"\n>>> ";
+            // SAFETY: write(2) is async-signal-safe and the buffer is static.
+            unsafe { write(1, prompt.as_ptr(), prompt.len()); }
+        }
+    }
+
+    pub fn install(handle: InterruptHandle) {
+        if HANDLE.set(handle).is_ok() {
+            // SAFETY: the handler only touches atomics and calls write(2).
+            unsafe { signal(SIGINT, on_sigint); }
+        }
+    }
+
+    pub fn read_line(buf: &mut String) -> io::Result<usize> {
+        READING.store(true, Ordering::SeqCst);
+        let result = io::stdin().read_line(buf);
+        READING.store(false, Ordering::SeqCst);
+        result
+    }
+}
+
+// Elsewhere Ctrl-C keeps its default behaviour and ends the process.
+#[cfg(not(unix))]
+mod sigint {
+    use std::io;
+    use whispem::InterruptHandle;
+
+    pub fn install(_: InterruptHandle) {}
+    pub fn read_line(buf: &mut String) -> io::Result<usize> { io::stdin().read_line(buf) }
+}
@@ -0,0 +1,49 @@
+//! The generator behind `random`, `random_int` and `seed`.
+//!
+//! SplitMix64: one `u64` of state and a few lines of arithmetic, so
+//! `vm/wvm.c` produces the same sequence from the same seed.
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self { Self { state: seed } }
+
+    /// Seeded from the clock, for scripts that never call `seed`.
+    pub fn from_time() -> Self {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64);
+        Self::new(nanos)
+    }
+
+    pub fn seed(&mut self, seed: u64) { self.state = seed; }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `[0, 1)`, from the top 53 bits.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Uniform in `[low, high]`; `low` must not exceed `high`.  Draws below
+    /// `2^64 mod span` are rejected so every value is equally likely.
+    pub fn int_in(&mut self, low: i64, high: i64) -> i64 {
+        let span = (high as i128 - low as i128 + 1) as u128;
+        if span > u64::MAX as u128 { return self.next_u64() as i64; }
+        let span      = span as u64;
+        let threshold = span.wrapping_neg() % span;
+        loop {
+            let x = self.next_u64();
+            if x >= threshold { return (low as i128 + (x % span) as i128) as i64; }
+        }
+    }
+}
@@ -7,14 +7,16 @@ pub enum FStrPart {
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     Let, Print, If, Else, ElseIf, While, For, In, And, Or, Not, Fn, Return, Break, Continue,
+    Try, Catch, Throw, Import, As, Struct, Match, Global, Yield,
     Length, Push, Pop, Reverse, Slice, Range, Input, ReadFile, WriteFile, Keys, Values, HasKey,
     CharAt, Substr, Ord, NumToStr, StrToNum, Args, WriteHex,
     Assert, TypeOf, Exit,
     Map, Filter, Reduce,
-    True, False, Identifier(String), Number(f64), Str(String), FStr(Vec<FStrPart>),
-    Plus, Minus, Star, Slash, Percent,
+    True, False, None, Identifier(String), Int(i64), Number(f64), Str(String), FStr(Vec<FStrPart>),
+    Plus, Minus, Star, Slash, SlashSlash, Percent,
+    PlusEqual, MinusEqual, StarEqual, SlashEqual, PercentEqual,
     Equals, EqualEqual, Bang, BangEqual, Less, LessEqual, Greater, GreaterEqual,
-    LParen, RParen, LeftBrace, RightBrace, LeftBracket, RightBracket, Comma, Colon,
+    LParen, RParen, LeftBrace, RightBrace, LeftBracket, RightBracket, Comma, Colon, Question, Dot, FatArrow,
     Newline, Eof,
 }
 
@@ -36,8 +38,18 @@ impl std::fmt::Display for Token {
             Token::Return        => "'return'".to_string(),
             Token::Break         => "'break'".to_string(),
             Token::Continue      => "'continue'".to_string(),
+            Token::Try           => "'try'".to_string(),
+            Token::Catch         => "'catch'".to_string(),
+            Token::Throw         => "'throw'".to_string(),
+            Token::Import        => "'import'".to_string(),
+            Token::As            => "'as'".to_string(),
+            Token::Struct        => "'struct'".to_string(),
+            Token::Match         => "'match'".to_string(),
+            Token::Global        => "'global'".to_string(),
+            Token::Yield         => "'yield'".to_string(),
             Token::True          => "'true'".to_string(),
             Token::False         => "'false'".to_string(),
+            Token::None          => "'none'".to_string(),
             Token::Assert        => "'assert'".to_string(),
             Token::TypeOf        => "'type_of'".to_string(),
             Token::Exit          => "'exit'".to_string(),
@@ -45,6 +57,7 @@ impl std::fmt::Display for Token {
             Token::Filter        => "'filter'".to_string(),
             Token::Reduce        => "'reduce'".to_string(),
             Token::Identifier(n) => format!("identifier '{}'", n),
+            Token::Int(n)        => format!("number '{}'", n),
             Token::Number(n)     => format!("number '{}'", n),
             Token::Str(s)        => format!("string \"{}\"", s),
             Token::FStr(_)       => "f-string".to_string(),
@@ -52,7 +65,13 @@ impl std::fmt::Display for Token {
             Token::Minus         => "'-'".to_string(),
             Token::Star          => "'*'".to_string(),
             Token::Slash         => "'/'".to_string(),
+            Token::SlashSlash    => "'//'".to_string(),
             Token::Percent       => "'%'".to_string(),
+            Token::PlusEqual     => "'+='".to_string(),
+            Token::MinusEqual    => "'-='".to_string(),
+            Token::StarEqual     => "'*='".to_string(),
+            Token::SlashEqual    => "'/='".to_string(),
+            Token::PercentEqual  => "'%='".to_string(),
             Token::Equals        => "'='".to_string(),
             Token::EqualEqual    => "'=='".to_string(),
             Token::Bang          => "'!'".to_string(),
@@ -69,6 +88,9 @@ impl std::fmt::Display for Token {
             Token::RightBracket  => "']'".to_string(),
             Token::Comma         => "','".to_string(),
             Token::Colon         => "':'".to_string(),
+            Token::Question      => "'?'".to_string(),
+            Token::Dot           => "'.'".to_string(),
+            Token::FatArrow      => "'=>'".to_string(),
             Token::Newline       => "newline".to_string(),
             Token::Eof           => "end of file".to_string(),
             Token::Length        => "'length'".to_string(),
@@ -4,6 +4,8 @@ use std::fmt;
 use std::rc::Rc;
 
 use crate::chunk::Chunk;
+use crate::error::WhispemResult;
+use crate::vm::{Generator, Vm};
 
 #[derive(Debug, Clone)]
 pub struct Upvalue(pub Box<Value>);
@@ -11,20 +13,142 @@ pub struct Upvalue(pub Box<Value>);
 impl Upvalue {
     pub fn new(val: Value) -> Self        { Upvalue(Box::new(val)) }
     pub fn get(&self)      -> &Value      { &self.0 }
-    pub fn set(&mut self, val: Value)     { self.0 = Box::new(val); }
+    pub fn set(&mut self, val: Value)     { *self.0 = val; }
+}
+
+/// Signature of a host function exposed to scripts with `Vm::register_native`.
+pub type NativeFn = dyn Fn(&mut Vm, Vec<Value>) -> WhispemResult<Value>;
+
+/// A host-defined function.  Called like any other function; the VM checks
+/// `arity` before invoking `func`.
+pub struct Native {
+    pub name:  String,
+    pub arity: usize,
+    pub func:  Box<NativeFn>,
+}
+
+impl fmt::Debug for Native {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Native({}/{})", self.name, self.arity)
+    }
+}
+
+/// A `struct` declaration: the type name and its field names, in order.
+#[derive(Debug, PartialEq)]
+pub struct StructDef {
+    pub name:   String,
+    pub fields: Vec<String>,
+}
+
+impl StructDef {
+    pub fn field_index(&self, field: &str) -> Option<usize> {
+        self.fields.iter().position(|f| f == field)
+    }
+}
+
+/// An instance of a declared struct.  `fields` follows `def.fields`.
+#[derive(Debug, Clone)]
+pub struct StructValue {
+    pub def:    Rc<StructDef>,
+    pub fields: Vec<Value>,
+}
+
+/// A `range(start, end, step)`.  Scripts see an array, but elements are
+/// computed when indexed or iterated, so a range of any length costs the same.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Range {
+    seq:      Progression,
+    // Elements skipped from the start of `seq`, after a `slice`.
+    offset:   usize,
+    len:      usize,
+    reversed: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Progression {
+    Int   { start: i64, step: i64 },
+    Float { start: f64, step: f64 },
+}
+
+impl Range {
+    /// Integers from `start` up to (or down to) `end`, exclusive.  `step`
+    /// must not be zero.
+    pub fn int(start: i64, end: i64, step: i64) -> Self {
+        let span = if step > 0 { end as i128 - start as i128 } else { start as i128 - end as i128 };
+        let stride = (step as i128).abs();
+        let len = if span <= 0 { 0 } else { (span + stride - 1) / stride };
+        Range { seq: Progression::Int { start, step }, offset: 0, len: len as usize, reversed: false }
+    }
+
+    /// Floats `start + i * step` that lie before `end`.  `step` must not be
+    /// zero; a NaN bound gives an empty range.
+    pub fn float(start: f64, end: f64, step: f64) -> Self {
+        let at     = |i: usize| if i == 0 { start } else { start + i as f64 * step };
+        let before = |i: usize| if step > 0.0 { at(i) < end } else { at(i) > end };
+        let estimate = ((end - start) / step).ceil();
+        let mut len = if estimate > 0.0 { estimate as usize } else { 0 };
+        // The division can round either way; settle the last element exactly.
+        while len > 0 && !before(len - 1) { len -= 1; }
+        if len < usize::MAX && before(len) { len += 1; }
+        Range { seq: Progression::Float { start, step }, offset: 0, len, reversed: false }
+    }
+
+    pub fn len(&self) -> usize     { self.len }
+    pub fn is_empty(&self) -> bool { self.len == 0 }
+
+    /// The same elements in the opposite order.
+    pub fn reversed(mut self) -> Self {
+        self.reversed = !self.reversed;
+        self
+    }
+
+    /// Elements `start..end`, which must lie within the range.
+    pub fn slice(mut self, start: usize, end: usize) -> Self {
+        debug_assert!(start <= end && end <= self.len);
+        self.offset += if self.reversed { self.len - end } else { start };
+        self.len = end - start;
+        self
+    }
+
+    pub fn get(&self, i: usize) -> Option<Value> {
+        if i >= self.len { return None; }
+        Some(self.nth(if self.reversed { self.len - 1 - i } else { i }))
+    }
+
+    pub fn iter(self) -> impl Iterator<Item = Value> {
+        (0..self.len).filter_map(move |i| self.get(i))
+    }
+
+    // Element `i` counting from `offset`, ignoring `reversed`.
+    fn nth(&self, i: usize) -> Value {
+        let i = i + self.offset;
+        match self.seq {
+            Progression::Int { start, step } => Value::Int((start as i128 + i as i128 * step as i128) as i64),
+            Progression::Float { start, .. } if i == 0 => Value::Number(start),
+            Progression::Float { start, step } => Value::Number(start + i as f64 * step),
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub enum Value {
+    Int(i64),
     Number(f64),
     Bool(bool),
     Str(String),
     Array(Rc<Vec<Value>>),
+    // An array whose elements are computed on demand; `type_of` is "array".
+    Range(Rc<Range>),
     Dict(Rc<HashMap<String, Value>>),
+    Struct(Rc<StructValue>),
     Closure {
         chunk:    Rc<Chunk>,
         upvalues: Vec<Rc<RefCell<Upvalue>>>,
     },
+    Native(Rc<Native>),
+    // Returned by a call to a function that `yield`s; `for` loops also
+    // step through arrays, strings and dicts with one.
+    Generator(Rc<RefCell<Generator>>),
     None,
 }
 
@@ -35,8 +159,34 @@ impl fmt::Display for Value {
 }
 
 impl Value {
+    /// The constant `MAKE_STRUCT` builds instances from: every field `none`.
+    pub fn struct_prototype(def: StructDef) -> Self {
+        let fields = vec![Value::None; def.fields.len()];
+        Value::Struct(Rc::new(StructValue { def: Rc::new(def), fields }))
+    }
+
+    /// The value as a float, for arithmetic that mixes ints and floats.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Int(i)    => Some(*i as f64),
+            Value::Number(n) => Some(*n),
+            _                => None,
+        }
+    }
+
+    /// The value as an exact integer: an int, or a float with no fraction
+    /// that fits an i64.
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Value::Int(i) => Some(*i),
+            Value::Number(n) if n.fract() == 0.0 && *n >= i64::MIN as f64 && *n < i64::MAX as f64 => Some(*n as i64),
+            _ => None,
+        }
+    }
+
     pub fn format(&self) -> String {
         match self {
+            Value::Int(i)    => i.to_string(),
             Value::Number(n) => {
                 if n.fract() == 0.0 && n.abs() < 1e15 { format!("{}", *n as i64) }
                 else { n.to_string() }
@@ -47,6 +197,10 @@ impl Value {
                 let parts: Vec<String> = elements.iter().map(|v| v.format()).collect();
                 format!("[{}]", parts.join(", "))
             }
+            Value::Range(r) => {
+                let parts: Vec<String> = r.iter().map(|v| v.format()).collect();
+                format!("[{}]", parts.join(", "))
+            }
             Value::Dict(map) => {
                 let mut parts: Vec<String> = map
                     .iter()
@@ -55,19 +209,33 @@ impl Value {
                 parts.sort();
                 format!("{{{}}}", parts.join(", "))
             }
+            Value::Struct(st) => {
+                let parts: Vec<String> = st.def.fields.iter().zip(&st.fields)
+                    .map(|(k, v)| format!("{}: {}", k, v.format()))
+                    .collect();
+                if parts.is_empty() { format!("{} {{}}", st.def.name) }
+                else                { format!("{} {{ {} }}", st.def.name, parts.join(", ")) }
+            }
             Value::Closure { chunk, .. } => format!("<fn {}>", chunk.name),
-            Value::None => String::new(),
+            Value::Native(n)             => format!("<native fn {}>", n.name),
+            Value::Generator(g)          => format!("<generator {}>", g.borrow().name()),
+            Value::None => "none".to_string(),
         }
     }
 
     pub fn type_name(&self) -> &'static str {
         match self {
+            Value::Int(_)       => "number",
             Value::Number(_)    => "number",
             Value::Bool(_)      => "bool",
             Value::Str(_)       => "string",
             Value::Array(_)     => "array",
+            Value::Range(_)     => "array",
             Value::Dict(_)      => "dict",
+            Value::Struct(_)    => "struct",
             Value::Closure {..} => "function",
+            Value::Native(_)    => "function",
+            Value::Generator(_) => "generator",
             Value::None         => "none",
         }
     }
@@ -75,11 +243,16 @@ impl Value {
     pub fn is_truthy(&self) -> bool {
         match self {
             Value::Bool(b)      => *b,
+            Value::Int(i)       => *i != 0,
             Value::Number(n)    => *n != 0.0,
             Value::Str(s)       => !s.is_empty(),
             Value::Array(a)     => !a.is_empty(),
+            Value::Range(r)     => !r.is_empty(),
             Value::Dict(d)      => !d.is_empty(),
+            Value::Struct(_)    => true,
             Value::Closure {..} => true,
+            Value::Native(_)    => true,
+            Value::Generator(_) => true,
             Value::None         => false,
         }
     }
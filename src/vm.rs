@@ -1,12 +1,20 @@
 use crate::chunk::Chunk;
 use crate::error::{ErrorKind, Span, WhispemError, WhispemResult};
+use crate::limits::{release, shallow_size, HeapMeter, Limits};
 use crate::opcode::OpCode;
-use crate::value::{Upvalue, Value};
+use crate::permissions::Permissions;
+use crate::rng::Rng;
+use crate::value::{Native, Range, StructValue, Upvalue, Value};
 use std::cell::RefCell;
+use std::cmp::Ordering;
 use std::collections::HashMap;
+use std::fmt;
 use std::fs;
-use std::io::{self, Write};
+use std::io::{self, BufRead, Write};
+use std::path::Path;
 use std::rc::Rc;
+use std::sync::atomic::{self, AtomicBool};
+use std::sync::Arc;
 
 struct CallFrame {
     chunk:         Rc<Chunk>,
@@ -14,11 +22,20 @@ struct CallFrame {
     locals:        HashMap<String, Value>,
     upvalues:      Vec<Rc<RefCell<Upvalue>>>,
     open_upvalues: HashMap<String, Rc<RefCell<Upvalue>>>,
+    // Parameters the caller left to their defaults, read by `HAS_ARG`.
+    missing:       Vec<bool>,
+    // Set while a generator runs this frame; its stack slots start at
+    // `stack_base`.
+    generator:     Option<Rc<RefCell<Generator>>>,
+    stack_base:    usize,
 }
 
 impl CallFrame {
     fn new(chunk: Rc<Chunk>, upvalues: Vec<Rc<RefCell<Upvalue>>>) -> Self {
-        Self { chunk, ip: 0, locals: HashMap::new(), upvalues, open_upvalues: HashMap::new() }
+        Self {
+            chunk, ip: 0, locals: HashMap::new(), upvalues, open_upvalues: HashMap::new(),
+            missing: Vec::new(), generator: None, stack_base: 0,
+        }
     }
 
     #[inline]
@@ -40,13 +57,122 @@ impl CallFrame {
     }
 }
 
+// An active `try` block: where its `catch` starts and the frame and stack
+// heights to unwind to when an error reaches it.
+struct Handler {
+    frame_depth: usize,
+    stack_len:   usize,
+    catch_ip:    usize,
+}
+
+/// What a `Value::Generator` steps through: the suspended frame of a
+/// function that `yield`s, or a `for` loop's position in an array, string
+/// or dict.
+pub struct Generator {
+    name:  String,
+    state: GenState,
+}
+
+enum GenState {
+    Suspended(Box<Suspended>),
+    Running,
+    Done,
+    Items { items: Rc<Vec<Value>>, next: usize },
+    Range { range: Range, next: usize },
+    // `next` is a byte offset into `text`.
+    Chars { text: String, next: usize },
+}
+
+// A generator's frame between resumptions, with the stack slots above its
+// base and its open `try` blocks (heights relative to that base).
+struct Suspended {
+    frame:    CallFrame,
+    stack:    Vec<Value>,
+    handlers: Vec<Handler>,
+}
+
+impl Generator {
+    fn new(name: &str, state: GenState) -> Rc<RefCell<Self>> {
+        Rc::new(RefCell::new(Self { name: name.to_string(), state }))
+    }
+
+    /// The generator function's name, or the type of value a `for` loop
+    /// is stepping through.
+    pub fn name(&self) -> &str { &self.name }
+
+    pub(crate) fn for_each_value(&self, mut f: impl FnMut(&Value)) {
+        match &self.state {
+            GenState::Suspended(s) => {
+                s.frame.locals.values().chain(&s.stack).for_each(&mut f);
+                for uv in s.frame.upvalues.iter().chain(s.frame.open_upvalues.values()) { f(&uv.borrow().0); }
+            }
+            GenState::Items { items, .. } => items.iter().for_each(f),
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Debug for Generator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Generator({})", self.name)
+    }
+}
+
+/// The target of `Vm::call`: a function looked up by name, or a function
+/// value (closure or native) handed to the host by a script.
+pub enum Callee {
+    Name(String),
+    Value(Value),
+}
+
+impl From<&str>   for Callee { fn from(name: &str)   -> Self { Callee::Name(name.to_string()) } }
+impl From<String> for Callee { fn from(name: String) -> Self { Callee::Name(name) } }
+impl From<Value>  for Callee { fn from(v: Value)     -> Self { Callee::Value(v) } }
+
+/// Stops a running script from another thread or a signal handler.
+///
+/// The VM polls the flag at backward jumps and calls; once it sees it set,
+/// execution aborts with `ErrorKind::Interrupted`, the flag is cleared and
+/// the `Vm` is ready for the next `run`.
+#[derive(Debug, Clone)]
+pub struct InterruptHandle(Arc<AtomicBool>);
+
+impl InterruptHandle {
+    /// Ask the VM to stop at its next check.
+    pub fn interrupt(&self) { self.0.store(true, atomic::Ordering::SeqCst); }
+
+    /// Clear a pending request and report whether there was one.
+    pub fn take(&self) -> bool { self.0.swap(false, atomic::Ordering::SeqCst) }
+}
+
 pub struct Vm {
     stack:           Vec<Value>,
     frames:          Vec<CallFrame>,
+    handlers:        Vec<Handler>,
     globals:         HashMap<String, Value>,
     pub functions:   HashMap<String, Chunk>,
     pub script_args: Vec<String>,
-    output:          Box<dyn Write + Send>,
+    pub permissions: Permissions,
+    pub limits:      Limits,
+    natives:         HashMap<String, Rc<Native>>,
+    output:          Box<dyn Write>,
+    // `None` reads straight from the process's stdin.
+    input:           Option<Box<dyn BufRead>>,
+    steps:           u64,
+    // Heap bytes as of the last full measurement plus everything built since.
+    heap_estimate:   usize,
+    // Nesting of `invoke_closure`, which recurses on the Rust stack.
+    reentry:         usize,
+    interrupt:       InterruptHandle,
+    rng:             Rng,
+}
+
+impl Drop for Vm {
+    fn drop(&mut self) {
+        let locals = self.frames.drain(..).flat_map(|f| f.locals.into_values());
+        let values: Vec<Value> = locals.chain(self.stack.drain(..)).chain(self.globals.drain().map(|(_, v)| v)).collect();
+        release(values);
+    }
 }
 
 impl Vm {
@@ -54,36 +180,131 @@ impl Vm {
         Self {
             stack:       Vec::with_capacity(256),
             frames:      Vec::with_capacity(64),
+            handlers:    Vec::new(),
             globals:     HashMap::new(),
             functions:   HashMap::new(),
             script_args: Vec::new(),
+            permissions: Permissions::all(),
+            limits:      Limits::none(),
+            natives:     HashMap::new(),
             output:      Box::new(io::stdout()),
+            input:       None,
+            steps:       0,
+            heap_estimate: 0,
+            reentry:     0,
+            interrupt:   InterruptHandle(Arc::new(AtomicBool::new(false))),
+            rng:         Rng::from_time(),
         }
     }
 
-    #[cfg(test)]
-    pub fn capturing(buf: std::sync::Arc<std::sync::Mutex<Vec<u8>>>) -> Self {
-        struct ArcWriter(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
-        impl Write for ArcWriter {
-            fn write(&mut self, data: &[u8]) -> io::Result<usize> {
-                self.0.lock().unwrap().extend_from_slice(data); Ok(data.len())
-            }
-            fn flush(&mut self) -> io::Result<()> { Ok(()) }
+    /// A VM whose `print` output and `input` prompts go to `output` and
+    /// whose `input` reads lines from `input` instead of the process's
+    /// stdin/stdout.
+    pub fn with_io(output: impl Write + 'static, input: impl BufRead + 'static) -> Self {
+        let mut vm = Self::new();
+        vm.output = Box::new(output);
+        vm.input  = Some(Box::new(input));
+        vm
+    }
+
+    /// Expose a host function to scripts under `name`.  It is callable by
+    /// name (`name(a, b)`) and loadable as a value (`let f = name`); script
+    /// functions with the same name take precedence.
+    pub fn register_native(
+        &mut self,
+        name:  &str,
+        arity: usize,
+        func:  impl Fn(&mut Vm, Vec<Value>) -> WhispemResult<Value> + 'static,
+    ) {
+        let native = Native { name: name.to_string(), arity, func: Box::new(func) };
+        self.natives.insert(name.to_string(), Rc::new(native));
+    }
+
+    /// A handle that can stop this VM's current (or next) execution from
+    /// elsewhere.
+    pub fn interrupt_handle(&self) -> InterruptHandle { self.interrupt.clone() }
+
+    pub fn run(&mut self, main_chunk: Chunk) -> WhispemResult<()> {
+        let (depth, stack_len) = (self.frames.len(), self.stack.len());
+        if depth == 0 { self.steps = 0; }
+        self.push_frame(CallFrame::new(Rc::new(main_chunk), vec![]))?;
+        let result = self.run_until(depth);
+        if result.is_err() { self.unwind_to(depth, stack_len); }
+        result
+    }
+
+    /// Call a script function, closure, native or builtin from the host and
+    /// return its result.  Usable once `run` has finished: globals and
+    /// functions loaded by earlier runs stay in place, and a failed call
+    /// leaves the `Vm` ready for the next one.
+    pub fn call(&mut self, callee: impl Into<Callee>, args: Vec<Value>) -> WhispemResult<Value> {
+        // Host calls run on top of a placeholder frame so the callee gets
+        // function-local storage, exactly as when called from a script.
+        let host_frame = self.frames.is_empty();
+        if host_frame {
+            self.steps = 0;
+            self.frames.push(CallFrame::new(Rc::new(Chunk::new("<host>")), vec![]));
         }
-        unsafe impl Send for ArcWriter {}
-        Self {
-            stack:       Vec::with_capacity(256),
-            frames:      Vec::with_capacity(64),
-            globals:     HashMap::new(),
-            functions:   HashMap::new(),
-            script_args: Vec::new(),
-            output:      Box::new(ArcWriter(buf)),
+        let (depth, stack_len) = (self.frames.len(), self.stack.len());
+
+        let result = match callee.into() {
+            Callee::Value(v)   => self.invoke_closure(v, args),
+            Callee::Name(name) => self.call_by_name(&name, args),
+        };
+
+        if result.is_err() { self.unwind_to(depth, stack_len); }
+        if host_frame { self.frames.pop(); }
+        result
+    }
+
+    fn call_by_name(&mut self, name: &str, args: Vec<Value>) -> WhispemResult<Value> {
+        if !self.overrides_builtin(name) {
+            if let Some(v) = self.call_builtin(name, args.clone())? { return Ok(v); }
         }
+        let f = self.resolve_function(name)?;
+        self.invoke_closure(f, args)
     }
 
-    pub fn run(&mut self, main_chunk: Chunk) -> WhispemResult<()> {
-        self.frames.push(CallFrame::new(Rc::new(main_chunk), vec![]));
-        self.execute()
+    // Whether the script or the host binds `name` to a function of its own,
+    // which then takes precedence over a builtin of the same name.
+    fn overrides_builtin(&self, name: &str) -> bool {
+        matches!(self.lookup_local(name), Some(Value::Closure { .. }))
+            || self.functions.contains_key(name)
+            || self.natives.contains_key(name)
+    }
+
+    // Look up a callable by name the way `CALL` does (after builtins).
+    fn resolve_function(&self, name: &str) -> WhispemResult<Value> {
+        if let Some(v @ (Value::Closure { .. } | Value::Native(_))) = self.lookup_local(name) {
+            return Ok(v);
+        }
+        if let Some(chunk) = self.functions.get(name) {
+            return Ok(Value::Closure { chunk: Rc::new(chunk.clone()), upvalues: vec![] });
+        }
+        self.native_value(name).ok_or_else(|| {
+            WhispemError::new(ErrorKind::UndefinedFunction(name.to_string()), Span::new(self.frame().current_line(), 0))
+        })
+    }
+
+    // Drop the frames and stack slots left behind by an aborted execution.
+    fn unwind_to(&mut self, depth: usize, stack_len: usize) {
+        self.truncate_frames(depth);
+        self.stack.truncate(stack_len);
+        self.drop_handlers_above(depth);
+    }
+
+    // Drop the frames above `depth`.  A generator whose frame is among them
+    // ended with an error and is finished.
+    fn truncate_frames(&mut self, depth: usize) {
+        if depth >= self.frames.len() { return; }
+        for frame in self.frames.drain(depth..) {
+            if let Some(gen) = frame.generator { gen.borrow_mut().state = GenState::Done; }
+        }
+    }
+
+    // Forget `try` blocks belonging to frames that no longer exist.
+    fn drop_handlers_above(&mut self, depth: usize) {
+        while self.handlers.last().is_some_and(|h| h.frame_depth > depth) { self.handlers.pop(); }
     }
 
     // Push a closure frame and run until it returns.  Returns the result value.
@@ -91,23 +312,20 @@ impl Vm {
     fn invoke_closure(&mut self, callee: Value, args: Vec<Value>) -> WhispemResult<Value> {
         match callee {
             Value::Closure { chunk, upvalues } => {
-                if args.len() != chunk.param_count {
-                    return Err(WhispemError::new(
-                        ErrorKind::ArgumentCount {
-                            name:     chunk.name.clone(),
-                            expected: chunk.param_count,
-                            got:      args.len(),
-                        },
-                        Span::new(self.frame().current_line(), 0),
-                    ));
-                }
+                let (args, missing) = self.bind_args(&chunk, args, Vec::new())?;
+                self.check_reentry()?;
                 let target_depth = self.frames.len();
-                let new_frame    = CallFrame::new(chunk, upvalues);
+                let mut new_frame = CallFrame::new(chunk, upvalues);
+                new_frame.missing = missing;
                 for arg in args { self.stack.push(arg); }
-                self.frames.push(new_frame);
-                self.run_until(target_depth)?;
+                self.push_frame(new_frame)?;
+                self.reentry += 1;
+                let result = self.run_until(target_depth);
+                self.reentry -= 1;
+                result?;
                 self.pop()
             }
+            Value::Native(native) => self.call_native(native, args),
             other => Err(WhispemError::new(
                 ErrorKind::TypeError { expected: "function".to_string(), found: other.type_name().to_string() },
                 Span::new(self.frame().current_line(), 0),
@@ -115,50 +333,166 @@ impl Vm {
         }
     }
 
-    // Run the dispatch loop until the frame stack shrinks back to `target_depth`.
+    // Run the dispatch loop until the frame stack shrinks back to `target_depth`
+    // (or `HALT` ends the main chunk).  Errors raised inside a `try` opened
+    // by this loop are caught here; anything else propagates to the caller.
     fn run_until(&mut self, target_depth: usize) -> WhispemResult<()> {
         loop {
             if self.frames.len() == target_depth { return Ok(()); }
-            let byte = self.frame_mut().read_byte();
-            let op   = OpCode::from_byte(byte).ok_or_else(|| {
-                WhispemError::new(ErrorKind::InvalidOpcode(byte), Span::new(self.frame().current_line(), 0))
-            })?;
-            match op {
-                OpCode::Return => {
-                    let val = self.pop()?;
-                    self.frames.pop();
-                    self.stack.push(val);
-                    if self.frames.len() == target_depth { return Ok(()); }
-                }
-                OpCode::ReturnNone => {
-                    self.frames.pop();
-                    self.stack.push(Value::None);
-                    if self.frames.len() == target_depth { return Ok(()); }
-                }
-                OpCode::Halt => {
-                    if !self.frames.is_empty() { self.frames.pop(); }
-                    return Ok(());
-                }
-                _ => self.step(op)?,
+            match self.dispatch(target_depth) {
+                Ok(true)  => return Ok(()),
+                Ok(false) => {}
+                Err(e)    => self.catch(e, target_depth)?,
             }
         }
     }
 
-    fn execute(&mut self) -> WhispemResult<()> {
-        loop {
-            let byte = self.frame_mut().read_byte();
-            let op   = OpCode::from_byte(byte).ok_or_else(|| {
-                WhispemError::new(ErrorKind::InvalidOpcode(byte), Span::new(self.frame().current_line(), 0))
-            })?;
-            match op {
-                OpCode::Return     => { let val = self.pop()?; self.frames.pop(); self.stack.push(val); }
-                OpCode::ReturnNone => { self.frames.pop(); self.stack.push(Value::None); }
-                OpCode::Halt       => { self.frames.pop(); return Ok(()); }
-                _                  => self.step(op)?,
+    // Execute one instruction.  Returns `true` once execution is finished.
+    fn dispatch(&mut self, target_depth: usize) -> WhispemResult<bool> {
+        self.tick()?;
+        let byte = self.frame_mut().read_byte();
+        let op   = OpCode::from_byte(byte).ok_or_else(|| {
+            WhispemError::new(ErrorKind::InvalidOpcode(byte), Span::new(self.frame().current_line(), 0))
+        })?;
+        match op {
+            OpCode::Return => {
+                let val = self.pop()?;
+                self.return_value(val);
+                Ok(self.frames.len() == target_depth)
+            }
+            OpCode::ReturnNone => {
+                self.return_value(Value::None);
+                Ok(self.frames.len() == target_depth)
+            }
+            OpCode::MakeGenerator => {
+                // The call returns the generator; its body runs on `next`.
+                let frame = self.frames.pop().expect("empty call stack");
+                let name  = frame.chunk.name.clone();
+                let state = GenState::Suspended(Box::new(Suspended { frame, stack: Vec::new(), handlers: Vec::new() }));
+                self.stack.push(Value::Generator(Generator::new(&name, state)));
+                Ok(self.frames.len() == target_depth)
             }
+            OpCode::Yield => {
+                self.suspend()?;
+                Ok(self.frames.len() == target_depth)
+            }
+            OpCode::Halt => {
+                self.frames.pop();
+                Ok(true)
+            }
+            _ => { self.step(op)?; Ok(false) }
         }
     }
 
+    // Pop the returning frame and push its result for the caller.  A
+    // generator's frame reports that it is exhausted instead.
+    fn return_value(&mut self, val: Value) {
+        let frame = self.frames.pop().expect("empty call stack");
+        self.drop_handlers_above(self.frames.len());
+        match frame.generator {
+            Some(gen) => {
+                gen.borrow_mut().state = GenState::Done;
+                self.stack.push(Value::Bool(false));
+            }
+            None => self.stack.push(val),
+        }
+    }
+
+    // `YIELD`: park the running generator's frame with its stack slots and
+    // `try` blocks, and hand the value and `true` to whoever resumed it.
+    fn suspend(&mut self) -> WhispemResult<()> {
+        let val = self.pop()?;
+        if self.frame().generator.is_none() {
+            return Err(WhispemError::new(
+                ErrorKind::InvalidBytecode("YIELD outside a generator".to_string()),
+                Span::new(self.frame().current_line(), 0),
+            ));
+        }
+        let mut frame = self.frames.pop().expect("empty call stack");
+        let gen   = frame.generator.take().unwrap();
+        let base  = frame.stack_base;
+        let depth = self.frames.len();
+        let mut handlers = Vec::new();
+        while self.handlers.last().is_some_and(|h| h.frame_depth > depth) {
+            let mut h = self.handlers.pop().unwrap();
+            h.stack_len -= base;
+            handlers.push(h);
+        }
+        handlers.reverse();
+        let stack = self.stack.split_off(base);
+        gen.borrow_mut().state = GenState::Suspended(Box::new(Suspended { frame, stack, handlers }));
+        self.stack.push(val);
+        self.stack.push(Value::Bool(true));
+        Ok(())
+    }
+
+    // `ITER_NEXT` and `next`: push the generator's next item and `true`, or
+    // `false` once it is exhausted.  A suspended frame is pushed back
+    // instead, and pushes the same when it next yields or returns.
+    fn resume(&mut self, gen: Rc<RefCell<Generator>>) -> WhispemResult<()> {
+        let mut g = gen.borrow_mut();
+        let item = match &mut g.state {
+            GenState::Items { items, next } => {
+                let item = items.get(*next).cloned();
+                if item.is_some() { *next += 1; }
+                item
+            }
+            GenState::Range { range, next } => {
+                let item = range.get(*next);
+                if item.is_some() { *next += 1; }
+                item
+            }
+            GenState::Chars { text, next } => {
+                let c = text[*next..].chars().next();
+                if let Some(c) = c { *next += c.len_utf8(); }
+                c.map(|c| Value::Str(c.to_string()))
+            }
+            GenState::Done => None,
+            GenState::Running => {
+                return Err(WhispemError::new(
+                    ErrorKind::GeneratorRunning(g.name.clone()), Span::new(self.frame().current_line(), 0),
+                ));
+            }
+            GenState::Suspended(_) => {
+                let GenState::Suspended(s) = std::mem::replace(&mut g.state, GenState::Running) else { unreachable!() };
+                drop(g);
+                let Suspended { mut frame, stack, handlers } = *s;
+                let base = self.stack.len();
+                frame.stack_base = base;
+                frame.generator  = Some(Rc::clone(&gen));
+                if let Err(e) = self.push_frame(frame) {
+                    gen.borrow_mut().state = GenState::Done;
+                    return Err(e);
+                }
+                let depth = self.frames.len();
+                self.stack.extend(stack);
+                self.handlers.extend(handlers.into_iter().map(|h| Handler { frame_depth: depth, stack_len: base + h.stack_len, ..h }));
+                return Ok(());
+            }
+        };
+        match item {
+            Some(v) => { self.stack.push(v); self.stack.push(Value::Bool(true)); }
+            None    => self.stack.push(Value::Bool(false)),
+        }
+        Ok(())
+    }
+
+    // Hand `e` to the innermost `try` opened above `target_depth`: unwind to
+    // it and jump to its `catch` with the error dict on the stack.
+    fn catch(&mut self, e: WhispemError, target_depth: usize) -> WhispemResult<()> {
+        match self.handlers.last() {
+            Some(h) if h.frame_depth > target_depth && e.kind.is_catchable() => {}
+            _ => return Err(e),
+        }
+        let line = if e.span.is_known() { e.span.line } else { self.frames.last().map_or(0, |f| f.current_line()) };
+        let h    = self.handlers.pop().unwrap();
+        self.truncate_frames(h.frame_depth);
+        self.stack.truncate(h.stack_len);
+        self.stack.push(error_value(e.kind, line));
+        self.frame_mut().ip = h.catch_ip;
+        Ok(())
+    }
+
     fn step(&mut self, op: OpCode) -> WhispemResult<()> {
         match op {
             OpCode::PushConst => {
@@ -173,17 +507,23 @@ impl Vm {
             OpCode::Load => {
                 let idx  = self.frame_mut().read_byte();
                 let name = self.const_str(idx);
-                let val  = self.lookup_local(&name).ok_or_else(|| {
-                    WhispemError::new(ErrorKind::UndefinedVariable(name.clone()), Span::new(self.frame().current_line(), 0))
-                })?;
+                let val  = self.lookup_local(&name)
+                    .or_else(|| self.native_value(&name))
+                    .or_else(|| builtin_constant(&name))
+                    .ok_or_else(|| WhispemError::new(
+                        ErrorKind::UndefinedVariable(name.clone()), Span::new(self.frame().current_line(), 0),
+                    ))?;
                 self.stack.push(val);
             }
             OpCode::LoadGlobal => {
                 let idx  = self.frame_mut().read_byte();
                 let name = self.const_str(idx);
-                let val  = self.globals.get(&name).cloned().ok_or_else(|| {
-                    WhispemError::new(ErrorKind::UndefinedVariable(name.clone()), Span::new(self.frame().current_line(), 0))
-                })?;
+                let val  = self.globals.get(&name).cloned()
+                    .or_else(|| self.native_value(&name))
+                    .or_else(|| builtin_constant(&name))
+                    .ok_or_else(|| WhispemError::new(
+                        ErrorKind::UndefinedVariable(name.clone()), Span::new(self.frame().current_line(), 0),
+                    ))?;
                 self.stack.push(val);
             }
             OpCode::Store => {
@@ -192,6 +532,12 @@ impl Vm {
                 let val  = self.pop()?;
                 self.store(name, val);
             }
+            OpCode::StoreGlobal => {
+                let idx  = self.frame_mut().read_byte();
+                let name = self.const_str(idx);
+                let val  = self.pop()?;
+                self.globals.insert(name, val);
+            }
             OpCode::LoadUpvalue => {
                 let slot = self.frame_mut().read_byte() as usize;
                 let val  = self.load_upvalue(slot)?;
@@ -202,16 +548,30 @@ impl Vm {
                 let val  = self.pop()?;
                 self.store_upvalue(slot, val)?;
             }
-            OpCode::CloseUpvalue => { self.frame_mut().read_byte(); }
+            OpCode::CloseUpvalue => {
+                // End of a block: closures keep the variable's cell, and the
+                // next binding of the name starts a fresh one.
+                let idx  = self.frame_mut().read_byte();
+                let name = self.const_str(idx);
+                let top_level = self.frames.len() == 1;
+                let frame = self.frame_mut();
+                frame.open_upvalues.remove(&name);
+                if top_level { self.globals.remove(&name); } else { frame.locals.remove(&name); }
+            }
 
-            OpCode::Add => { let (a, b) = self.pop2()?; let r = self.add(a, b)?;               self.stack.push(r); }
-            OpCode::Sub => { let (a, b) = self.pop2()?; let r = self.numeric(a, b, |x,y| x-y)?; self.stack.push(r); }
-            OpCode::Mul => { let (a, b) = self.pop2()?; let r = self.numeric(a, b, |x,y| x*y)?; self.stack.push(r); }
-            OpCode::Div => { let (a, b) = self.pop2()?; let r = self.divmod(a, b, false)?;       self.stack.push(r); }
-            OpCode::Mod => { let (a, b) = self.pop2()?; let r = self.divmod(a, b, true)?;        self.stack.push(r); }
+            OpCode::Add => { let (a, b) = self.pop2()?; let r = self.add(a, b)?;               self.push_alloc(r)?; }
+            OpCode::Sub    => { let (a, b) = self.pop2()?; let r = self.numeric(a, b, "-", i64::checked_sub, |x,y| x-y)?; self.stack.push(r); }
+            OpCode::Mul    => { let (a, b) = self.pop2()?; let r = self.numeric(a, b, "*", i64::checked_mul, |x,y| x*y)?; self.stack.push(r); }
+            OpCode::Div    => { let (a, b) = self.pop2()?; let r = self.divmod(a, b, op)?; self.stack.push(r); }
+            OpCode::IntDiv => { let (a, b) = self.pop2()?; let r = self.divmod(a, b, op)?; self.stack.push(r); }
+            OpCode::Mod    => { let (a, b) = self.pop2()?; let r = self.divmod(a, b, op)?; self.stack.push(r); }
             OpCode::Neg => {
                 let a = self.pop()?;
                 match a {
+                    Value::Int(i)    => {
+                        let n = i.checked_neg().ok_or_else(|| int_overflow(format!("-({})", i)))?;
+                        self.stack.push(Value::Int(n));
+                    }
                     Value::Number(n) => self.stack.push(Value::Number(-n)),
                     other            => return Err(self.type_err("number", other.type_name())),
                 }
@@ -219,14 +579,15 @@ impl Vm {
 
             OpCode::Eq  => { let (a, b) = self.pop2()?; self.stack.push(Value::Bool( self.eq_val(&a, &b))); }
             OpCode::Neq => { let (a, b) = self.pop2()?; self.stack.push(Value::Bool(!self.eq_val(&a, &b))); }
-            OpCode::Lt  => { let (a, b) = self.pop2()?; let r = self.cmp(a, b, |x,y| x< y, |x,y| x< y)?; self.stack.push(r); }
-            OpCode::Lte => { let (a, b) = self.pop2()?; let r = self.cmp(a, b, |x,y| x<=y, |x,y| x<=y)?; self.stack.push(r); }
-            OpCode::Gt  => { let (a, b) = self.pop2()?; let r = self.cmp(a, b, |x,y| x> y, |x,y| x> y)?; self.stack.push(r); }
-            OpCode::Gte => { let (a, b) = self.pop2()?; let r = self.cmp(a, b, |x,y| x>=y, |x,y| x>=y)?; self.stack.push(r); }
+            OpCode::Lt  => { let (a, b) = self.pop2()?; let r = self.cmp(a, b, Ordering::is_lt)?; self.stack.push(r); }
+            OpCode::Lte => { let (a, b) = self.pop2()?; let r = self.cmp(a, b, Ordering::is_le)?; self.stack.push(r); }
+            OpCode::Gt  => { let (a, b) = self.pop2()?; let r = self.cmp(a, b, Ordering::is_gt)?; self.stack.push(r); }
+            OpCode::Gte => { let (a, b) = self.pop2()?; let r = self.cmp(a, b, Ordering::is_ge)?; self.stack.push(r); }
             OpCode::Not => { let a = self.pop()?; self.stack.push(Value::Bool(!a.is_truthy())); }
 
             OpCode::Jump => {
                 let target = self.frame_mut().read_u16() as usize;
+                if target < self.frame().ip { self.check_interrupt()?; }
                 self.frame_mut().ip = target;
             }
             OpCode::JumpIfFalse => {
@@ -303,51 +664,40 @@ impl Vm {
                 let name_idx = self.frame_mut().read_byte();
                 let argc     = self.frame_mut().read_byte() as usize;
                 let name     = self.const_str(name_idx);
-
-                let mut args: Vec<Value> = (0..argc)
-                    .map(|_| self.pop())
-                    .collect::<WhispemResult<_>>()?;
-                args.reverse();
-
-                if name == "__callee__" {
-                    let callee = self.pop()?;
-                    self.push_call_frame(callee, args, argc)?;
-                    return Ok(());
-                }
-
-                if let Some(result) = self.call_builtin(&name, args.clone())? {
-                    self.stack.push(result);
-                    return Ok(());
-                }
-
-                if let Some(closure_val) = self.lookup_local(&name) {
-                    if matches!(closure_val, Value::Closure { .. }) {
-                        self.push_call_frame(closure_val, args, argc)?;
-                        return Ok(());
-                    }
-                }
-
-                let chunk = self.functions.get(&name).cloned().ok_or_else(|| {
-                    WhispemError::new(ErrorKind::UndefinedFunction(name.clone()), Span::new(self.frame().current_line(), 0))
-                })?;
-
-                if argc != chunk.param_count {
-                    return Err(WhispemError::new(
-                        ErrorKind::ArgumentCount { name: name.clone(), expected: chunk.param_count, got: argc },
-                        Span::new(self.frame().current_line(), 0),
-                    ));
+                let args     = self.pop_args(argc)?;
+                self.call_value(name, args, Vec::new())?;
+            }
+            OpCode::CallNamed => {
+                let name_idx = self.frame_mut().read_byte();
+                let argc     = self.frame_mut().read_byte() as usize;
+                let count    = self.frame_mut().read_byte() as usize;
+                let name     = self.const_str(name_idx);
+                let mut named = Vec::with_capacity(count);
+                for _ in 0..count {
+                    let value = self.pop()?;
+                    let param = match self.pop()? {
+                        Value::Str(s) => s,
+                        _ => return Err(WhispemError::runtime(ErrorKind::InvalidBytecode(
+                            "CALL_NAMED argument name is not a string".to_string(),
+                        ))),
+                    };
+                    named.push((param, value));
                 }
-
-                let new_frame = CallFrame::new(Rc::new(chunk), vec![]);
-                for arg in args { self.stack.push(arg); }
-                self.frames.push(new_frame);
+                named.reverse();
+                let args = self.pop_args(argc)?;
+                self.call_value(name, args, named)?;
+            }
+            OpCode::HasArg => {
+                let i = self.frame_mut().read_byte() as usize;
+                let given = !self.frame().missing.get(i).copied().unwrap_or(false);
+                self.stack.push(Value::Bool(given));
             }
 
             OpCode::MakeArray => {
                 let n = self.frame_mut().read_byte() as usize;
                 let mut elems: Vec<Value> = (0..n).map(|_| self.pop()).collect::<WhispemResult<_>>()?;
                 elems.reverse();
-                self.stack.push(Value::Array(Rc::new(elems)));
+                self.push_alloc(Value::Array(Rc::new(elems)))?;
             }
             OpCode::MakeDict => {
                 let n = self.frame_mut().read_byte() as usize;
@@ -359,7 +709,7 @@ impl Vm {
                 }
                 pairs.reverse();
                 let map: HashMap<String, Value> = pairs.into_iter().collect();
-                self.stack.push(Value::Dict(Rc::new(map)));
+                self.push_alloc(Value::Dict(Rc::new(map)))?;
             }
             OpCode::GetIndex => {
                 let idx = self.pop()?;
@@ -370,7 +720,76 @@ impl Vm {
                 let new_val = self.pop()?;
                 let idx     = self.pop()?;
                 let obj     = self.pop()?;
-                self.stack.push(self.set_index(obj, idx, new_val)?);
+                let updated = self.set_index(obj, idx, new_val)?;
+                self.push_alloc(updated)?;
+            }
+            OpCode::MakeStruct => {
+                let idx = self.frame_mut().read_byte() as usize;
+                let def = match &self.frame().chunk.constants[idx] {
+                    Value::Struct(proto) => proto.def.clone(),
+                    _ => return Err(WhispemError::runtime(ErrorKind::InvalidBytecode(
+                        "MAKE_STRUCT operand is not a struct".to_string(),
+                    ))),
+                };
+                let fields = self.stack.split_off(self.stack.len().checked_sub(def.fields.len())
+                    .ok_or_else(|| WhispemError::runtime(ErrorKind::StackUnderflow))?);
+                self.push_alloc(Value::Struct(Rc::new(StructValue { def, fields })))?;
+            }
+            OpCode::GetField => {
+                let idx   = self.frame_mut().read_byte();
+                let field = self.const_str(idx);
+                let obj   = self.pop()?;
+                let st    = self.as_struct(obj)?;
+                let i     = self.field_index(&st, &field)?;
+                self.stack.push(st.fields[i].clone());
+            }
+            OpCode::SetField => {
+                let idx     = self.frame_mut().read_byte();
+                let field   = self.const_str(idx);
+                let new_val = self.pop()?;
+                let obj     = self.pop()?;
+                let mut st  = self.as_struct(obj)?;
+                let i       = self.field_index(&st, &field)?;
+                Rc::make_mut(&mut st).fields[i] = new_val;
+                self.push_alloc(Value::Struct(st))?;
+            }
+            OpCode::UnpackArray => {
+                let count = self.frame_mut().read_byte() as usize;
+                let rest  = self.frame_mut().read_byte() != 0;
+                let line  = self.frame().current_line();
+                let popped = self.pop()?;
+                let arr   = match self.materialise(popped, line)? {
+                    Value::Array(a) => a,
+                    other => return Err(self.type_err_at("array", other.type_name(), line)),
+                };
+                if arr.len() < count || (!rest && arr.len() > count) {
+                    return Err(WhispemError::new(
+                        ErrorKind::UnpackLength { expected: count, at_least: rest, got: arr.len() },
+                        Span::new(line, 0),
+                    ));
+                }
+                if rest { self.push_alloc(Value::Array(Rc::new(arr[count..].to_vec())))?; }
+                self.stack.extend(arr[..count].iter().rev().cloned());
+            }
+            OpCode::UnpackDict => {
+                let count = self.frame_mut().read_byte() as usize;
+                let line  = self.frame().current_line();
+                let keys  = self.stack.split_off(self.stack.len().saturating_sub(count));
+                let mut values = Vec::with_capacity(count);
+                match self.pop()? {
+                    Value::Dict(d) => for key in &keys {
+                        let Value::Str(key) = key else { unreachable!("compiled as string constants") };
+                        values.push(d.get(key).cloned().ok_or_else(|| WhispemError::new(
+                            ErrorKind::KeyNotFound(key.clone()), Span::new(line, 0),
+                        ))?);
+                    },
+                    Value::Struct(st) => for key in &keys {
+                        let Value::Str(key) = key else { unreachable!("compiled as string constants") };
+                        values.push(st.fields[self.field_index(&st, key)?].clone());
+                    },
+                    other => return Err(self.type_err_at("dict or struct", other.type_name(), line)),
+                }
+                self.stack.extend(values.into_iter().rev());
             }
             OpCode::Print => {
                 let val  = self.pop()?;
@@ -379,25 +798,109 @@ impl Vm {
             }
             OpCode::Pop => { self.pop()?; }
 
+            OpCode::TryBegin => {
+                let catch_ip = self.frame_mut().read_u16() as usize;
+                self.handlers.push(Handler { frame_depth: self.frames.len(), stack_len: self.stack.len(), catch_ip });
+            }
+            OpCode::TryEnd => { self.handlers.pop(); }
+            OpCode::Throw => {
+                let val = self.pop()?;
+                return Err(WhispemError::new(ErrorKind::Thrown(val), Span::new(self.frame().current_line(), 0)));
+            }
+            OpCode::Iter => {
+                let gen = match self.pop()? {
+                    Value::Generator(gen) => gen,
+                    Value::Array(items)   => Generator::new("array", GenState::Items { items, next: 0 }),
+                    Value::Range(range)   => Generator::new("range", GenState::Range { range: *range, next: 0 }),
+                    Value::Str(text)      => Generator::new("string", GenState::Chars { text, next: 0 }),
+                    Value::Dict(d) => {
+                        let mut keys: Vec<&String> = d.keys().collect();
+                        keys.sort();
+                        let items = Rc::new(keys.into_iter().map(|k| Value::Str(k.clone())).collect());
+                        Generator::new("dict", GenState::Items { items, next: 0 })
+                    }
+                    other => return Err(self.type_err("array, string, dict or generator", other.type_name())),
+                };
+                self.stack.push(Value::Generator(gen));
+            }
+            OpCode::IterNext => {
+                let gen = match self.pop()? {
+                    Value::Generator(gen) => gen,
+                    other => return Err(self.type_err("generator", other.type_name())),
+                };
+                self.resume(gen)?;
+            }
+            OpCode::MatchFail => {
+                let val = self.pop()?;
+                return Err(WhispemError::new(
+                    ErrorKind::NonExhaustiveMatch(val.format()),
+                    Span::new(self.frame().current_line(), 0),
+                ));
+            }
+
             // Handled in the outer loops; unreachable here.
-            OpCode::Return | OpCode::ReturnNone | OpCode::Halt => unreachable!(),
+            OpCode::Return | OpCode::ReturnNone | OpCode::Halt | OpCode::MakeGenerator | OpCode::Yield => unreachable!(),
         }
         Ok(())
     }
 
-    fn push_call_frame(&mut self, callee: Value, args: Vec<Value>, argc: usize) -> WhispemResult<()> {
+    fn pop_args(&mut self, argc: usize) -> WhispemResult<Vec<Value>> {
+        let mut args: Vec<Value> = (0..argc)
+            .map(|_| self.pop())
+            .collect::<WhispemResult<_>>()?;
+        args.reverse();
+        Ok(args)
+    }
+
+    // `CALL` / `CALL_NAMED`: builtins first, then a local closure, then a
+    // script function, then a native.  Any of those named like a builtin
+    // (`fn log(..)`) overrides it.  `__callee__` means the
+    // callee sits below the arguments.
+    fn call_value(&mut self, name: String, args: Vec<Value>, named: Vec<(String, Value)>) -> WhispemResult<()> {
+        if name == "__callee__" {
+            let callee = self.pop()?;
+            return self.push_call_frame(callee, args, named);
+        }
+
+        // Builtins take no named arguments; a named call skips them and
+        // fails below if nothing else answers to the name.
+        if named.is_empty() && !self.overrides_builtin(&name) {
+            if let Some(result) = self.call_builtin(&name, args.clone())? {
+                return self.push_alloc(result);
+            }
+        }
+
+        if let Some(closure_val) = self.lookup_local(&name) {
+            if matches!(closure_val, Value::Closure { .. } | Value::Native(_)) {
+                return self.push_call_frame(closure_val, args, named);
+            }
+        }
+
+        match self.functions.get(&name).cloned() {
+            Some(chunk) => self.push_call_frame(Value::Closure { chunk: Rc::new(chunk), upvalues: vec![] }, args, named),
+            None => match self.natives.get(&name).cloned() {
+                Some(native)              => self.push_call_frame(Value::Native(native), args, named),
+                None if !named.is_empty() => Err(self.no_named_args(&name, &named)),
+                None => Err(WhispemError::new(
+                    ErrorKind::UndefinedFunction(name), Span::new(self.frame().current_line(), 0),
+                )),
+            },
+        }
+    }
+
+    fn push_call_frame(&mut self, callee: Value, args: Vec<Value>, named: Vec<(String, Value)>) -> WhispemResult<()> {
         match callee {
             Value::Closure { chunk, upvalues } => {
-                if argc != chunk.param_count {
-                    return Err(WhispemError::new(
-                        ErrorKind::ArgumentCount { name: chunk.name.clone(), expected: chunk.param_count, got: argc },
-                        Span::new(self.frame().current_line(), 0),
-                    ));
-                }
-                let new_frame = CallFrame::new(chunk, upvalues);
+                let (args, missing) = self.bind_args(&chunk, args, named)?;
+                let mut new_frame = CallFrame::new(chunk, upvalues);
+                new_frame.missing = missing;
                 for arg in args { self.stack.push(arg); }
-                self.frames.push(new_frame);
-                Ok(())
+                self.push_frame(new_frame)
+            }
+            Value::Native(native) => {
+                if !named.is_empty() { return Err(self.no_named_args(&native.name, &named)); }
+                let result = self.call_native(native, args)?;
+                self.push_alloc(result)
             }
             other => Err(WhispemError::new(
                 ErrorKind::TypeError { expected: "function".to_string(), found: other.type_name().to_string() },
@@ -406,6 +909,139 @@ impl Vm {
         }
     }
 
+    // Match call arguments to `chunk`'s parameters.  Returns one value per
+    // parameter, `none` standing in for an optional one the caller left out
+    // (flagged in the second vector), with any extras collected into an
+    // array for the rest parameter.
+    fn bind_args(
+        &self, chunk: &Chunk, mut args: Vec<Value>, named: Vec<(String, Value)>,
+    ) -> WhispemResult<(Vec<Value>, Vec<bool>)> {
+        let n = chunk.param_count;
+        if named.is_empty() && args.len() == n && !chunk.has_rest {
+            return Ok((args, Vec::new()));
+        }
+        let err = |kind| WhispemError::new(kind, Span::new(self.frame().current_line(), 0));
+        let count = ErrorKind::ArgumentCount {
+            name: chunk.name.clone(),
+            min:  chunk.required,
+            max:  (!chunk.has_rest).then_some(n),
+            got:  args.len() + named.len(),
+        };
+        if args.len() > n && !chunk.has_rest { return Err(err(count)); }
+
+        let rest = args.split_off(n.min(args.len()));
+        let mut slots: Vec<Option<Value>> = args.into_iter().map(Some).collect();
+        slots.resize(n, None);
+        let has_named = !named.is_empty();
+        for (param, value) in named {
+            let function = chunk.name.clone();
+            let Some(i) = chunk.param_names.iter().take(n).position(|p| *p == param) else {
+                return Err(err(ErrorKind::UnknownArgument { function, param }));
+            };
+            if slots[i].is_some() { return Err(err(ErrorKind::DuplicateArgument { function, param })); }
+            slots[i] = Some(value);
+        }
+        if let Some(i) = slots[..chunk.required].iter().position(Option::is_none) {
+            return Err(err(if !has_named { count } else {
+                ErrorKind::MissingArgument { function: chunk.name.clone(), param: chunk.param_names[i].clone() }
+            }));
+        }
+
+        let missing = slots.iter().map(Option::is_none).collect();
+        let mut values: Vec<Value> = slots.into_iter().map(|v| v.unwrap_or(Value::None)).collect();
+        if chunk.has_rest { values.push(Value::Array(Rc::new(rest))); }
+        Ok((values, missing))
+    }
+
+    fn no_named_args(&self, function: &str, named: &[(String, Value)]) -> WhispemError {
+        WhispemError::new(
+            ErrorKind::UnknownArgument { function: function.to_string(), param: named[0].0.clone() },
+            Span::new(self.frame().current_line(), 0),
+        )
+    }
+
+    // Count one instruction against `max_steps` and check the value stack.
+    fn tick(&mut self) -> WhispemResult<()> {
+        self.steps += 1;
+        if let Some(max) = self.limits.max_steps {
+            if self.steps > max {
+                return Err(WhispemError::new(ErrorKind::StepLimitExceeded(max), Span::new(self.frame().current_line(), 0)));
+            }
+        }
+        if let Some(max) = self.limits.max_stack {
+            if self.stack.len() > max { return Err(self.overflow("value stack size", max)); }
+        }
+        Ok(())
+    }
+
+    fn check_interrupt(&self) -> WhispemResult<()> {
+        if !self.interrupt.0.load(atomic::Ordering::Relaxed) || !self.interrupt.take() { return Ok(()); }
+        let line = self.frames.last().map_or(0, |f| f.current_line());
+        Err(WhispemError::new(ErrorKind::Interrupted, Span::new(line, 0)))
+    }
+
+    fn push_frame(&mut self, frame: CallFrame) -> WhispemResult<()> {
+        self.check_interrupt()?;
+        if let Some(max) = self.limits.max_frames {
+            if self.frames.len() >= max { return Err(self.overflow("call depth", max)); }
+        }
+        self.frames.push(frame);
+        Ok(())
+    }
+
+    // Callbacks run from builtins recurse on the Rust stack; past
+    // `max_callback_depth` they fail with `StackOverflow` instead of
+    // crashing the process.
+    fn check_reentry(&self) -> WhispemResult<()> {
+        let max = self.limits.max_callback_depth;
+        if self.reentry >= max { return Err(self.overflow("nested callback depth", max)); }
+        Ok(())
+    }
+
+    fn overflow(&self, what: &str, limit: usize) -> WhispemError {
+        let line = self.frames.last().map_or(0, |f| f.current_line());
+        WhispemError::new(ErrorKind::StackOverflow { what: what.to_string(), limit }, Span::new(line, 0))
+    }
+
+    // Push a value the current instruction just built and charge it against
+    // `max_heap_bytes`.  The running estimate only over-counts (nothing is
+    // subtracted when values die), so a full measurement is taken before
+    // reporting an error.
+    fn push_alloc(&mut self, value: Value) -> WhispemResult<()> {
+        let size = shallow_size(&value);
+        self.stack.push(value);
+        let Some(max) = self.limits.max_heap_bytes else { return Ok(()) };
+        self.heap_estimate += size;
+        if self.heap_estimate <= max { return Ok(()); }
+        self.heap_estimate = self.heap_in_use();
+        if self.heap_estimate <= max { return Ok(()); }
+        Err(WhispemError::new(ErrorKind::MemoryLimitExceeded(max), Span::new(self.frame().current_line(), 0)))
+    }
+
+    // Approximate bytes reachable from the stack, globals and live frames.
+    fn heap_in_use(&self) -> usize {
+        let mut meter = HeapMeter::default();
+        for v in self.stack.iter().chain(self.globals.values()) { meter.add(v); }
+        for frame in &self.frames {
+            for v in frame.locals.values() { meter.add(v); }
+            for uv in frame.upvalues.iter().chain(frame.open_upvalues.values()) { meter.add(&uv.borrow().0); }
+        }
+        meter.total
+    }
+
+    fn call_native(&mut self, native: Rc<Native>, args: Vec<Value>) -> WhispemResult<Value> {
+        let line = self.frame().current_line();
+        self.arity(&native.name, native.arity, args.len(), line)?;
+        (native.func)(self, args).map_err(|mut e| {
+            if !e.span.is_known() { e.span = Span::new(line, 0); }
+            e
+        })
+    }
+
+    fn native_value(&self, name: &str) -> Option<Value> {
+        self.natives.get(name).map(|n| Value::Native(Rc::clone(n)))
+    }
+
     fn load_upvalue(&self, slot: usize) -> WhispemResult<Value> {
         let uv = self.frame().upvalues.get(slot).ok_or_else(|| {
             WhispemError::runtime(ErrorKind::UpvalueError(format!("upvalue slot {} out of range", slot)))
@@ -421,15 +1057,22 @@ impl Vm {
         Ok(())
     }
 
-    fn call_builtin(&mut self, name: &str, args: Vec<Value>) -> WhispemResult<Option<Value>> {
+    fn call_builtin(&mut self, name: &str, mut args: Vec<Value>) -> WhispemResult<Option<Value>> {
         let line = self.frame().current_line();
+        // Only these see a range as it is; every other builtin gets its elements.
+        if !matches!(name, "length" | "reverse" | "type_of" | "slice" | "pop") {
+            for arg in args.iter_mut().filter(|a| matches!(a, Value::Range(_))) {
+                *arg = self.materialise(std::mem::replace(arg, Value::None), line)?;
+            }
+        }
         let result = match name {
             "length" => {
                 self.arity(name, 1, args.len(), line)?;
                 match &args[0] {
-                    Value::Array(a) => Value::Number(a.len() as f64),
-                    Value::Str(s)   => Value::Number(s.chars().count() as f64),
-                    Value::Dict(d)  => Value::Number(d.len() as f64),
+                    Value::Array(a) => Value::Int(a.len() as i64),
+                    Value::Range(r) => i64::try_from(r.len()).map_or(Value::Number(r.len() as f64), Value::Int),
+                    Value::Str(s)   => Value::Int(s.chars().count() as i64),
+                    Value::Dict(d)  => Value::Int(d.len() as i64),
                     other => return Err(self.type_err_at("array, string, or dict", other.type_name(), line)),
                 }
             }
@@ -440,6 +1083,7 @@ impl Vm {
                     other => return Err(self.type_err_at("array", other.type_name(), line)),
                 }
             }
+            "next" => return self.next_builtin(args, line).map(Some),
             "pop" => {
                 self.arity(name, 1, args.len(), line)?;
                 match args[0].clone() {
@@ -449,6 +1093,9 @@ impl Vm {
                         }
                         Rc::make_mut(&mut a).pop().unwrap()
                     }
+                    Value::Range(r) => r.get(r.len().wrapping_sub(1)).ok_or_else(|| {
+                        WhispemError::new(ErrorKind::EmptyArray, Span::new(line, 0))
+                    })?,
                     other => return Err(self.type_err_at("array", other.type_name(), line)),
                 }
             }
@@ -456,6 +1103,7 @@ impl Vm {
                 self.arity(name, 1, args.len(), line)?;
                 match args[0].clone() {
                     Value::Array(mut a) => { Rc::make_mut(&mut a).reverse(); Value::Array(a) }
+                    Value::Range(r)     => Value::Range(Rc::new(r.reversed())),
                     other => return Err(self.type_err_at("array", other.type_name(), line)),
                 }
             }
@@ -463,42 +1111,52 @@ impl Vm {
                 self.arity(name, 3, args.len(), line)?;
                 let start = self.to_usize(&args[1], line)?;
                 let end   = self.to_usize(&args[2], line)?;
-                match &args[0] {
-                    Value::Array(a) => {
-                        if start > end {
-                            return Err(WhispemError::new(ErrorKind::InvalidSlice { start, end }, Span::new(line, 0)));
-                        }
-                        if end > a.len() {
-                            return Err(WhispemError::new(ErrorKind::SliceOutOfBounds { end, length: a.len() }, Span::new(line, 0)));
-                        }
-                        Value::Array(Rc::new(a[start..end].to_vec()))
-                    }
+                let length = match &args[0] {
+                    Value::Array(a) => a.len(),
+                    Value::Range(r) => r.len(),
                     other => return Err(self.type_err_at("array", other.type_name(), line)),
+                };
+                if start > end {
+                    return Err(WhispemError::new(ErrorKind::InvalidSlice { start, end }, Span::new(line, 0)));
+                }
+                if end > length {
+                    return Err(WhispemError::new(ErrorKind::SliceOutOfBounds { end, length }, Span::new(line, 0)));
+                }
+                match &args[0] {
+                    Value::Range(r) => Value::Range(Rc::new(r.slice(start, end))),
+                    Value::Array(a) => Value::Array(Rc::new(a[start..end].to_vec())),
+                    _               => unreachable!("checked above"),
                 }
             }
-            "range" => {
-                self.arity(name, 2, args.len(), line)?;
-                let start = self.to_i64(&args[0], line)?;
-                let end   = self.to_i64(&args[1], line)?;
-                Value::Array(Rc::new((start..end).map(|i| Value::Number(i as f64)).collect()))
-            }
+            "range" => return self.range_builtin(&args, line).map(Some),
             "input" => {
+                self.require(self.permissions.stdin, name, "stdin", line)?;
                 if args.len() > 1 {
                     return Err(WhispemError::new(
-                        ErrorKind::ArgumentCount { name: "input".into(), expected: 1, got: args.len() },
+                        ErrorKind::ArgumentCount { name: "input".into(), min: 0, max: Some(1), got: args.len() },
                         Span::new(line, 0),
                     ));
                 }
                 let prompt = if args.is_empty() { String::new() }
                              else { match &args[0] { Value::Str(s) => s.clone(), other => return Err(self.type_err_at("string", other.type_name(), line)) } };
-                if !prompt.is_empty() { print!("{}", prompt); io::stdout().flush().unwrap(); }
+                if !prompt.is_empty() {
+                    let _ = self.output.write_all(prompt.as_bytes());
+                    let _ = self.output.flush();
+                }
                 let mut buf = String::new();
-                io::stdin().read_line(&mut buf).unwrap();
+                let read = match self.input.as_mut() {
+                    Some(input) => input.read_line(&mut buf),
+                    None        => io::stdin().read_line(&mut buf),
+                };
+                read.map_err(|e| WhispemError::new(
+                    ErrorKind::FileRead { path: "<stdin>".into(), reason: e.to_string() }, Span::new(line, 0),
+                ))?;
                 Value::Str(buf.trim_end_matches('\n').trim_end_matches('\r').to_string())
             }
             "read_file" => {
                 self.arity(name, 1, args.len(), line)?;
                 let path = match &args[0] { Value::Str(s) => s.clone(), other => return Err(self.type_err_at("string", other.type_name(), line)) };
+                self.require(self.permissions.can_read(Path::new(&path)), name, &path, line)?;
                 fs::read_to_string(&path).map(Value::Str).map_err(|e| WhispemError::new(
                     ErrorKind::FileRead { path: path.clone(), reason: e.to_string() }, Span::new(line, 0),
                 ))?
@@ -506,6 +1164,7 @@ impl Vm {
             "write_file" => {
                 self.arity(name, 2, args.len(), line)?;
                 let path    = match &args[0] { Value::Str(s) => s.clone(), other => return Err(self.type_err_at("string", other.type_name(), line)) };
+                self.require(self.permissions.can_write(Path::new(&path)), name, &path, line)?;
                 let content = args[1].format();
                 fs::write(&path, content).map(|_| Value::None).map_err(|e| WhispemError::new(
                     ErrorKind::FileWrite { path: path.clone(), reason: e.to_string() }, Span::new(line, 0),
@@ -516,7 +1175,7 @@ impl Vm {
                 match &args[0] {
                     Value::Dict(map) => {
                         let mut ks: Vec<Value> = map.keys().map(|k| Value::Str(k.clone())).collect();
-                        ks.sort_by(|a, b| a.format().cmp(&b.format()));
+                        ks.sort_by_key(|k| k.format());
                         Value::Array(Rc::new(ks))
                     }
                     other => return Err(self.type_err_at("dict", other.type_name(), line)),
@@ -547,8 +1206,8 @@ impl Vm {
             "char_at" => {
                 self.arity(name, 2, args.len(), line)?;
                 match (&args[0], &args[1]) {
-                    (Value::Str(s), Value::Number(n)) => {
-                        let i  = *n as usize;
+                    (Value::Str(s), n) if n.as_f64().is_some() => {
+                        let i  = self.to_usize(n, line)?;
                         let ch = s.chars().nth(i).ok_or_else(|| WhispemError::new(
                             ErrorKind::IndexOutOfBounds { index: i, length: s.chars().count() }, Span::new(line, 0),
                         ))?;
@@ -560,11 +1219,11 @@ impl Vm {
             "substr" => {
                 self.arity(name, 3, args.len(), line)?;
                 match (&args[0], &args[1], &args[2]) {
-                    (Value::Str(s), Value::Number(start), Value::Number(len)) => {
-                        let st    = *start as usize;
-                        let ln    = *len as usize;
+                    (Value::Str(s), start, len) if start.as_f64().is_some() && len.as_f64().is_some() => {
+                        let st    = self.to_usize(start, line)?;
+                        let ln    = self.to_usize(len, line)?;
                         let chars: Vec<char> = s.chars().collect();
-                        let end   = (st + ln).min(chars.len());
+                        let end   = st.saturating_add(ln).min(chars.len());
                         Value::Str(chars[st.min(chars.len())..end].iter().collect())
                     }
                     _ => return Err(self.type_err_at("string, number, number", "wrong types", line)),
@@ -578,14 +1237,23 @@ impl Vm {
                             ErrorKind::TypeError { expected: "non-empty string".into(), found: "empty string".into() },
                             Span::new(line, 0),
                         ))?;
-                        Value::Number(ch as u32 as f64)
+                        Value::Int(ch as u32 as i64)
                     }
                     other => return Err(self.type_err_at("string", other.type_name(), line)),
                 }
             }
+            "chr" => {
+                self.arity(name, 1, args.len(), line)?;
+                let code = self.to_i64(&args[0], line)?;
+                let ch = u32::try_from(code).ok().and_then(char::from_u32)
+                    .ok_or_else(|| self.type_err_at("Unicode code point", &code.to_string(), line))?;
+                Value::Str(ch.to_string())
+            }
+
             "num_to_str" => {
                 self.arity(name, 1, args.len(), line)?;
                 match &args[0] {
+                    Value::Int(i)    => Value::Str(i.to_string()),
                     Value::Number(n) => Value::Str(if n.fract() == 0.0 { format!("{}", *n as i64) } else { format!("{}", n) }),
                     other => return Err(self.type_err_at("number", other.type_name(), line)),
                 }
@@ -593,23 +1261,25 @@ impl Vm {
             "str_to_num" => {
                 self.arity(name, 1, args.len(), line)?;
                 match &args[0] {
-                    Value::Str(s) => {
-                        let n = s.trim().parse::<f64>().map_err(|_| WhispemError::new(
+                    Value::Str(s) => match s.trim().parse::<i64>() {
+                        Ok(i)  => Value::Int(i),
+                        Err(_) => Value::Number(s.trim().parse::<f64>().map_err(|_| WhispemError::new(
                             ErrorKind::TypeError { expected: "numeric string".into(), found: format!("\"{}\"", s) },
                             Span::new(line, 0),
-                        ))?;
-                        Value::Number(n)
-                    }
+                        ))?),
+                    },
                     other => return Err(self.type_err_at("string", other.type_name(), line)),
                 }
             }
             "args" => {
                 self.arity(name, 0, args.len(), line)?;
+                self.require(self.permissions.args, name, "script arguments", line)?;
                 Value::Array(Rc::new(self.script_args.iter().map(|s| Value::Str(s.clone())).collect()))
             }
             "num_to_hex" => {
                 self.arity(name, 1, args.len(), line)?;
                 match &args[0] {
+                    Value::Int(i)    => Value::Str(format!("{:016x}", (*i as f64).to_bits())),
                     Value::Number(n) => Value::Str(format!("{:016x}", n.to_bits())),
                     other => return Err(self.type_err_at("number", other.type_name(), line)),
                 }
@@ -618,6 +1288,7 @@ impl Vm {
                 self.arity(name, 2, args.len(), line)?;
                 let path = match &args[0] { Value::Str(s) => s.clone(), other => return Err(self.type_err_at("string", other.type_name(), line)) };
                 let hex  = match &args[1] { Value::Str(s) => s.clone(), other => return Err(self.type_err_at("string", other.type_name(), line)) };
+                self.require(self.permissions.can_write(Path::new(&path)), name, &path, line)?;
                 let bytes: Vec<u8> = (0..hex.len()).step_by(2)
                     .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap_or(0))
                     .collect();
@@ -627,9 +1298,9 @@ impl Vm {
                 Value::None
             }
             "assert" => {
-                if args.len() < 1 || args.len() > 2 {
+                if args.is_empty() || args.len() > 2 {
                     return Err(WhispemError::new(
-                        ErrorKind::ArgumentCount { name: "assert".into(), expected: 2, got: args.len() },
+                        ErrorKind::ArgumentCount { name: "assert".into(), min: 1, max: Some(2), got: args.len() },
                         Span::new(line, 0),
                     ));
                 }
@@ -641,12 +1312,16 @@ impl Vm {
             }
             "type_of" => {
                 self.arity(name, 1, args.len(), line)?;
-                Value::Str(args[0].type_name().to_string())
+                match &args[0] {
+                    Value::Struct(st) => Value::Str(st.def.name.clone()),
+                    other             => Value::Str(other.type_name().to_string()),
+                }
             }
             "exit" => {
+                self.require(self.permissions.exit, name, "process exit", line)?;
                 if args.len() > 1 {
                     return Err(WhispemError::new(
-                        ErrorKind::ArgumentCount { name: "exit".into(), expected: 1, got: args.len() },
+                        ErrorKind::ArgumentCount { name: "exit".into(), min: 0, max: Some(1), got: args.len() },
                         Span::new(line, 0),
                     ));
                 }
@@ -701,6 +1376,336 @@ impl Vm {
                 accum
             }
 
+            _ => {
+                if let Some(v) = self.string_builtin(name, &args, line)? { return Ok(Some(v)); }
+                if let Some(v) = self.sort_builtin(name, &args, line)?   { return Ok(Some(v)); }
+                return self.math_builtin(name, args, line);
+            }
+        };
+        Ok(Some(result))
+    }
+
+    // The string toolkit.  Positions and widths count chars, not bytes.
+    // Kept out of `call_builtin` so its stack frame, paid on every level of
+    // `invoke_closure` recursion, stays small.
+    fn string_builtin(&mut self, name: &str, args: &[Value], line: usize) -> WhispemResult<Option<Value>> {
+        let result = match name {
+            "split" => {
+                self.arity(name, 2, args.len(), line)?;
+                let s   = self.str_arg(&args[0], line)?;
+                let sep = self.str_arg(&args[1], line)?;
+                let parts: Vec<Value> = if sep.is_empty() {
+                    s.chars().map(|c| Value::Str(c.to_string())).collect()
+                } else {
+                    s.split(sep).map(|p| Value::Str(p.to_string())).collect()
+                };
+                Value::Array(Rc::new(parts))
+            }
+            "join" => {
+                self.arity(name, 2, args.len(), line)?;
+                let sep = self.str_arg(&args[1], line)?;
+                match &args[0] {
+                    Value::Array(a) => Value::Str(a.iter().map(Value::format).collect::<Vec<_>>().join(sep)),
+                    other => return Err(self.type_err_at("array", other.type_name(), line)),
+                }
+            }
+            "trim" | "trim_start" | "trim_end" => {
+                self.arity(name, 1, args.len(), line)?;
+                let s = self.str_arg(&args[0], line)?;
+                Value::Str(match name {
+                    "trim"       => s.trim(),
+                    "trim_start" => s.trim_start(),
+                    _            => s.trim_end(),
+                }.to_string())
+            }
+            "replace" => {
+                self.arity(name, 3, args.len(), line)?;
+                let s    = self.str_arg(&args[0], line)?;
+                let from = self.str_arg(&args[1], line)?;
+                let to   = self.str_arg(&args[2], line)?;
+                if from.is_empty() {
+                    return Err(self.type_err_at("non-empty string", "empty string", line));
+                }
+                Value::Str(s.replace(from, to))
+            }
+            "find" | "rfind" => {
+                self.arity(name, 2, args.len(), line)?;
+                let s   = self.str_arg(&args[0], line)?;
+                let sub = self.str_arg(&args[1], line)?;
+                let at  = if name == "find" { s.find(sub) } else { s.rfind(sub) };
+                Value::Int(at.map_or(-1, |b| s[..b].chars().count() as i64))
+            }
+            "starts_with" | "ends_with" | "contains" => {
+                self.arity(name, 2, args.len(), line)?;
+                let s   = self.str_arg(&args[0], line)?;
+                let sub = self.str_arg(&args[1], line)?;
+                Value::Bool(match name {
+                    "starts_with" => s.starts_with(sub),
+                    "ends_with"   => s.ends_with(sub),
+                    _             => s.contains(sub),
+                })
+            }
+            "upper" | "lower" => {
+                self.arity(name, 1, args.len(), line)?;
+                let s = self.str_arg(&args[0], line)?;
+                Value::Str(if name == "upper" { s.to_uppercase() } else { s.to_lowercase() })
+            }
+            "repeat" => {
+                self.arity(name, 2, args.len(), line)?;
+                let s = self.str_arg(&args[0], line)?;
+                let n = self.count_arg(&args[1], line)?;
+                // Refuse up front rather than let a huge result abort the process.
+                if let Some(max) = self.limits.max_heap_bytes {
+                    if s.len().saturating_mul(n) > max {
+                        return Err(WhispemError::new(ErrorKind::MemoryLimitExceeded(max), Span::new(line, 0)));
+                    }
+                }
+                Value::Str(s.repeat(n))
+            }
+            "pad_left" | "pad_right" => {
+                if args.len() < 2 || args.len() > 3 {
+                    return Err(WhispemError::new(
+                        ErrorKind::ArgumentCount { name: name.into(), min: 2, max: Some(3), got: args.len() },
+                        Span::new(line, 0),
+                    ));
+                }
+                let s     = self.str_arg(&args[0], line)?;
+                let width = self.count_arg(&args[1], line)?;
+                let fill  = if args.len() == 3 { self.str_arg(&args[2], line)? } else { " " };
+                let mut fill_chars = fill.chars();
+                let (Some(fill), None) = (fill_chars.next(), fill_chars.next()) else {
+                    return Err(self.type_err_at("single-character string", &format!("\"{}\"", fill), line));
+                };
+                let pad: String = std::iter::repeat_n(fill, width.saturating_sub(s.chars().count())).collect();
+                Value::Str(if name == "pad_left" { pad + s } else { format!("{}{}", s, pad) })
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(result))
+    }
+
+    // `range(start, end, step?)`: ints when every bound is an integer,
+    // otherwise floats.
+    fn range_builtin(&self, args: &[Value], line: usize) -> WhispemResult<Value> {
+        if args.len() < 2 || args.len() > 3 {
+            return Err(WhispemError::new(
+                ErrorKind::ArgumentCount { name: "range".into(), min: 2, max: Some(3), got: args.len() },
+                Span::new(line, 0),
+            ));
+        }
+        let step = args.get(2).cloned().unwrap_or(Value::Int(1));
+        let bounds = [&args[0], &args[1], &step];
+        let range = if let [Some(start), Some(end), Some(step)] = bounds.map(Value::as_int) {
+            if step == 0 { return Err(self.zero_step(line)); }
+            Range::int(start, end, step)
+        } else {
+            let (start, end, step) = (self.num_arg(&args[0], line)?, self.num_arg(&args[1], line)?, self.num_arg(&step, line)?);
+            if step == 0.0 { return Err(self.zero_step(line)); }
+            Range::float(start, end, step)
+        };
+        Ok(Value::Range(Rc::new(range)))
+    }
+
+    fn zero_step(&self, line: usize) -> WhispemError {
+        WhispemError::new(
+            ErrorKind::InvalidArgument { function: "range".into(), reason: "step must not be zero".into() },
+            Span::new(line, 0),
+        )
+    }
+
+    // `next(gen)` / `next(gen, default)`: the generator's next item, or
+    // `default` (`none`) once it is exhausted.  Runs a suspended frame on
+    // the Rust stack, like `invoke_closure`.
+    fn next_builtin(&mut self, args: Vec<Value>, line: usize) -> WhispemResult<Value> {
+        if args.is_empty() || args.len() > 2 {
+            return Err(WhispemError::new(
+                ErrorKind::ArgumentCount { name: "next".into(), min: 1, max: Some(2), got: args.len() },
+                Span::new(line, 0),
+            ));
+        }
+        let Value::Generator(gen) = &args[0] else {
+            return Err(self.type_err_at("generator", args[0].type_name(), line));
+        };
+        self.check_reentry()?;
+        let depth = self.frames.len();
+        self.resume(Rc::clone(gen))?;
+        if self.frames.len() > depth {
+            self.reentry += 1;
+            let result = self.run_until(depth);
+            self.reentry -= 1;
+            result?;
+        }
+        if let Value::Bool(true) = self.pop()? { return self.pop(); }
+        Ok(args.get(1).cloned().unwrap_or(Value::None))
+    }
+
+    // `sort`, `sort_by` and `sort_key`.  All three are stable and return a
+    // new array.
+    fn sort_builtin(&mut self, name: &str, args: &[Value], line: usize) -> WhispemResult<Option<Value>> {
+        if !matches!(name, "sort" | "sort_by" | "sort_key") { return Ok(None); }
+        self.arity(name, if name == "sort" { 1 } else { 2 }, args.len(), line)?;
+        let items = match &args[0] {
+            Value::Array(a) => a.to_vec(),
+            other => return Err(self.type_err_at("array", other.type_name(), line)),
+        };
+        let sorted = match name {
+            "sort" => {
+                self.check_sortable(&items, line)?;
+                merge_sort(items, &mut |a, b| Ok(sort_order(a, b).is_lt()))?
+            }
+            // The comparator returns a negative number when `a` goes first.
+            "sort_by" => {
+                let f = args[1].clone();
+                merge_sort(items, &mut |a, b| {
+                    match self.invoke_closure(f.clone(), vec![a.clone(), b.clone()])? {
+                        r @ (Value::Int(_) | Value::Number(_)) => Ok(r.as_f64().is_some_and(|n| n < 0.0)),
+                        other => Err(self.type_err_at("number from comparator", other.type_name(), line)),
+                    }
+                })?
+            }
+            // Each key is computed once, then the keys are sorted like `sort`.
+            _ => {
+                let keys = items.iter()
+                    .map(|item| self.invoke_closure(args[1].clone(), vec![item.clone()]))
+                    .collect::<WhispemResult<Vec<_>>>()?;
+                self.check_sortable(&keys, line)?;
+                let pairs = keys.into_iter().zip(items).collect();
+                merge_sort(pairs, &mut |(ka, _), (kb, _)| Ok(sort_order(ka, kb).is_lt()))?
+                    .into_iter().map(|(_, v)| v).collect()
+            }
+        };
+        Ok(Some(Value::Array(Rc::new(sorted))))
+    }
+
+    // `sort` orders all numbers or all strings; anything else is an error.
+    fn check_sortable(&self, items: &[Value], line: usize) -> WhispemResult<()> {
+        let class = |v: &Value| match v {
+            Value::Int(_) | Value::Number(_) => Some("number"),
+            Value::Str(_)                    => Some("string"),
+            _                                => None,
+        };
+        let Some(first) = items.first() else { return Ok(()) };
+        for v in items {
+            if class(first).is_none() || class(v) != class(first) {
+                let found = if class(first).is_some() { format!("{} and {}", first.type_name(), v.type_name()) }
+                            else                      { first.type_name().to_string() };
+                return Err(self.type_err_at("all numbers or all strings", &found, line));
+            }
+        }
+        Ok(())
+    }
+
+    // Math and pseudo-random numbers.  Rounding returns an int when one can
+    // hold the result.
+    fn math_builtin(&mut self, name: &str, args: Vec<Value>, line: usize) -> WhispemResult<Option<Value>> {
+        let result = match name {
+            "abs" => {
+                self.arity(name, 1, args.len(), line)?;
+                match &args[0] {
+                    Value::Int(i)    => Value::Int(i.checked_abs().ok_or_else(|| int_overflow(format!("abs({})", i)))?),
+                    Value::Number(n) => Value::Number(n.abs()),
+                    other => return Err(self.type_err_at("number", other.type_name(), line)),
+                }
+            }
+            "floor" | "ceil" | "round" | "trunc" => {
+                self.arity(name, 1, args.len(), line)?;
+                match &args[0] {
+                    Value::Int(i)    => Value::Int(*i),
+                    Value::Number(n) => float_to_int(match name {
+                        "floor" => n.floor(),
+                        "ceil"  => n.ceil(),
+                        "round" => n.round(),
+                        _       => n.trunc(),
+                    }),
+                    other => return Err(self.type_err_at("number", other.type_name(), line)),
+                }
+            }
+            "sqrt" | "exp" | "log" | "sin" | "cos" | "tan" | "asin" | "acos" | "atan" => {
+                self.arity(name, 1, args.len(), line)?;
+                let x = self.num_arg(&args[0], line)?;
+                Value::Number(match name {
+                    "sqrt" => x.sqrt(),
+                    "exp"  => x.exp(),
+                    "log"  => x.ln(),
+                    "sin"  => x.sin(),
+                    "cos"  => x.cos(),
+                    "tan"  => x.tan(),
+                    "asin" => x.asin(),
+                    "acos" => x.acos(),
+                    _      => x.atan(),
+                })
+            }
+            "atan2" => {
+                self.arity(name, 2, args.len(), line)?;
+                let y = self.num_arg(&args[0], line)?;
+                let x = self.num_arg(&args[1], line)?;
+                Value::Number(y.atan2(x))
+            }
+            "pow" => {
+                self.arity(name, 2, args.len(), line)?;
+                match (&args[0], &args[1]) {
+                    (Value::Int(b), Value::Int(e)) if *e >= 0 => Value::Int(
+                        u32::try_from(*e).ok().and_then(|e| b.checked_pow(e))
+                            .ok_or_else(|| int_overflow(format!("pow({}, {})", b, e)))?,
+                    ),
+                    (b, e) => Value::Number(self.num_arg(b, line)?.powf(self.num_arg(e, line)?)),
+                }
+            }
+            "min" | "max" => {
+                if args.is_empty() {
+                    return Err(WhispemError::new(
+                        ErrorKind::ArgumentCount { name: name.into(), min: 1, max: None, got: 0 },
+                        Span::new(line, 0),
+                    ));
+                }
+                // `min(array)` or `min(a, b, ...)`.
+                let items = match (&args[0], args.len()) {
+                    (Value::Array(a), 1) => a.to_vec(),
+                    (other, 1)           => return Err(self.type_err_at("array", other.type_name(), line)),
+                    _                    => args,
+                };
+                // Ints compare exactly; only mixed pairs go through f64.
+                // NaN is unordered, so it never replaces a best item.
+                let wanted = if name == "min" { Ordering::Less } else { Ordering::Greater };
+                let mut best: Option<Value> = None;
+                for item in items {
+                    self.num_arg(&item, line)?;
+                    let better = match &best {
+                        None    => true,
+                        Some(b) => num_order(&item, b) == Some(wanted),
+                    };
+                    if better { best = Some(item); }
+                }
+                best.ok_or_else(|| WhispemError::new(ErrorKind::EmptyArray, Span::new(line, 0)))?
+            }
+            "is_nan" => {
+                self.arity(name, 1, args.len(), line)?;
+                Value::Bool(self.num_arg(&args[0], line)?.is_nan())
+            }
+
+            // Pseudo-random numbers (SplitMix64, see `rng.rs`).
+            "random" => {
+                self.arity(name, 0, args.len(), line)?;
+                Value::Number(self.rng.next_f64())
+            }
+            "random_int" => {
+                self.arity(name, 2, args.len(), line)?;
+                let low  = self.to_i64(&args[0], line)?;
+                let high = self.to_i64(&args[1], line)?;
+                if low > high {
+                    return Err(WhispemError::new(
+                        ErrorKind::InvalidArgument { function: name.into(), reason: format!("low {} is greater than high {}", low, high) },
+                        Span::new(line, 0),
+                    ));
+                }
+                Value::Int(self.rng.int_in(low, high))
+            }
+            "seed" => {
+                self.arity(name, 1, args.len(), line)?;
+                let n = self.to_i64(&args[0], line)?;
+                self.rng.seed(n as u64);
+                Value::None
+            }
             _ => return Ok(None),
         };
         Ok(Some(result))
@@ -735,22 +1740,37 @@ impl Vm {
                 }
                 Ok(a[i].clone())
             }
+            Value::Range(r) => {
+                let i = self.to_usize(&idx, line)?;
+                r.get(i).ok_or_else(|| {
+                    WhispemError::new(ErrorKind::IndexOutOfBounds { index: i, length: r.len() }, Span::new(line, 0))
+                })
+            }
             Value::Dict(map) => {
                 let key = self.to_dict_key(idx)?;
                 map.get(&key).cloned().ok_or_else(|| {
-                    WhispemError::new(
-                        ErrorKind::UndefinedVariable(format!("key \"{}\" not found in dict", key)),
-                        Span::new(line, 0),
-                    )
+                    WhispemError::new(ErrorKind::KeyNotFound(key), Span::new(line, 0))
                 })
             }
             other => Err(self.type_err_at("array or dict", other.type_name(), line)),
         }
     }
 
+    // A range's elements as an array, refused up front when they would not
+    // fit the heap limit.  Other values pass through.
+    fn materialise(&self, v: Value, line: usize) -> WhispemResult<Value> {
+        let Value::Range(r) = v else { return Ok(v) };
+        if let Some(max) = self.limits.max_heap_bytes {
+            if r.len().saturating_mul(std::mem::size_of::<Value>()) > max {
+                return Err(WhispemError::new(ErrorKind::MemoryLimitExceeded(max), Span::new(line, 0)));
+            }
+        }
+        Ok(Value::Array(Rc::new(r.iter().collect())))
+    }
+
     fn set_index(&self, obj: Value, idx: Value, new_val: Value) -> WhispemResult<Value> {
         let line = self.frame().current_line();
-        match obj {
+        match self.materialise(obj, line)? {
             Value::Array(mut a) => {
                 let i = self.to_usize(&idx, line)?;
                 if i >= a.len() {
@@ -768,32 +1788,77 @@ impl Vm {
         }
     }
 
+    fn as_struct(&self, v: Value) -> WhispemResult<Rc<StructValue>> {
+        match v {
+            Value::Struct(st) => Ok(st),
+            other => Err(self.type_err_at("struct", other.type_name(), self.frame().current_line())),
+        }
+    }
+
+    fn field_index(&self, st: &StructValue, field: &str) -> WhispemResult<usize> {
+        st.def.field_index(field).ok_or_else(|| WhispemError::new(
+            ErrorKind::UnknownField { struct_name: st.def.name.clone(), field: field.to_string() },
+            Span::new(self.frame().current_line(), 0),
+        ))
+    }
+
     fn to_dict_key(&self, v: Value) -> WhispemResult<String> {
         let line = self.frame().current_line();
         match v {
             Value::Str(s)    => Ok(s),
+            Value::Int(i)    => Ok(i.to_string()),
             Value::Number(n) => Ok(if n.fract() == 0.0 { format!("{}", n as i64) } else { format!("{}", n) }),
             other => Err(self.type_err_at("string or number (as dict key)", other.type_name(), line)),
         }
     }
 
+    /// An index or count: a non-negative integer.  A float is accepted
+    /// only when it is integral, so `-1` and `1.5` are errors rather than
+    /// silently truncated.
     fn to_usize(&self, v: &Value, line: usize) -> WhispemResult<usize> {
-        match v {
-            Value::Number(n) => Ok(*n as usize),
+        match v.as_int() {
+            Some(i) if i >= 0 => Ok(i as usize),
             _ => Err(WhispemError::new(ErrorKind::InvalidIndex, Span::new(line, 0))),
         }
     }
 
     fn to_i64(&self, v: &Value, line: usize) -> WhispemResult<i64> {
+        v.as_int().ok_or_else(|| self.type_err_at("integer", &Self::describe_number(v), line))
+    }
+
+    fn str_arg<'a>(&self, v: &'a Value, line: usize) -> WhispemResult<&'a str> {
         match v {
-            Value::Number(n) => Ok(*n as i64),
-            other => Err(self.type_err_at("number", other.type_name(), line)),
+            Value::Str(s) => Ok(s),
+            other         => Err(self.type_err_at("string", other.type_name(), line)),
+        }
+    }
+
+    fn num_arg(&self, v: &Value, line: usize) -> WhispemResult<f64> {
+        v.as_f64().ok_or_else(|| self.type_err_at("number", v.type_name(), line))
+    }
+
+    // A repeat count or pad width.
+    fn count_arg(&self, v: &Value, line: usize) -> WhispemResult<usize> {
+        match v.as_int() {
+            Some(n) if n >= 0 => Ok(n as usize),
+            Some(n) => Err(self.type_err_at("non-negative integer", &n.to_string(), line)),
+            None    => Err(self.type_err_at("non-negative integer", &Self::describe_number(v), line)),
+        }
+    }
+
+    fn describe_number(v: &Value) -> String {
+        match v {
+            Value::Number(n) => format!("number {}", n),
+            other            => other.type_name().to_string(),
         }
     }
 
     fn add(&self, a: Value, b: Value) -> WhispemResult<Value> {
         match (a, b) {
-            (Value::Number(x), Value::Number(y)) => Ok(Value::Number(x + y)),
+            (Value::Int(x), Value::Int(y)) => x.checked_add(y).map(Value::Int)
+                .ok_or_else(|| int_overflow(format!("{} + {}", x, y))),
+            (a, b) if a.as_f64().is_some() && b.as_f64().is_some() =>
+                Ok(Value::Number(a.as_f64().unwrap_or(0.0) + b.as_f64().unwrap_or(0.0))),
             (Value::Str(x),    Value::Str(y))    => Ok(Value::Str(format!("{}{}", x, y))),
             (Value::Str(x),    other)             => Ok(Value::Str(format!("{}{}", x, other.format()))),
             (other,            Value::Str(y))     => Ok(Value::Str(format!("{}{}", other.format(), y))),
@@ -801,38 +1866,89 @@ impl Vm {
         }
     }
 
-    fn numeric(&self, a: Value, b: Value, f: impl Fn(f64, f64) -> f64) -> WhispemResult<Value> {
-        match (a, b) {
-            (Value::Number(x), Value::Number(y)) => Ok(Value::Number(f(x, y))),
-            (a, b) => Err(self.type_err("number", &format!("{} and {}", a.type_name(), b.type_name()))),
+    /// Two ints give a checked int; any float operand promotes both to float.
+    fn numeric(
+        &self, a: Value, b: Value, sym: &str,
+        int: impl Fn(i64, i64) -> Option<i64>, float: impl Fn(f64, f64) -> f64,
+    ) -> WhispemResult<Value> {
+        match (&a, &b) {
+            (Value::Int(x), Value::Int(y)) => int(*x, *y).map(Value::Int)
+                .ok_or_else(|| int_overflow(format!("{} {} {}", x, sym, y))),
+            _ => match (a.as_f64(), b.as_f64()) {
+                (Some(x), Some(y)) => Ok(Value::Number(float(x, y))),
+                _ => Err(self.type_err("number", &format!("{} and {}", a.type_name(), b.type_name()))),
+            },
         }
     }
 
-    fn divmod(&self, a: Value, b: Value, is_mod: bool) -> WhispemResult<Value> {
-        match (a, b) {
-            (Value::Number(x), Value::Number(y)) => {
-                if y == 0.0 { return Err(WhispemError::runtime(ErrorKind::DivisionByZero)); }
-                Ok(Value::Number(if is_mod { x % y } else { x / y }))
+    /// `/` stays an int only when it divides exactly; `//` truncates toward
+    /// zero and `%` takes the sign of the dividend, so `a == (a // b) * b + a % b`.
+    fn divmod(&self, a: Value, b: Value, op: OpCode) -> WhispemResult<Value> {
+        let zero = || WhispemError::runtime(ErrorKind::DivisionByZero);
+        if let (Value::Int(x), Value::Int(y)) = (&a, &b) {
+            let (x, y) = (*x, *y);
+            if y == 0 { return Err(zero()); }
+            return match op {
+                OpCode::Mod                             => Ok(Value::Int(x.wrapping_rem(y))),
+                OpCode::Div if x.wrapping_rem(y) != 0   => Ok(Value::Number(x as f64 / y as f64)),
+                _ => x.checked_div(y).map(Value::Int)
+                    .ok_or_else(|| int_overflow(format!("{} {} {}", x, if op == OpCode::Div { "/" } else { "//" }, y))),
+            };
+        }
+        match (a.as_f64(), b.as_f64()) {
+            (Some(x), Some(y)) => {
+                if y == 0.0 { return Err(zero()); }
+                Ok(Value::Number(match op {
+                    OpCode::Mod    => x % y,
+                    OpCode::IntDiv => (x / y).trunc(),
+                    _              => x / y,
+                }))
             }
-            (a, b) => Err(self.type_err("number", &format!("{} and {}", a.type_name(), b.type_name()))),
+            _ => Err(self.type_err("number", &format!("{} and {}", a.type_name(), b.type_name()))),
         }
     }
 
+    // Arrays (ranges included), dicts and structs compare by contents; a
+    // struct equals only an instance of the same declaration.  Walks pairs
+    // with a worklist so deeply nested values don't overflow the native stack.
     fn eq_val(&self, a: &Value, b: &Value) -> bool {
-        match (a, b) {
-            (Value::Number(x), Value::Number(y)) => x == y,
-            (Value::Str(x),    Value::Str(y))    => x == y,
-            (Value::Bool(x),   Value::Bool(y))   => x == y,
-            (Value::None,      Value::None)       => true,
-            _                                    => false,
+        let mut pending = vec![(a, b)];
+        while let Some((a, b)) = pending.pop() {
+            let same = match (a, b) {
+                (Value::Array(x), Value::Array(y)) => {
+                    x.len() == y.len() && { pending.extend(x.iter().zip(y.iter())); true }
+                }
+                (Value::Dict(x), Value::Dict(y)) => {
+                    x.len() == y.len() && x.iter().all(|(k, v)| match y.get(k) {
+                        Some(w) => { pending.push((v, w)); true }
+                        None    => false,
+                    })
+                }
+                (Value::Struct(x), Value::Struct(y)) => {
+                    x.def == y.def && { pending.extend(x.fields.iter().zip(y.fields.iter())); true }
+                }
+                (Value::Range(x), Value::Range(y)) => {
+                    x == y || (x.len() == y.len() && x.iter().zip(y.iter()).all(|(p, q)| scalar_eq(&p, &q)))
+                }
+                (Value::Range(r), Value::Array(items)) | (Value::Array(items), Value::Range(r)) => {
+                    r.len() == items.len() && r.iter().zip(items.iter()).all(|(p, q)| scalar_eq(&p, q))
+                }
+                _ => scalar_eq(a, b),
+            };
+            if !same { return false; }
         }
+        true
     }
 
-    fn cmp(&self, a: Value, b: Value, nf: impl Fn(f64,f64)->bool, sf: impl Fn(&str,&str)->bool) -> WhispemResult<Value> {
-        match (a, b) {
-            (Value::Number(x), Value::Number(y)) => Ok(Value::Bool(nf(x, y))),
-            (Value::Str(x),    Value::Str(y))    => Ok(Value::Bool(sf(&x, &y))),
-            (a, b) => Err(self.type_err("number or string", &format!("{} and {}", a.type_name(), b.type_name()))),
+    fn cmp(&self, a: Value, b: Value, test: impl Fn(Ordering) -> bool) -> WhispemResult<Value> {
+        match (&a, &b) {
+            (Value::Int(x), Value::Int(y)) => Ok(Value::Bool(test(x.cmp(y)))),
+            (Value::Str(x), Value::Str(y)) => Ok(Value::Bool(test(x.as_str().cmp(y.as_str())))),
+            _ => match (a.as_f64(), b.as_f64()) {
+                // NaN is unordered: every comparison with it is false.
+                (Some(x), Some(y)) => Ok(Value::Bool(x.partial_cmp(&y).is_some_and(test))),
+                _ => Err(self.type_err("number or string", &format!("{} and {}", a.type_name(), b.type_name()))),
+            },
         }
     }
 
@@ -862,11 +1978,121 @@ impl Vm {
         WhispemError::new(ErrorKind::TypeError { expected: expected.into(), found: found.into() }, Span::new(line, 0))
     }
 
+    fn require(&self, granted: bool, builtin: &str, resource: &str, line: usize) -> WhispemResult<()> {
+        if granted { return Ok(()); }
+        Err(WhispemError::new(
+            ErrorKind::PermissionDenied { builtin: builtin.into(), resource: resource.into() },
+            Span::new(line, 0),
+        ))
+    }
+
     fn arity(&self, name: &str, expected: usize, got: usize, line: usize) -> WhispemResult<()> {
         if got != expected {
-            Err(WhispemError::new(ErrorKind::ArgumentCount { name: name.into(), expected, got }, Span::new(line, 0)))
+            Err(WhispemError::new(
+                ErrorKind::ArgumentCount { name: name.into(), min: expected, max: Some(expected), got }, Span::new(line, 0),
+            ))
         } else {
             Ok(())
         }
     }
-}
\ No newline at end of file
+}
+
+impl Default for Vm {
+    fn default() -> Self { Self::new() }
+}
+
+// The dict a `catch` block receives: `kind`, `message` and `line`.  A
+// thrown error dict (one with `kind` and `message`, e.g. a caught error
+// being re-thrown) arrives unchanged; any other thrown value is wrapped
+// with kind `"Thrown"` and kept under `value`.
+fn error_value(kind: ErrorKind, line: usize) -> Value {
+    if let ErrorKind::Thrown(Value::Dict(d)) = &kind {
+        if d.contains_key("kind") && d.contains_key("message") {
+            let mut d = (**d).clone();
+            d.entry("line".to_string()).or_insert(Value::Int(line as i64));
+            return Value::Dict(Rc::new(d));
+        }
+    }
+    let mut d = HashMap::new();
+    d.insert("kind".to_string(), Value::Str(kind.name().to_string()));
+    d.insert("line".to_string(), Value::Int(line as i64));
+    match kind {
+        ErrorKind::Thrown(v) => {
+            d.insert("message".to_string(), Value::Str(crate::error::thrown_message(&v)));
+            d.insert("value".to_string(), v);
+        }
+        other => { d.insert("message".to_string(), Value::Str(other.message())); }
+    }
+    Value::Dict(Rc::new(d))
+}
+
+// Numbers by value with NaN after every other number, strings by code
+// point.  Callers check that the values are of one kind first.
+fn sort_order(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Int(x), Value::Int(y)) => x.cmp(y),
+        (Value::Str(x), Value::Str(y)) => x.cmp(y),
+        _ => match (a.as_f64(), b.as_f64()) {
+            (Some(x), Some(y)) => x.partial_cmp(&y).unwrap_or_else(|| x.is_nan().cmp(&y.is_nan())),
+            _                  => Ordering::Equal,
+        },
+    }
+}
+
+fn scalar_eq(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Int(x),    Value::Int(y))    => x == y,
+        (Value::Int(i),    Value::Number(n))
+        | (Value::Number(n), Value::Int(i))  => Value::Number(*n).as_int() == Some(*i),
+        (Value::Number(x), Value::Number(y)) => x == y,
+        (Value::Str(x),    Value::Str(y))    => x == y,
+        (Value::Bool(x),   Value::Bool(y))   => x == y,
+        (Value::None,      Value::None)       => true,
+        _                                    => false,
+    }
+}
+
+// Numbers by value, or `None` when either is NaN or not a number.
+fn num_order(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Int(x), Value::Int(y)) => Some(x.cmp(y)),
+        _ => a.as_f64()?.partial_cmp(&b.as_f64()?),
+    }
+}
+
+// Stable merge sort on a fallible "goes before" test.  Unlike
+// `slice::sort_by` it copes with inconsistent script comparators and
+// stops at the first error one raises.
+fn merge_sort<T>(mut items: Vec<T>, before: &mut impl FnMut(&T, &T) -> WhispemResult<bool>) -> WhispemResult<Vec<T>> {
+    if items.len() < 2 { return Ok(items); }
+    let right = items.split_off(items.len() / 2);
+    let (left, right) = (merge_sort(items, before)?, merge_sort(right, before)?);
+    let mut out = Vec::with_capacity(left.len() + right.len());
+    let (mut left, mut right) = (left.into_iter().peekable(), right.into_iter().peekable());
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        // Take from the right only when strictly first, so ties keep their order.
+        let next = if before(r, l)? { right.next() } else { left.next() };
+        out.extend(next);
+    }
+    out.extend(left);
+    out.extend(right);
+    Ok(out)
+}
+
+// `PI` and `E`: read when no variable, function or native has the name.
+fn builtin_constant(name: &str) -> Option<Value> {
+    match name {
+        "PI" => Some(Value::Number(std::f64::consts::PI)),
+        "E"  => Some(Value::Number(std::f64::consts::E)),
+        _    => None,
+    }
+}
+
+// A rounded float as an int when one can hold it exactly.
+fn float_to_int(f: f64) -> Value {
+    if f.is_finite() && f >= i64::MIN as f64 && f < i64::MAX as f64 { Value::Int(f as i64) } else { Value::Number(f) }
+}
+
+fn int_overflow(expr: String) -> WhispemError {
+    WhispemError::runtime(ErrorKind::IntegerOverflow(expr))
+}
@@ -1,7 +1,7 @@
 /*
  * wvm.c — Whispem Virtual Machine v6.0.0
  *
- * Executes .whbc bytecode files (format version 4).
+ * Executes .whbc bytecode files (format version 5).
  *
  *   gcc -O2 -o wvm vm/wvm.c -lm
  *   ./wvm examples/hello.whbc
@@ -20,6 +20,7 @@
 #include <stdio.h>
 #include <stdlib.h>
 #include <string.h>
+#include <time.h>
 
 #define MAX_STACK       4096
 #define MAX_FRAMES      256
@@ -36,12 +37,14 @@ enum {
     OP_LOAD_UPVALUE    = 0x13,
     OP_STORE_UPVALUE   = 0x14,
     OP_CLOSE_UPVALUE   = 0x15,
+    OP_STORE_GLOBAL    = 0x17,
     OP_ADD             = 0x20,
     OP_SUB             = 0x21,
     OP_MUL             = 0x22,
     OP_DIV             = 0x23,
     OP_MOD             = 0x24,
     OP_NEG             = 0x25,
+    OP_INT_DIV         = 0x26,
     OP_EQ              = 0x30,
     OP_NEQ             = 0x31,
     OP_LT              = 0x32,
@@ -64,6 +67,10 @@ enum {
     OP_SET_INDEX       = 0x63,
     OP_PRINT           = 0x70,
     OP_POP             = 0x71,
+    OP_ITER            = 0x90,
+    OP_ITER_NEXT       = 0x91,
+    OP_MAKE_GENERATOR  = 0x92,
+    OP_YIELD           = 0x93,
     OP_HALT            = 0xFF,
 };
 
@@ -105,19 +112,25 @@ typedef struct Value   Value;
 typedef struct WArray  WArray;
 typedef struct WDict   WDict;
 typedef struct Closure Closure;
+typedef struct WGen    WGen;
+typedef struct WRange  WRange;
 
-enum ValueTag { V_NUM, V_BOOL, V_STR, V_ARRAY, V_DICT, V_CLOSURE, V_NONE };
+enum ValueTag { V_NUM, V_BOOL, V_STR, V_ARRAY, V_RANGE, V_DICT, V_CLOSURE, V_GEN, V_NONE };
 
 struct Value {
     enum ValueTag tag;
-    union { double num; bool boolean; WStr *str; WArray *array; WDict *dict; Closure *closure; } as;
+    union { double num; bool boolean; WStr *str; WArray *array; WRange *range; WDict *dict; Closure *closure; WGen *gen; } as;
 };
 
 static void val_drop(Value *v);
 static Value val_clone(const Value *v);
 static void warray_dec(WArray *a);
+static void wrange_dec(WRange *r);
 static void wdict_dec(WDict *d);
 static void closure_dec(Closure *c);
+static void wgen_inc(WGen *g);
+static void wgen_dec(WGen *g);
+static const char *wgen_name(const WGen *g);
 
 static Value val_num(double n)    { return (Value){.tag=V_NUM,  .as.num=n}; }
 static Value val_bool(bool b)     { return (Value){.tag=V_BOOL, .as.boolean=b}; }
@@ -129,8 +142,10 @@ static void val_drop(Value *v) {
     switch (v->tag) {
     case V_STR:     wstr_dec(v->as.str);       break;
     case V_ARRAY:   warray_dec(v->as.array);   break;
+    case V_RANGE:   wrange_dec(v->as.range);   break;
     case V_DICT:    wdict_dec(v->as.dict);     break;
     case V_CLOSURE: closure_dec(v->as.closure);break;
+    case V_GEN:     wgen_dec(v->as.gen);       break;
     default: break;
     }
     v->tag = V_NONE;
@@ -173,6 +188,60 @@ static WArray *warray_cow(WArray *a) {
 }
 static Value val_array_own(WArray *a) { return (Value){.tag=V_ARRAY, .as.array=a}; }
 
+/* ── Ranges ───────────────────────────────────────────────────────────────── */
+
+/* range(start, end, step): an array whose elements are computed on demand.
+   Lengths follow the Rust VM: exact integer arithmetic when every bound is
+   a whole number, otherwise the last float element is checked against end. */
+struct WRange { int rc; double start, step; size_t offset, len; bool reversed; };
+
+static bool is_whole(double x) { return x == floor(x) && x >= (double)INT64_MIN && x < (double)INT64_MAX; }
+
+static double wrange_nth(const WRange *r, size_t i) { i += r->offset; return i == 0 ? r->start : r->start + (double)i * r->step; }
+static bool wrange_before(const WRange *r, size_t i, double end) {
+    double x = wrange_nth(r, i);
+    return r->step > 0 ? x < end : x > end;
+}
+
+static WRange *wrange_new(double start, double end, double step) {
+    WRange *r = malloc(sizeof(WRange)); if (!r) die("oom");
+    r->rc = 1; r->start = start; r->step = step; r->offset = 0; r->len = 0; r->reversed = false;
+    if (is_whole(start) && is_whole(end) && is_whole(step)) {
+        int64_t s = (int64_t)start, e = (int64_t)end, st = (int64_t)step;
+        uint64_t stride = st > 0 ? (uint64_t)st : 0 - (uint64_t)st;
+        if (st > 0 ? e > s : e < s) {
+            uint64_t span = st > 0 ? (uint64_t)e - (uint64_t)s : (uint64_t)s - (uint64_t)e;
+            r->len = (size_t)(span / stride + (span % stride != 0));
+        }
+        return r;
+    }
+    double estimate = ceil((end - start) / step);
+    if (estimate > 0) r->len = estimate >= (double)SIZE_MAX ? SIZE_MAX : (size_t)estimate;
+    while (r->len > 0 && !wrange_before(r, r->len - 1, end)) r->len--;
+    if (r->len < SIZE_MAX && wrange_before(r, r->len, end)) r->len++;
+    return r;
+}
+static void wrange_inc(WRange *r) { if (r) r->rc++; }
+static void wrange_dec(WRange *r) { if (r && --r->rc <= 0) free(r); }
+static Value wrange_get(const WRange *r, size_t i) {
+    return val_num(wrange_nth(r, r->reversed ? r->len - 1 - i : i));
+}
+static WArray *wrange_items(const WRange *r) {
+    WArray *a = warray_new(r->len);
+    for (size_t i = 0; i < r->len; i++) warray_push(a, wrange_get(r, i));
+    return a;
+}
+static WRange *wrange_copy(const WRange *src) {
+    WRange *r = malloc(sizeof(WRange)); if (!r) die("oom");
+    *r = *src; r->rc = 1; return r;
+}
+/* Builtins other than length, reverse, slice and pop see a range's elements. */
+static void materialise(Value *v) {
+    if (v->tag != V_RANGE) return;
+    WArray *a = wrange_items(v->as.range);
+    val_drop(v); *v = val_array_own(a);
+}
+
 /* ── Dicts ────────────────────────────────────────────────────────────────── */
 
 struct WDict { int rc; size_t len; size_t cap; WStr **keys; Value *vals; };
@@ -271,21 +340,60 @@ static Value val_clone(const Value *v) {
     switch (v->tag) {
     case V_STR:     wstr_inc(r.as.str);        break;
     case V_ARRAY:   warray_inc(r.as.array);    break;
+    case V_RANGE:   wrange_inc(r.as.range);    break;
     case V_DICT:    wdict_inc(r.as.dict);      break;
     case V_CLOSURE: closure_inc(r.as.closure); break;
+    case V_GEN:     wgen_inc(r.as.gen);        break;
     default: break;
     }
     return r;
 }
 
+/* `==`: arrays (ranges included) and dicts compare by contents. */
+static Value seq_at(const Value *v, size_t i) {
+    return v->tag == V_RANGE ? wrange_get(v->as.range, i) : val_clone(&v->as.array->items[i]);
+}
+static bool val_eq(const Value *a, const Value *b) {
+    bool seq_a = a->tag == V_ARRAY || a->tag == V_RANGE, seq_b = b->tag == V_ARRAY || b->tag == V_RANGE;
+    if (seq_a && seq_b) {
+        size_t n = a->tag == V_RANGE ? a->as.range->len : a->as.array->len;
+        if (n != (b->tag == V_RANGE ? b->as.range->len : b->as.array->len)) return false;
+        for (size_t i = 0; i < n; i++) {
+            Value x = seq_at(a, i), y = seq_at(b, i);
+            bool same = val_eq(&x, &y); val_drop(&x); val_drop(&y);
+            if (!same) return false;
+        }
+        return true;
+    }
+    if (a->tag != b->tag) return false;
+    switch (a->tag) {
+    case V_NUM:  return a->as.num == b->as.num;
+    case V_BOOL: return a->as.boolean == b->as.boolean;
+    case V_STR:  return a->as.str->len == b->as.str->len && memcmp(a->as.str->data, b->as.str->data, a->as.str->len) == 0;
+    case V_NONE: return true;
+    case V_DICT: {
+        const WDict *x = a->as.dict, *y = b->as.dict;
+        if (x->len != y->len) return false;
+        for (size_t i = 0; i < x->len; i++) {
+            const Value *w = wdict_get(y, x->keys[i]->data, x->keys[i]->len);
+            if (!w || !val_eq(&x->vals[i], w)) return false;
+        }
+        return true;
+    }
+    default: return false;
+    }
+}
+
 static bool is_truthy(const Value *v) {
     switch (v->tag) {
     case V_BOOL:    return v->as.boolean;
     case V_NUM:     return v->as.num != 0.0;
     case V_STR:     return v->as.str->len > 0;
     case V_ARRAY:   return v->as.array->len > 0;
+    case V_RANGE:   return v->as.range->len > 0;
     case V_DICT:    return v->as.dict->len > 0;
     case V_CLOSURE: return true;
+    case V_GEN:     return true;
     case V_NONE:    return false;
     }
     return false;
@@ -314,8 +422,13 @@ static char *val_format(const Value *v) {
     case V_NUM:  return fmt_number(v->as.num);
     case V_BOOL: return strdup(v->as.boolean ? "true" : "false");
     case V_STR:  return strdup(v->as.str->data);
-    case V_NONE: return strdup("");
+    case V_NONE: return strdup("none");
     case V_CLOSURE: { char buf[64]; snprintf(buf, sizeof(buf), "<fn>"); return strdup(buf); }
+    case V_GEN:     { char buf[96]; snprintf(buf, sizeof(buf), "<generator %s>", wgen_name(v->as.gen)); return strdup(buf); }
+    case V_RANGE: {
+        Value items = val_array_own(wrange_items(v->as.range));
+        char *out = val_format(&items); val_drop(&items); return out;
+    }
     case V_ARRAY: {
         size_t tot = 3, n = v->as.array->len;
         char **p = malloc(sizeof(char*) * (n + 1));
@@ -350,8 +463,10 @@ static const char *type_name(const Value *v) {
     case V_BOOL:    return "bool";
     case V_STR:     return "string";
     case V_ARRAY:   return "array";
+    case V_RANGE:   return "array";
     case V_DICT:    return "dict";
     case V_CLOSURE: return "function";
+    case V_GEN:     return "generator";
     case V_NONE:    return "none";
     }
     return "unknown";
@@ -373,7 +488,8 @@ static void  need(Reader *r, size_t n) { if (r->pos + n > r->len) die("unexpecte
 static uint8_t  read_u8(Reader *r)  { need(r, 1); return r->data[r->pos++]; }
 static uint16_t read_u16(Reader *r) { need(r, 2); uint16_t v = ((uint16_t)r->data[r->pos]<<8)|r->data[r->pos+1]; r->pos += 2; return v; }
 static uint32_t read_u32(Reader *r) { need(r, 4); uint32_t v = ((uint32_t)r->data[r->pos]<<24)|((uint32_t)r->data[r->pos+1]<<16)|((uint32_t)r->data[r->pos+2]<<8)|(uint32_t)r->data[r->pos+3]; r->pos += 4; return v; }
-static double   read_f64(Reader *r) { need(r, 8); uint64_t bits = 0; for (int i = 0; i < 8; i++) bits = (bits<<8)|r->data[r->pos+i]; r->pos += 8; double d; memcpy(&d, &bits, 8); return d; }
+static uint64_t read_u64(Reader *r) { need(r, 8); uint64_t v = 0; for (int i = 0; i < 8; i++) v = (v<<8)|r->data[r->pos+i]; r->pos += 8; return v; }
+static double   read_f64(Reader *r) { uint64_t bits = read_u64(r); double d; memcpy(&d, &bits, 8); return d; }
 
 typedef struct {
     char     *name;
@@ -394,6 +510,7 @@ static Value read_const(Reader *r) {
     case 1: return val_bool(read_u8(r) != 0);
     case 2: { uint16_t sl = read_u16(r); need(r, sl); WStr *s = wstr_new((const char*)r->data + r->pos, sl); r->pos += sl; return val_str_own(s); }
     case 3: return val_none();
+    case 5: return val_num((double)(int64_t)read_u64(r));
     default: die("unknown constant tag %u", tag); return val_none();
     }
 }
@@ -404,6 +521,10 @@ static Chunk read_chunk(Reader *r) {
     c.name = malloc(nlen + 1); memcpy(c.name, r->data + r->pos, nlen); c.name[nlen] = '\0'; r->pos += nlen;
     c.param_count   = read_u8(r);
     c.upvalue_count = read_u8(r);
+    /* Default and rest parameters are not supported: skip their metadata. */
+    read_u8(r);
+    int param_names = c.param_count + (read_u8(r) ? 1 : 0);
+    for (int i = 0; i < param_names; i++) { uint16_t plen = read_u16(r); need(r, plen); r->pos += plen; }
     c.const_count   = read_u8(r);
     c.constants = malloc(sizeof(Value) * (c.const_count ? c.const_count : 1));
     for (int i = 0; i < c.const_count; i++) c.constants[i] = read_const(r);
@@ -423,7 +544,7 @@ static Program load_program(const uint8_t *data, size_t len) {
     if (memcmp(r.data, "WHBC", 4) != 0) die("bad magic bytes");
     r.pos = 4;
     uint8_t ver = read_u8(&r);
-    if (ver != 4) die("version mismatch: expected 4, got %u", ver);
+    if (ver != 5) die("version mismatch: expected 5, got %u", ver);
     uint16_t fn_count = read_u16(&r);
     if (fn_count == 0) die("no chunks");
     Program p; p.chunk_count = fn_count;
@@ -453,10 +574,13 @@ typedef struct {
     int          open_uv_count;
     UpvalueCell **upvalues;
     int           uv_count;
+    WGen         *gen;          /* set while a generator runs this frame */
+    int           stack_base;   /* its first stack slot */
 } CallFrame;
 
 static void frame_init(CallFrame *f, int ci, UpvalueCell **uvs, int uvc) {
     f->chunk_idx = ci; f->ip = 0; f->local_count = 0; f->local_cap = 16;
+    f->gen = NULL; f->stack_base = 0;
     f->locals = malloc(sizeof(Local) * f->local_cap);
     f->open_uv_count = 0;
     f->upvalues = uvs; f->uv_count = uvc;
@@ -492,6 +616,40 @@ static void frame_set(CallFrame *f, WStr *name, Value val) {
     f->local_count++;
 }
 
+/* ── Generators ───────────────────────────────────────────────────────────── */
+
+/* A generator value: the suspended frame of a function that yields, or a
+   for loop's position in an array (a dict's sorted keys) or a string. */
+enum GenKind { GEN_SUSPENDED, GEN_RUNNING, GEN_DONE, GEN_ITEMS, GEN_RANGE, GEN_CHARS };
+
+struct WGen {
+    int          rc;
+    enum GenKind kind;
+    char         name[64];
+    CallFrame    frame;       /* GEN_SUSPENDED */
+    Value       *stack;       /* GEN_SUSPENDED: the slots above frame.stack_base */
+    int          stack_len;
+    Value        src;         /* GEN_ITEMS: an array, GEN_CHARS: a string */
+    size_t       next;        /* index, or byte offset into src */
+};
+
+static WGen *wgen_new(const char *name, enum GenKind kind) {
+    WGen *g = calloc(1, sizeof(WGen)); if (!g) die("oom");
+    g->rc = 1; g->kind = kind; g->src = val_none();
+    snprintf(g->name, sizeof(g->name), "%s", name);
+    return g;
+}
+static void wgen_inc(WGen *g) { if (g) g->rc++; }
+static void wgen_dec(WGen *g) {
+    if (!g || --g->rc > 0) return;
+    if (g->kind == GEN_SUSPENDED) {
+        frame_free(&g->frame);
+        for (int i = 0; i < g->stack_len; i++) val_drop(&g->stack[i]);
+    }
+    free(g->stack); val_drop(&g->src); free(g);
+}
+static const char *wgen_name(const WGen *g) { return g->name; }
+
 /* ── Globals ──────────────────────────────────────────────────────────────── */
 
 static Local *g_globals     = NULL;
@@ -545,6 +703,90 @@ static uint32_t utf8_decode(const char *s, size_t *adv) {
     else if (c < 0xF0) { *adv = 3; return ((c&0x0F)<<12)|((s[1]&0x3F)<<6)|(s[2]&0x3F); }
     else               { *adv = 4; return ((c&0x07)<<18)|((s[1]&0x3F)<<12)|((s[2]&0x3F)<<6)|(s[3]&0x3F); }
 }
+static size_t utf8_encode(uint32_t cp, char *out) {
+    if (cp < 0x80)    { out[0]=(char)cp; return 1; }
+    if (cp < 0x800)   { out[0]=(char)(0xC0|(cp>>6)); out[1]=(char)(0x80|(cp&0x3F)); return 2; }
+    if (cp < 0x10000) { out[0]=(char)(0xE0|(cp>>12)); out[1]=(char)(0x80|((cp>>6)&0x3F)); out[2]=(char)(0x80|(cp&0x3F)); return 3; }
+    out[0]=(char)(0xF0|(cp>>18)); out[1]=(char)(0x80|((cp>>12)&0x3F)); out[2]=(char)(0x80|((cp>>6)&0x3F)); out[3]=(char)(0x80|(cp&0x3F)); return 4;
+}
+/* Unicode White_Space — the set Rust's char::is_whitespace uses. */
+static bool utf8_is_space(uint32_t cp) {
+    return (cp >= 0x09 && cp <= 0x0D) || cp == 0x20 || cp == 0x85 || cp == 0xA0 || cp == 0x1680
+        || (cp >= 0x2000 && cp <= 0x200A) || cp == 0x2028 || cp == 0x2029 || cp == 0x202F
+        || cp == 0x205F || cp == 0x3000;
+}
+/* One-to-one case mapping for ASCII, Latin-1, Latin Extended-A, Greek and
+   Cyrillic.  Other code points, and mappings that change length (ß → SS),
+   are left as they are; the Rust VM applies the full Unicode rules. */
+static uint32_t case_map(uint32_t cp, bool upper) {
+    if (upper) {
+        if ((cp >= 'a' && cp <= 'z') || (cp >= 0xE0 && cp <= 0xFE && cp != 0xF7)) return cp - 0x20;
+        if (cp == 0xFF)                                  return 0x178;
+        if (cp == 0x3C2)                                 return 0x3A3;
+        if (cp >= 0x3B1 && cp <= 0x3C9)                  return cp - 0x20;
+        if (cp >= 0x430 && cp <= 0x44F)                  return cp - 0x20;
+        if (cp >= 0x450 && cp <= 0x45F)                  return cp - 0x50;
+    } else {
+        if ((cp >= 'A' && cp <= 'Z') || (cp >= 0xC0 && cp <= 0xDE && cp != 0xD7)) return cp + 0x20;
+        if (cp == 0x178)                                 return 0xFF;
+        if (cp >= 0x391 && cp <= 0x3A9 && cp != 0x3A2)   return cp + 0x20;
+        if (cp >= 0x410 && cp <= 0x42F)                  return cp + 0x20;
+        if (cp >= 0x400 && cp <= 0x40F)                  return cp + 0x50;
+    }
+    /* Latin Extended-A alternates upper/lower pairs; the 0x139–0x148 and
+       0x179–0x17E runs start on an odd code point. */
+    if (cp >= 0x100 && cp <= 0x17F && cp != 0x130 && cp != 0x131 && cp != 0x138 && cp != 0x149 && cp != 0x17F) {
+        bool odd_run  = (cp >= 0x139 && cp <= 0x148) || (cp >= 0x179 && cp <= 0x17E);
+        bool is_upper = odd_run ? (cp & 1) : !(cp & 1);
+        if (upper && !is_upper) return cp - 1;
+        if (!upper && is_upper) return cp + 1;
+    }
+    return cp;
+}
+/* Byte offset of the first (or last) occurrence of sub in s, or (size_t)-1. */
+static size_t str_find(const char *s, size_t sl, const char *sub, size_t n, bool last) {
+    if (n > sl) return (size_t)-1;
+    if (last) { for (size_t i = sl - n + 1; i-- > 0;) if (memcmp(s+i, sub, n) == 0) return i; }
+    else      { for (size_t i = 0; i + n <= sl; i++)  if (memcmp(s+i, sub, n) == 0) return i; }
+    return (size_t)-1;
+}
+
+/* Growable byte buffer for building result strings. */
+typedef struct { char *data; size_t len, cap; } SBuf;
+static void sbuf_put(SBuf *b, const char *s, size_t n) {
+    if (b->len + n > b->cap) {
+        b->cap = (b->len + n) * 2 + 16;
+        b->data = realloc(b->data, b->cap); if (!b->data) die("oom");
+    }
+    memcpy(b->data + b->len, s, n); b->len += n;
+}
+static WStr *sbuf_take(SBuf *b) { WStr *w = wstr_new(b->data ? b->data : "", b->len); free(b->data); return w; }
+
+/* ── Pseudo-random numbers ────────────────────────────────────────────────── */
+
+/* SplitMix64, as in src/rng.rs: the same seed gives the same sequence. */
+static uint64_t g_rng;
+static uint64_t rng_next(void) {
+    uint64_t z = (g_rng += 0x9e3779b97f4a7c15ULL);
+    z = (z ^ (z >> 30)) * 0xbf58476d1ce4e5b9ULL;
+    z = (z ^ (z >> 27)) * 0x94d049bb133111ebULL;
+    return z ^ (z >> 31);
+}
+/* Uniform in [lo, hi], rejecting draws below 2^64 mod span. */
+static int64_t rng_int_in(int64_t lo, int64_t hi) {
+    uint64_t span = (uint64_t)hi - (uint64_t)lo + 1;
+    if (span == 0) return (int64_t)rng_next();
+    uint64_t threshold = (0 - span) % span, x;
+    do { x = rng_next(); } while (x < threshold);
+    return (int64_t)((uint64_t)lo + x % span);
+}
+
+/* PI and E: read when no variable has the name. */
+static bool builtin_const(const char *name, size_t nlen, Value *out) {
+    if (nlen == 2 && memcmp(name, "PI", 2) == 0) { *out = val_num(3.14159265358979323846); return true; }
+    if (nlen == 1 && name[0] == 'E')              { *out = val_num(2.71828182845904523536); return true; }
+    return false;
+}
 
 /* ── VM state ─────────────────────────────────────────────────────────────── */
 
@@ -572,6 +814,7 @@ static uint32_t current_line(void) { CallFrame *f = frame(); uint32_t ip = f->ip
 static Value lookup_var(const char *name, size_t nlen) {
     Value *v = frame_get(frame(), name, nlen); if (v) return val_clone(v);
     v = global_get(name, nlen); if (v) return val_clone(v);
+    Value c; if (builtin_const(name, nlen, &c)) return c;
     die("line %u: undefined variable '%.*s'", current_line(), (int)nlen, name);
     return val_none();
 }
@@ -612,6 +855,74 @@ static Value invoke_closure(Value callee, Value *args, int argc) {
     return pop_();
 }
 
+/* ── Generator frames ─────────────────────────────────────────────────────── */
+
+/* Pop the returning frame and push its result; a generator's frame
+   reports that it is exhausted instead. */
+static void return_value(Value val) {
+    g_fp--;
+    WGen *g = g_frames[g_fp].gen;
+    frame_free(&g_frames[g_fp]);
+    if (!g) { push(val); return; }
+    g->kind = GEN_DONE; wgen_dec(g);
+    val_drop(&val); push(val_bool(false));
+}
+
+/* YIELD: park the running generator's frame with its stack slots and hand
+   the value and true to whoever resumed it. */
+static void gen_suspend(Value val) {
+    CallFrame *f = frame();
+    WGen *g = f->gen;
+    if (!g) die("line %u: yield outside a generator", current_line());
+    f->gen = NULL; g_fp--;
+    g->frame = *f;
+    g->stack_len = g_sp - f->stack_base;
+    g->stack = realloc(g->stack, sizeof(Value) * (g->stack_len + 1));
+    memcpy(g->stack, &g_stack[f->stack_base], sizeof(Value) * g->stack_len);
+    g_sp = f->stack_base;
+    g->kind = GEN_SUSPENDED; wgen_dec(g);
+    push(val); push(val_bool(true));
+}
+
+/* ITER_NEXT and next(): push the generator's next item and true, or false
+   once it is exhausted.  A suspended frame is pushed back instead, and
+   pushes the same when it next yields or returns. */
+static void gen_resume(WGen *g) {
+    switch (g->kind) {
+    case GEN_ITEMS: {
+        WArray *a = g->src.as.array;
+        if (g->next >= a->len) { push(val_bool(false)); return; }
+        push(val_clone(&a->items[g->next++])); push(val_bool(true));
+        return;
+    }
+    case GEN_RANGE: {
+        WRange *r = g->src.as.range;
+        if (g->next >= r->len) { push(val_bool(false)); return; }
+        push(wrange_get(r, g->next++)); push(val_bool(true));
+        return;
+    }
+    case GEN_CHARS: {
+        WStr *s = g->src.as.str;
+        if (g->next >= s->len) { push(val_bool(false)); return; }
+        size_t n = 1;
+        while (g->next + n < s->len && (s->data[g->next + n] & 0xC0) == 0x80) n++;
+        push(val_str_own(wstr_new(s->data + g->next, n))); g->next += n; push(val_bool(true));
+        return;
+    }
+    case GEN_DONE: push(val_bool(false)); return;
+    case GEN_RUNNING: die("line %u: generator '%s' resumed while it is running", current_line(), g->name); return;
+    case GEN_SUSPENDED:
+        if (g_fp >= MAX_FRAMES) die("call stack overflow");
+        g->kind = GEN_RUNNING; wgen_inc(g);
+        g_frames[g_fp] = g->frame;
+        g_frames[g_fp].gen = g; g_frames[g_fp].stack_base = g_sp;
+        g_fp++;
+        for (int i = 0; i < g->stack_len; i++) push(g->stack[i]);
+        g->stack_len = 0;
+        return;
+    }
+}
+
 /* ── Main dispatch loop ───────────────────────────────────────────────────── */
 
 static void execute_until(int target_depth) {
@@ -634,9 +945,10 @@ static void execute_until(int target_depth) {
         case OP_LOAD_GLOBAL: {
             uint8_t i = frame_read_byte();
             const char *n = const_str(i); size_t nl = const_str_len(i);
-            Value *v = global_get(n, nl);
-            if (!v) die("line %u: undefined variable '%s'", current_line(), n);
-            push(val_clone(v)); break;
+            Value *v = global_get(n, nl), c;
+            if (v) { push(val_clone(v)); break; }
+            if (!builtin_const(n, nl, &c)) die("line %u: undefined variable '%s'", current_line(), n);
+            push(c); break;
         }
         case OP_STORE: {
             uint8_t i = frame_read_byte();
@@ -644,6 +956,12 @@ static void execute_until(int target_depth) {
             store_var(const_str(i), const_str_len(i), val);
             break;
         }
+        case OP_STORE_GLOBAL: {
+            uint8_t i = frame_read_byte();
+            Value val = pop_();
+            global_set(wstr_new(const_str(i), const_str_len(i)), val);
+            break;
+        }
         case OP_LOAD_UPVALUE: {
             uint8_t slot = frame_read_byte();
             CallFrame *f = frame();
@@ -667,7 +985,28 @@ static void execute_until(int target_depth) {
                     }
             break;
         }
-        case OP_CLOSE_UPVALUE: frame_read_byte(); break;
+        case OP_CLOSE_UPVALUE: {
+            uint8_t i = frame_read_byte();
+            const char *n = const_str(i); size_t nl = const_str_len(i);
+            CallFrame *f = frame();
+            for (int oi = 0; oi < f->open_uv_count; oi++)
+                if (strcmp(f->open_uv_names[oi], n) == 0) {
+                    uv_cell_dec(f->open_uv_cells[oi]);
+                    f->open_uv_count--;
+                    f->open_uv_cells[oi] = f->open_uv_cells[f->open_uv_count];
+                    memcpy(f->open_uv_names[oi], f->open_uv_names[f->open_uv_count], 256);
+                    break;
+                }
+            Local *vars  = g_fp > 1 ? f->locals       : g_globals;
+            int   *count = g_fp > 1 ? &f->local_count : &g_global_count;
+            for (int vi = 0; vi < *count; vi++)
+                if (vars[vi].name->len == nl && memcmp(vars[vi].name->data, n, nl) == 0) {
+                    wstr_dec(vars[vi].name); val_drop(&vars[vi].val);
+                    vars[vi] = vars[--*count];
+                    break;
+                }
+            break;
+        }
 
         case OP_MAKE_CLOSURE: {
             uint8_t name_idx = frame_read_byte();
@@ -697,7 +1036,9 @@ static void execute_until(int target_depth) {
                     for (int oi = 0; oi < enc->open_uv_count; oi++)
                         if (strcmp(enc->open_uv_names[oi], descs[ui].name) == 0) { cell = enc->open_uv_cells[oi]; break; }
                     if (!cell) {
-                        Value *lv = frame_get(enc, descs[ui].name, strlen(descs[ui].name));
+                        size_t dl = strlen(descs[ui].name);
+                        Value *lv = frame_get(enc, descs[ui].name, dl);
+                        if (!lv && g_fp == 1) lv = global_get(descs[ui].name, dl);
                         cell = uv_cell_new(lv ? val_clone(lv) : val_none());
                         if (enc->open_uv_count < MAX_OPEN_UPVALS) {
                             uv_cell_inc(cell);
@@ -730,10 +1071,11 @@ static void execute_until(int target_depth) {
         case OP_MUL: { Value b=pop_(),a=pop_(); if(a.tag!=V_NUM||b.tag!=V_NUM) die("cannot multiply"); push(val_num(a.as.num*b.as.num)); break; }
         case OP_DIV: { Value b=pop_(),a=pop_(); if(a.tag!=V_NUM||b.tag!=V_NUM) die("cannot divide"); if(b.as.num==0.0) die("division by zero"); push(val_num(a.as.num/b.as.num)); break; }
         case OP_MOD: { Value b=pop_(),a=pop_(); if(a.tag!=V_NUM||b.tag!=V_NUM) die("cannot modulo"); if(b.as.num==0.0) die("division by zero"); push(val_num(fmod(a.as.num,b.as.num))); break; }
+        case OP_INT_DIV: { Value b=pop_(),a=pop_(); if(a.tag!=V_NUM||b.tag!=V_NUM) die("cannot divide"); if(b.as.num==0.0) die("division by zero"); push(val_num(trunc(a.as.num/b.as.num))); break; }
         case OP_NEG: { Value a=pop_(); if(a.tag!=V_NUM) die("cannot negate"); push(val_num(-a.as.num)); break; }
 
-        case OP_EQ:  { Value b=pop_(),a=pop_(); bool r=false; if(a.tag==b.tag){if(a.tag==V_NUM)r=a.as.num==b.as.num;else if(a.tag==V_BOOL)r=a.as.boolean==b.as.boolean;else if(a.tag==V_STR)r=a.as.str->len==b.as.str->len&&memcmp(a.as.str->data,b.as.str->data,a.as.str->len)==0;else if(a.tag==V_NONE)r=true;} val_drop(&a);val_drop(&b);push(val_bool(r));break; }
-        case OP_NEQ: { Value b=pop_(),a=pop_(); bool r=true;  if(a.tag==b.tag){if(a.tag==V_NUM)r=a.as.num!=b.as.num;else if(a.tag==V_BOOL)r=a.as.boolean!=b.as.boolean;else if(a.tag==V_STR)r=!(a.as.str->len==b.as.str->len&&memcmp(a.as.str->data,b.as.str->data,a.as.str->len)==0);else if(a.tag==V_NONE)r=false;} val_drop(&a);val_drop(&b);push(val_bool(r));break; }
+        case OP_EQ:  { Value b=pop_(),a=pop_(); bool r= val_eq(&a,&b); val_drop(&a);val_drop(&b);push(val_bool(r));break; }
+        case OP_NEQ: { Value b=pop_(),a=pop_(); bool r=!val_eq(&a,&b); val_drop(&a);val_drop(&b);push(val_bool(r));break; }
         case OP_LT:  { Value b=pop_(),a=pop_(); if(a.tag==V_NUM&&b.tag==V_NUM)push(val_bool(a.as.num<b.as.num));  else if(a.tag==V_STR&&b.tag==V_STR){bool r=strcmp(a.as.str->data,b.as.str->data)<0;  val_drop(&a);val_drop(&b);push(val_bool(r));}else die("cannot compare");break; }
         case OP_LTE: { Value b=pop_(),a=pop_(); if(a.tag==V_NUM&&b.tag==V_NUM)push(val_bool(a.as.num<=b.as.num)); else if(a.tag==V_STR&&b.tag==V_STR){bool r=strcmp(a.as.str->data,b.as.str->data)<=0; val_drop(&a);val_drop(&b);push(val_bool(r));}else die("cannot compare");break; }
         case OP_GT:  { Value b=pop_(),a=pop_(); if(a.tag==V_NUM&&b.tag==V_NUM)push(val_bool(a.as.num>b.as.num));  else if(a.tag==V_STR&&b.tag==V_STR){bool r=strcmp(a.as.str->data,b.as.str->data)>0;  val_drop(&a);val_drop(&b);push(val_bool(r));}else die("cannot compare");break; }
@@ -770,8 +1112,13 @@ static void execute_until(int target_depth) {
                 break;
             }
 
+            /* A closure or script function named like a builtin overrides it. */
+            Value *own = frame_get(frame(), name, strlen(name));
+            if (!own) own = global_get(name, strlen(name));
+            bool overridden = (own && own->tag == V_CLOSURE) || find_chunk(&g_prog, name) >= 0;
+
             Value br;
-            if (call_builtin(name, arg_count, args_buf, &br)) {
+            if (!overridden && call_builtin(name, arg_count, args_buf, &br)) {
                 for (int i = 0; i < arg_count; i++) val_drop(&args_buf[i]);
                 push(br); break;
             }
@@ -801,15 +1148,47 @@ static void execute_until(int target_depth) {
         }
 
         case OP_RETURN: {
-            Value val = pop_(); g_fp--; frame_free(&g_frames[g_fp]); push(val);
+            return_value(pop_());
             if (g_fp <= target_depth) return;
             break;
         }
         case OP_RETURN_NONE: {
-            g_fp--; frame_free(&g_frames[g_fp]); push(val_none());
+            return_value(val_none());
+            if (g_fp <= target_depth) return;
+            break;
+        }
+        case OP_MAKE_GENERATOR: {
+            /* The call returns the generator; its body runs on next or for. */
+            g_fp--;
+            WGen *g = wgen_new(g_prog.chunks[g_frames[g_fp].chunk_idx].name, GEN_SUSPENDED);
+            g->frame = g_frames[g_fp];
+            push((Value){.tag=V_GEN, .as.gen=g});
             if (g_fp <= target_depth) return;
             break;
         }
+        case OP_YIELD: {
+            gen_suspend(pop_());
+            if (g_fp <= target_depth) return;
+            break;
+        }
+        case OP_ITER: {
+            Value v = pop_();
+            WGen *g;
+            if (v.tag == V_GEN) { push(v); break; }
+            if (v.tag == V_ARRAY)     { g = wgen_new("array", GEN_ITEMS); g->src = v; }
+            else if (v.tag == V_RANGE) { g = wgen_new("range", GEN_RANGE); g->src = v; }
+            else if (v.tag == V_STR)  { g = wgen_new("string", GEN_CHARS); g->src = v; }
+            else if (v.tag == V_DICT) { g = wgen_new("dict", GEN_ITEMS); call_builtin("keys", 1, &v, &g->src); val_drop(&v); }
+            else die("line %u: cannot iterate over %s", current_line(), type_name(&v));
+            push((Value){.tag=V_GEN, .as.gen=g});
+            break;
+        }
+        case OP_ITER_NEXT: {
+            Value it = pop_();
+            if (it.tag != V_GEN) die("line %u: ITER_NEXT expects generator, got %s", current_line(), type_name(&it));
+            gen_resume(it.as.gen);
+            val_drop(&it); break;
+        }
 
         case OP_MAKE_ARRAY: {
             uint8_t n = frame_read_byte(); WArray *a = warray_new(n);
@@ -831,6 +1210,11 @@ static void execute_until(int target_depth) {
                 size_t i = (size_t)idx.as.num;
                 if (i >= obj.as.array->len) die("line %u: index %zu out of bounds (len %zu)", current_line(), i, obj.as.array->len);
                 push(val_clone(&obj.as.array->items[i])); val_drop(&obj);
+            } else if (obj.tag == V_RANGE) {
+                if (idx.tag != V_NUM) die("array index must be number");
+                size_t i = (size_t)idx.as.num;
+                if (i >= obj.as.range->len) die("line %u: index %zu out of bounds (len %zu)", current_line(), i, obj.as.range->len);
+                push(wrange_get(obj.as.range, i)); val_drop(&obj);
             } else if (obj.tag == V_DICT) {
                 WStr *k = to_dict_key(&idx);
                 const Value *v = wdict_get(obj.as.dict, k->data, k->len);
@@ -841,6 +1225,7 @@ static void execute_until(int target_depth) {
         }
         case OP_SET_INDEX: {
             Value nv = pop_(), idx = pop_(), obj = pop_();
+            materialise(&obj);
             if (obj.tag == V_ARRAY) {
                 if (idx.tag != V_NUM) die("array index must be number");
                 size_t i = (size_t)idx.as.num;
@@ -861,12 +1246,67 @@ static void execute_until(int target_depth) {
     }
 }
 
+/* ── Sorting ──────────────────────────────────────────────────────────────── */
+
+/* Stable merge sort over indices into g_sort_keys.  With g_sort_cmp set
+   (sort_by) the comparator decides; otherwise numbers sort by value with
+   NaN last and strings by code point.  Both are saved around each sort
+   because a comparator may sort too. */
+static const Value *g_sort_keys;
+static const Value *g_sort_cmp;
+
+static bool sort_before(size_t a, size_t b) {
+    const Value *x = &g_sort_keys[a], *y = &g_sort_keys[b];
+    if (g_sort_cmp) {
+        Value pair[2] = { val_clone(x), val_clone(y) };
+        Value r = invoke_closure(*g_sort_cmp, pair, 2);
+        if (r.tag != V_NUM) die("line %u: sort_by() comparator must return a number, got %s", current_line(), type_name(&r));
+        return r.as.num < 0;
+    }
+    if (x->tag == V_NUM) return isnan(y->as.num) ? !isnan(x->as.num) : x->as.num < y->as.num;
+    size_t n = x->as.str->len < y->as.str->len ? x->as.str->len : y->as.str->len;
+    int c = memcmp(x->as.str->data, y->as.str->data, n);
+    return c < 0 || (c == 0 && x->as.str->len < y->as.str->len);
+}
+static void merge_sort(size_t *idx, size_t *tmp, size_t n) {
+    if (n < 2) return;
+    size_t h = n / 2, i = 0, j = h, k = 0;
+    merge_sort(idx, tmp, h); merge_sort(idx + h, tmp, n - h);
+    /* Take from the right only when strictly first, so ties keep their order. */
+    while (i < h && j < n) tmp[k++] = sort_before(idx[j], idx[i]) ? idx[j++] : idx[i++];
+    while (i < h) tmp[k++] = idx[i++];
+    while (j < n) tmp[k++] = idx[j++];
+    memcpy(idx, tmp, n * sizeof(size_t));
+}
+/* items sorted by keys (parallel arrays of n), as a new array. */
+static WArray *sorted_by_keys(const Value *items, const Value *keys, size_t n, const Value *cmp) {
+    const Value *saved_keys = g_sort_keys, *saved_cmp = g_sort_cmp;
+    size_t *idx = malloc(sizeof(size_t) * (n + 1)), *tmp = malloc(sizeof(size_t) * (n + 1));
+    for (size_t i = 0; i < n; i++) idx[i] = i;
+    g_sort_keys = keys; g_sort_cmp = cmp;
+    merge_sort(idx, tmp, n);
+    g_sort_keys = saved_keys; g_sort_cmp = saved_cmp;
+    WArray *out = warray_new(n);
+    for (size_t i = 0; i < n; i++) warray_push(out, val_clone(&items[idx[i]]));
+    free(idx); free(tmp); return out;
+}
+/* sort and sort_key take all numbers or all strings. */
+static void check_sortable(const char *fn, const Value *v, size_t n) {
+    for (size_t i = 0; i < n; i++)
+        if ((v[0].tag != V_NUM && v[0].tag != V_STR) || v[i].tag != v[0].tag)
+            die("line %u: %s() needs all numbers or all strings, got %s", current_line(), fn, type_name(&v[i]));
+}
+
 /* ── Builtins ─────────────────────────────────────────────────────────────── */
 
 static bool call_builtin(const char *name, int argc, Value *args, Value *result) {
+    if (strcmp(name, "length") != 0 && strcmp(name, "reverse") != 0 && strcmp(name, "type_of") != 0
+        && strcmp(name, "slice") != 0 && strcmp(name, "pop") != 0)
+        for (int i = 0; i < argc; i++) materialise(&args[i]);
     if (strcmp(name, "length") == 0) {
         if (argc != 1) die("length() takes 1 argument");
         if      (args[0].tag == V_ARRAY) *result = val_num((double)args[0].as.array->len);
+        else if (args[0].tag == V_RANGE) *result = val_num((double)args[0].as.range->len);
         else if (args[0].tag == V_STR)   *result = val_num((double)utf8_len(args[0].as.str->data, args[0].as.str->len));
         else if (args[0].tag == V_DICT)  *result = val_num((double)args[0].as.dict->len);
         else die("length() expects array, string, or dict");
@@ -880,6 +1320,11 @@ static bool call_builtin(const char *name, int argc, Value *args, Value *result)
     }
     if (strcmp(name, "pop") == 0) {
         if (argc != 1) die("pop() takes 1 argument");
+        if (args[0].tag == V_RANGE) {
+            const WRange *r = args[0].as.range;
+            if (r->len == 0) die("pop() on empty array");
+            *result = wrange_get(r, r->len - 1); return true;
+        }
         if (args[0].tag != V_ARRAY) die("pop() expects array");
         WArray *a = args[0].as.array;
         if (a->len == 0) die("pop() on empty array");
@@ -887,6 +1332,10 @@ static bool call_builtin(const char *name, int argc, Value *args, Value *result)
     }
     if (strcmp(name, "reverse") == 0) {
         if (argc != 1) die("reverse() takes 1 argument");
+        if (args[0].tag == V_RANGE) {
+            WRange *r = wrange_copy(args[0].as.range); r->reversed = !r->reversed;
+            *result = (Value){.tag=V_RANGE, .as.range=r}; return true;
+        }
         if (args[0].tag != V_ARRAY) die("reverse() expects array");
         WArray *a = args[0].as.array; warray_inc(a); a = warray_cow(a);
         if (a->len > 1) for (size_t i=0,j=a->len-1;i<j;i++,j--){Value t=a->items[i];a->items[i]=a->items[j];a->items[j]=t;}
@@ -894,8 +1343,15 @@ static bool call_builtin(const char *name, int argc, Value *args, Value *result)
     }
     if (strcmp(name, "slice") == 0) {
         if (argc != 3) die("slice() takes 3 arguments");
-        if (args[0].tag != V_ARRAY) die("slice() expects array");
         size_t s = (size_t)args[1].as.num, e = (size_t)args[2].as.num;
+        if (args[0].tag == V_RANGE) {
+            WRange *r = wrange_copy(args[0].as.range);
+            if (s > e) die("invalid slice");
+            if (e > r->len) die("slice out of bounds");
+            r->offset += r->reversed ? r->len - e : s; r->len = e - s;
+            *result = (Value){.tag=V_RANGE, .as.range=r}; return true;
+        }
+        if (args[0].tag != V_ARRAY) die("slice() expects array");
         WArray *src = args[0].as.array;
         if (s > e) die("invalid slice");
         if (e > src->len) die("slice out of bounds");
@@ -904,12 +1360,23 @@ static bool call_builtin(const char *name, int argc, Value *args, Value *result)
         *result = val_array_own(a); return true;
     }
     if (strcmp(name, "range") == 0) {
-        if (argc != 2) die("range() takes 2 arguments");
-        int64_t s = (int64_t)args[0].as.num, e = (int64_t)args[1].as.num;
-        size_t n = e > s ? (size_t)(e-s) : 0;
-        WArray *a = warray_new(n);
-        for (int64_t i = s; i < e; i++) warray_push(a, val_num((double)i));
-        *result = val_array_own(a); return true;
+        if (argc < 2 || argc > 3) die("range() takes 2 or 3 arguments");
+        for (int i = 0; i < argc; i++)
+            if (args[i].tag != V_NUM) die("line %u: range() expects numbers, got %s", current_line(), type_name(&args[i]));
+        double step = argc == 3 ? args[2].as.num : 1;
+        if (step == 0) die("line %u: range() step must not be zero", current_line());
+        *result = (Value){.tag=V_RANGE, .as.range=wrange_new(args[0].as.num, args[1].as.num, step)};
+        return true;
+    }
+    if (strcmp(name, "next") == 0) {
+        if (argc < 1 || argc > 2) die("next() takes 1 or 2 arguments");
+        if (args[0].tag != V_GEN) die("line %u: next() expects generator, got %s", current_line(), type_name(&args[0]));
+        int depth = g_fp;
+        gen_resume(args[0].as.gen);
+        if (g_fp > depth) execute_until(depth);
+        Value more = pop_();
+        *result = more.as.boolean ? pop_() : argc == 2 ? val_clone(&args[1]) : val_none();
+        return true;
     }
     if (strcmp(name, "keys") == 0) {
         if (argc != 1) die("keys() takes 1 argument");
@@ -966,6 +1433,206 @@ static bool call_builtin(const char *name, int argc, Value *args, Value *result)
         size_t adv; uint32_t cp = utf8_decode(args[0].as.str->data, &adv);
         *result = val_num((double)cp); return true;
     }
+    if (strcmp(name, "chr") == 0) {
+        if (argc != 1) die("chr() takes 1 argument");
+        if (args[0].tag != V_NUM) die("chr() expects number");
+        double n = args[0].as.num;
+        if (n != floor(n) || n < 0 || n > 0x10FFFF || (n >= 0xD800 && n <= 0xDFFF)) die("chr(): %g is not a Unicode code point", n);
+        char buf[4]; size_t l = utf8_encode((uint32_t)n, buf);
+        *result = val_str_own(wstr_new(buf, l)); return true;
+    }
+
+    /* String toolkit.  Positions and widths count chars, not bytes. */
+    if (strcmp(name, "split") == 0) {
+        if (argc != 2) die("split() takes 2 arguments");
+        if (args[0].tag != V_STR || args[1].tag != V_STR) die("split(string, string)");
+        const char *s = args[0].as.str->data, *sep = args[1].as.str->data;
+        size_t sl = args[0].as.str->len, pl = args[1].as.str->len, i = 0;
+        WArray *a = warray_new(4);
+        if (pl == 0) {
+            while (i < sl) { size_t adv; utf8_decode(s+i, &adv); warray_push(a, val_str_own(wstr_new(s+i, adv))); i += adv; }
+        } else {
+            for (;;) {
+                size_t at = str_find(s+i, sl-i, sep, pl, false);
+                if (at == (size_t)-1) { warray_push(a, val_str_own(wstr_new(s+i, sl-i))); break; }
+                warray_push(a, val_str_own(wstr_new(s+i, at))); i += at + pl;
+            }
+        }
+        *result = val_array_own(a); return true;
+    }
+    if (strcmp(name, "join") == 0) {
+        if (argc != 2) die("join() takes 2 arguments");
+        if (args[0].tag != V_ARRAY || args[1].tag != V_STR) die("join(array, string)");
+        WArray *src = args[0].as.array; SBuf b = {0};
+        for (size_t i = 0; i < src->len; i++) {
+            if (i > 0) sbuf_put(&b, args[1].as.str->data, args[1].as.str->len);
+            char *f = val_format(&src->items[i]); sbuf_put(&b, f, strlen(f)); free(f);
+        }
+        *result = val_str_own(sbuf_take(&b)); return true;
+    }
+    if (strcmp(name, "trim") == 0 || strcmp(name, "trim_start") == 0 || strcmp(name, "trim_end") == 0) {
+        if (argc != 1) die("%s() takes 1 argument", name);
+        if (args[0].tag != V_STR) die("%s() expects string", name);
+        const char *s = args[0].as.str->data; size_t st = 0, en = args[0].as.str->len, adv;
+        if (strcmp(name, "trim_end") != 0)
+            while (st < en && utf8_is_space(utf8_decode(s+st, &adv))) st += adv;
+        if (strcmp(name, "trim_start") != 0)
+            while (en > st) {
+                size_t p = en - 1; while (p > st && ((unsigned char)s[p] & 0xC0) == 0x80) p--;
+                if (!utf8_is_space(utf8_decode(s+p, &adv))) break;
+                en = p;
+            }
+        *result = val_str_own(wstr_new(s+st, en-st)); return true;
+    }
+    if (strcmp(name, "replace") == 0) {
+        if (argc != 3) die("replace() takes 3 arguments");
+        if (args[0].tag != V_STR || args[1].tag != V_STR || args[2].tag != V_STR) die("replace(string, string, string)");
+        const WStr *s = args[0].as.str, *from = args[1].as.str, *to = args[2].as.str;
+        if (from->len == 0) die("replace(): pattern must be a non-empty string");
+        SBuf b = {0}; size_t i = 0;
+        for (;;) {
+            size_t at = str_find(s->data+i, s->len-i, from->data, from->len, false);
+            if (at == (size_t)-1) { sbuf_put(&b, s->data+i, s->len-i); break; }
+            sbuf_put(&b, s->data+i, at); sbuf_put(&b, to->data, to->len); i += at + from->len;
+        }
+        *result = val_str_own(sbuf_take(&b)); return true;
+    }
+    if (strcmp(name, "find") == 0 || strcmp(name, "rfind") == 0) {
+        if (argc != 2) die("%s() takes 2 arguments", name);
+        if (args[0].tag != V_STR || args[1].tag != V_STR) die("%s(string, string)", name);
+        const WStr *s = args[0].as.str, *sub = args[1].as.str;
+        size_t at = str_find(s->data, s->len, sub->data, sub->len, name[0] == 'r');
+        *result = val_num(at == (size_t)-1 ? -1.0 : (double)utf8_len(s->data, at)); return true;
+    }
+    if (strcmp(name, "starts_with") == 0 || strcmp(name, "ends_with") == 0 || strcmp(name, "contains") == 0) {
+        if (argc != 2) die("%s() takes 2 arguments", name);
+        if (args[0].tag != V_STR || args[1].tag != V_STR) die("%s(string, string)", name);
+        const WStr *s = args[0].as.str, *sub = args[1].as.str;
+        bool r;
+        if      (name[0] == 's') r = sub->len <= s->len && memcmp(s->data, sub->data, sub->len) == 0;
+        else if (name[0] == 'e') r = sub->len <= s->len && memcmp(s->data + s->len - sub->len, sub->data, sub->len) == 0;
+        else                     r = str_find(s->data, s->len, sub->data, sub->len, false) != (size_t)-1;
+        *result = val_bool(r); return true;
+    }
+    if (strcmp(name, "upper") == 0 || strcmp(name, "lower") == 0) {
+        if (argc != 1) die("%s() takes 1 argument", name);
+        if (args[0].tag != V_STR) die("%s() expects string", name);
+        const char *s = args[0].as.str->data; size_t sl = args[0].as.str->len, i = 0, adv;
+        SBuf b = {0}; char buf[4];
+        while (i < sl) { uint32_t cp = utf8_decode(s+i, &adv); sbuf_put(&b, buf, utf8_encode(case_map(cp, name[0] == 'u'), buf)); i += adv; }
+        *result = val_str_own(sbuf_take(&b)); return true;
+    }
+    if (strcmp(name, "repeat") == 0) {
+        if (argc != 2) die("repeat() takes 2 arguments");
+        if (args[0].tag != V_STR || args[1].tag != V_NUM) die("repeat(string, number)");
+        double n = args[1].as.num;
+        if (n != floor(n) || n < 0) die("repeat(): count must be a non-negative integer");
+        SBuf b = {0};
+        for (double k = 0; k < n; k++) sbuf_put(&b, args[0].as.str->data, args[0].as.str->len);
+        *result = val_str_own(sbuf_take(&b)); return true;
+    }
+    if (strcmp(name, "pad_left") == 0 || strcmp(name, "pad_right") == 0) {
+        if (argc < 2 || argc > 3) die("%s() takes 2 or 3 arguments", name);
+        if (args[0].tag != V_STR || args[1].tag != V_NUM || (argc == 3 && args[2].tag != V_STR)) die("%s(string, number, string?)", name);
+        double w = args[1].as.num;
+        if (w != floor(w) || w < 0) die("%s(): width must be a non-negative integer", name);
+        const char *fill = argc == 3 ? args[2].as.str->data : " ";
+        size_t fl = argc == 3 ? args[2].as.str->len : 1;
+        if (utf8_len(fill, fl) != 1) die("%s(): fill must be a single character", name);
+        const WStr *s = args[0].as.str; size_t cc = utf8_len(s->data, s->len);
+        SBuf b = {0};
+        if (name[4] == 'r') sbuf_put(&b, s->data, s->len);
+        for (size_t k = cc; k < (size_t)w; k++) sbuf_put(&b, fill, fl);
+        if (name[4] == 'l') sbuf_put(&b, s->data, s->len);
+        *result = val_str_own(sbuf_take(&b)); return true;
+    }
+
+    if (strcmp(name, "sort") == 0 || strcmp(name, "sort_by") == 0 || strcmp(name, "sort_key") == 0) {
+        int want = strcmp(name, "sort") == 0 ? 1 : 2;
+        if (argc != want) die("%s() takes %d argument%s", name, want, want == 1 ? "" : "s");
+        if (args[0].tag != V_ARRAY) die("%s() expects array, got %s", name, type_name(&args[0]));
+        if (want == 2 && args[1].tag != V_CLOSURE) die("%s() expects function, got %s", name, type_name(&args[1]));
+        WArray *src = args[0].as.array;
+        if (strcmp(name, "sort_key") == 0) {
+            /* Each key is computed once, then sorted like sort(). */
+            Value *keys = malloc(sizeof(Value) * (src->len + 1));
+            for (size_t i = 0; i < src->len; i++) { Value item = val_clone(&src->items[i]); keys[i] = invoke_closure(args[1], &item, 1); }
+            check_sortable(name, keys, src->len);
+            *result = val_array_own(sorted_by_keys(src->items, keys, src->len, NULL));
+            for (size_t i = 0; i < src->len; i++) val_drop(&keys[i]);
+            free(keys); return true;
+        }
+        if (want == 1) check_sortable(name, src->items, src->len);
+        *result = val_array_own(sorted_by_keys(src->items, src->items, src->len, want == 2 ? &args[1] : NULL));
+        return true;
+    }
+
+    /* Math.  Every wvm number is a double, so rounding needs no int case. */
+    if (strcmp(name, "abs") == 0 || strcmp(name, "floor") == 0 || strcmp(name, "ceil") == 0
+        || strcmp(name, "round") == 0 || strcmp(name, "trunc") == 0 || strcmp(name, "sqrt") == 0
+        || strcmp(name, "exp") == 0 || strcmp(name, "log") == 0 || strcmp(name, "sin") == 0
+        || strcmp(name, "cos") == 0 || strcmp(name, "tan") == 0 || strcmp(name, "asin") == 0
+        || strcmp(name, "acos") == 0 || strcmp(name, "atan") == 0 || strcmp(name, "is_nan") == 0) {
+        if (argc != 1) die("%s() takes 1 argument", name);
+        if (args[0].tag != V_NUM) die("%s() expects number, got %s", name, type_name(&args[0]));
+        double x = args[0].as.num, r;
+        if      (strcmp(name, "is_nan") == 0) { *result = val_bool(isnan(x)); return true; }
+        else if (strcmp(name, "abs")   == 0) r = fabs(x);
+        else if (strcmp(name, "floor") == 0) r = floor(x);
+        else if (strcmp(name, "ceil")  == 0) r = ceil(x);
+        else if (strcmp(name, "round") == 0) r = round(x);
+        else if (strcmp(name, "trunc") == 0) r = trunc(x);
+        else if (strcmp(name, "sqrt")  == 0) r = sqrt(x);
+        else if (strcmp(name, "exp")   == 0) r = exp(x);
+        else if (strcmp(name, "log")   == 0) r = log(x);
+        else if (strcmp(name, "sin")   == 0) r = sin(x);
+        else if (strcmp(name, "cos")   == 0) r = cos(x);
+        else if (strcmp(name, "tan")   == 0) r = tan(x);
+        else if (strcmp(name, "asin")  == 0) r = asin(x);
+        else if (strcmp(name, "acos")  == 0) r = acos(x);
+        else                                 r = atan(x);
+        *result = val_num(r); return true;
+    }
+    if (strcmp(name, "atan2") == 0 || strcmp(name, "pow") == 0) {
+        if (argc != 2) die("%s() takes 2 arguments", name);
+        if (args[0].tag != V_NUM || args[1].tag != V_NUM) die("%s(number, number)", name);
+        double a = args[0].as.num, b = args[1].as.num;
+        *result = val_num(name[0] == 'a' ? atan2(a, b) : pow(a, b)); return true;
+    }
+    if (strcmp(name, "min") == 0 || strcmp(name, "max") == 0) {
+        if (argc < 1) die("%s() takes at least 1 argument", name);
+        const Value *items = args; size_t n = (size_t)argc;
+        if (argc == 1) {
+            if (args[0].tag != V_ARRAY) die("%s() expects array, got %s", name, type_name(&args[0]));
+            items = args[0].as.array->items; n = args[0].as.array->len;
+        }
+        if (n == 0) die("%s() of an empty array", name);
+        size_t best = 0;
+        for (size_t i = 0; i < n; i++) {
+            if (items[i].tag != V_NUM) die("%s() expects numbers, got %s", name, type_name(&items[i]));
+            if (name[1] == 'i' ? items[i].as.num < items[best].as.num : items[i].as.num > items[best].as.num) best = i;
+        }
+        *result = val_clone(&items[best]); return true;
+    }
+
+    /* Pseudo-random numbers. */
+    if (strcmp(name, "random") == 0) {
+        if (argc != 0) die("random() takes 0 arguments");
+        *result = val_num((double)(rng_next() >> 11) / 9007199254740992.0); return true;
+    }
+    if (strcmp(name, "random_int") == 0) {
+        if (argc != 2) die("random_int() takes 2 arguments");
+        if (args[0].tag != V_NUM || args[1].tag != V_NUM) die("random_int(number, number)");
+        double lo = args[0].as.num, hi = args[1].as.num;
+        if (lo != floor(lo) || hi != floor(hi)) die("random_int() expects integers");
+        if (lo > hi) die("random_int(): low %g is greater than high %g", lo, hi);
+        *result = val_num((double)rng_int_in((int64_t)lo, (int64_t)hi)); return true;
+    }
+    if (strcmp(name, "seed") == 0) {
+        if (argc != 1) die("seed() takes 1 argument");
+        if (args[0].tag != V_NUM || args[0].as.num != floor(args[0].as.num)) die("seed() expects an integer");
+        g_rng = (uint64_t)(int64_t)args[0].as.num; *result = val_none(); return true;
+    }
     if (strcmp(name, "num_to_str") == 0) {
         if (argc != 1) die("num_to_str() takes 1 argument");
         if (args[0].tag != V_NUM) die("num_to_str() expects number");
@@ -1110,6 +1777,7 @@ int main(int argc, char **argv) {
     g_argc   = argc - 2;
     g_argv   = argv + 2;
 
+    g_rng = (uint64_t)time(NULL) ^ ((uint64_t)clock() << 32);
     globals_init();
     frame_init(&g_frames[0], 0, NULL, 0); g_fp = 1;
     execute_until(0);